@@ -18,6 +18,11 @@ pub struct Config {
 pub struct Settings {
     pub continue_on_failure: bool,
     pub bypass_cert_verification: bool,
+    pub parallelism: u32,
+    pub pool_idle_timeout: u64,
+    pub pool_max_idle_per_host: Option<u32>,
+    pub max_connections_per_host: Option<u32>,
+    pub timeout: Option<u64>,
     pub project: Option<String>,
     pub environment: Option<String>,
     #[serde(skip_serializing)]
@@ -39,6 +44,11 @@ struct File {
 struct FileSettings {
     pub continue_on_failure: Option<bool>,
     pub bypass_cert_verification: Option<bool>,
+    pub parallelism: Option<u32>,
+    pub pool_idle_timeout: Option<u64>,
+    pub pool_max_idle_per_host: Option<u32>,
+    pub max_connections_per_host: Option<u32>,
+    pub timeout: Option<u64>,
     pub api_key: Option<String>,
     pub dev_mode: Option<bool>,
     pub project: Option<String>,
@@ -99,6 +109,11 @@ impl Default for Config {
             settings: Settings {
                 continue_on_failure: false,
                 bypass_cert_verification: false,
+                parallelism: 1,
+                pool_idle_timeout: 90,
+                pool_max_idle_per_host: None,
+                max_connections_per_host: None,
+                timeout: None,
                 api_key: None,
                 dev_mode: None,
                 project: None,
@@ -173,6 +188,26 @@ fn load_config_from_environment_variables_as_file() -> File {
         .ok()
         .and_then(|cfg| cfg.parse::<bool>().ok());
 
+    let envvar_parallelism = env::var("JIKKEN_PARALLELISM")
+        .ok()
+        .and_then(|cfg| cfg.parse::<u32>().ok());
+
+    let envvar_pool_idle_timeout = env::var("JIKKEN_POOL_IDLE_TIMEOUT")
+        .ok()
+        .and_then(|cfg| cfg.parse::<u64>().ok());
+
+    let envvar_pool_max_idle_per_host = env::var("JIKKEN_POOL_MAX_IDLE_PER_HOST")
+        .ok()
+        .and_then(|cfg| cfg.parse::<u32>().ok());
+
+    let envvar_max_connections_per_host = env::var("JIKKEN_MAX_CONNECTIONS_PER_HOST")
+        .ok()
+        .and_then(|cfg| cfg.parse::<u32>().ok());
+
+    let envvar_timeout = env::var("JIKKEN_TIMEOUT")
+        .ok()
+        .and_then(|cfg| cfg.parse::<u64>().ok());
+
     let envvar_apikey = env::var("JIKKEN_API_KEY").ok();
     let envvar_devmode = env::var("JIKKEN_DEV_MODE")
         .ok()
@@ -187,6 +222,11 @@ fn load_config_from_environment_variables_as_file() -> File {
             dev_mode: envvar_devmode,
             continue_on_failure: envvar_cof,
             bypass_cert_verification: envvar_bcv,
+            parallelism: envvar_parallelism,
+            pool_idle_timeout: envvar_pool_idle_timeout,
+            pool_max_idle_per_host: envvar_pool_max_idle_per_host,
+            max_connections_per_host: envvar_max_connections_per_host,
+            timeout: envvar_timeout,
             project: envvar_project,
             environment: envvar_env,
         }),
@@ -216,6 +256,17 @@ fn apply_config_file(config: Config, file_opt: Option<File>) -> Config {
                     bypass_cert_verification: settings
                         .bypass_cert_verification
                         .unwrap_or(config.settings.bypass_cert_verification),
+                    parallelism: settings.parallelism.unwrap_or(config.settings.parallelism),
+                    pool_idle_timeout: settings
+                        .pool_idle_timeout
+                        .unwrap_or(config.settings.pool_idle_timeout),
+                    pool_max_idle_per_host: settings
+                        .pool_max_idle_per_host
+                        .or(config.settings.pool_max_idle_per_host),
+                    max_connections_per_host: settings
+                        .max_connections_per_host
+                        .or(config.settings.max_connections_per_host),
+                    timeout: settings.timeout.or(config.settings.timeout),
                     api_key: settings.api_key.or(config.settings.api_key),
                     dev_mode: settings.dev_mode.or(config.settings.dev_mode),
                     project: settings.project.or(config.settings.project),
@@ -266,6 +317,26 @@ fn combine_config_files(lhs: Option<File>, rhs: Option<File>) -> Option<File> {
                                 .as_ref()
                                 .and_then(|s| s.bypass_cert_verification),
                         ),
+                        parallelism: settings
+                            .parallelism
+                            .or(existing_file.settings.as_ref().and_then(|s| s.parallelism)),
+                        pool_idle_timeout: settings.pool_idle_timeout.or(existing_file
+                            .settings
+                            .as_ref()
+                            .and_then(|s| s.pool_idle_timeout)),
+                        pool_max_idle_per_host: settings.pool_max_idle_per_host.or(existing_file
+                            .settings
+                            .as_ref()
+                            .and_then(|s| s.pool_max_idle_per_host)),
+                        max_connections_per_host: settings.max_connections_per_host.or(
+                            existing_file
+                                .settings
+                                .as_ref()
+                                .and_then(|s| s.max_connections_per_host),
+                        ),
+                        timeout: settings
+                            .timeout
+                            .or(existing_file.settings.as_ref().and_then(|s| s.timeout)),
                         api_key: settings.api_key.or(existing_file
                             .settings
                             .as_ref()
@@ -344,6 +415,11 @@ mod tests {
                 settings: Settings {
                     continue_on_failure: true,
                     bypass_cert_verification: false,
+                    parallelism: 1,
+                    pool_idle_timeout: 90,
+                    pool_max_idle_per_host: None,
+                    max_connections_per_host: None,
+                    timeout: None,
                     api_key: None,
                     dev_mode: None,
                     project: None,
@@ -380,6 +456,8 @@ mod tests {
             r#"
             [settings]
             continueOnFailure=true
+            parallelism=4
+            poolIdleTimeout=30
             apiKey="key"
             devMode=true
             
@@ -406,6 +484,9 @@ mod tests {
             continueOnFailure=false
             project="my_proj"
             environment="magic"
+            poolMaxIdlePerHost=8
+            maxConnectionsPerHost=4
+            timeout=5000
 
             [globals]
             my_override_global="bar"
@@ -429,6 +510,11 @@ mod tests {
                 settings: Settings {
                     continue_on_failure: false,
                     bypass_cert_verification: false,
+                    parallelism: 4,
+                    pool_idle_timeout: 30,
+                    pool_max_idle_per_host: Some(8),
+                    max_connections_per_host: Some(4),
+                    timeout: Some(5000),
                     api_key: Some(String::from("key")),
                     dev_mode: Some(true),
                     project: Some(String::from("my_proj")),
@@ -67,3 +67,21 @@ impl fmt::Display for GenericError {
         }
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct UnresolvedVariableError {
+    pub variables: Vec<String>,
+}
+
+impl std::error::Error for UnresolvedVariableError {}
+
+impl fmt::Display for UnresolvedVariableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unresolved variable{} {}",
+            if self.variables.len() == 1 { "" } else { "s" },
+            self.variables.join(", ")
+        )
+    }
+}
@@ -1,25 +1,31 @@
 use crate::{
     config,
+    errors::UnresolvedVariableError,
+    html_report, json,
     json::extractor::extract_json,
     telemetry, test,
     test::{
         definition,
         definition::ResponseDescriptor,
         file::{
-            BodyOrSchema, BodyOrSchemaChecker, Checker, NumericSpecification,
-            ValueOrNumericSpecification,
+            BodyOrSchema, BodyOrSchemaChecker, Checker, ContentSpecification, NumericSpecification,
+            Specification, StringSpecification, ValueOrNumericSpecification,
         },
         http,
         http::Header,
         validation, Definition, Variable,
     },
-    TagMode,
+    GraphFormat, TagMode,
 };
 use bytes::{Bytes, BytesMut};
+use futures_util::{stream, StreamExt};
 use http_body_util::{BodyExt, Full};
 use hyper::{body::Incoming, header::HeaderValue, Request};
-use hyper_rustls::HttpsConnectorBuilder;
-use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
 use log::{debug, error, info, trace, warn};
 use rustls::ClientConfig;
 use rustls_platform_verifier::Verifier;
@@ -28,14 +34,20 @@ use std::{
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     error::Error,
     fmt,
-    io::Write,
-    sync::Arc,
-    time::Instant,
+    io::{IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
     vec,
 };
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use url::Url;
 use validated::Validated::{self, Good};
 
+const MAX_RAW_BODY_LENGTH: usize = 65536;
+
 #[derive(Default)]
 pub struct Report {
     pub test_files: u16,
@@ -93,6 +105,9 @@ pub struct IterationResult {
     pub iteration_number: u32,
     pub status: TestStatus,
     pub stage_results: Option<Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>>>,
+    pub skip_reason: Option<String>,
+    //failed runs of this iteration made before this one when the test has a retry policy
+    pub previous_attempts: Vec<IterationResult>,
 }
 
 impl IterationResult {
@@ -114,14 +129,18 @@ impl IterationResult {
                 TestStatus::Failed
             },
             stage_results: Some(stage_results),
+            skip_reason: None,
+            previous_attempts: Vec::new(),
         }
     }
 
-    pub fn new_skipped(iteration_number: u32) -> Self {
+    pub fn new_skipped(iteration_number: u32, reason: &str) -> Self {
         Self {
             iteration_number,
             status: TestStatus::Skipped,
             stage_results: None,
+            skip_reason: Some(reason.to_string()),
+            previous_attempts: Vec::new(),
         }
     }
 }
@@ -129,6 +148,8 @@ impl IterationResult {
 pub struct TestResult {
     pub test_name: String,
     pub iteration_results: Vec<IterationResult>,
+    //the test that ran, used by reports to name it and redact its secrets
+    pub definition: test::Definition,
 }
 
 pub struct ExecutionResult {
@@ -136,6 +157,16 @@ pub struct ExecutionResult {
     pub runtime: u32,
 }
 
+//Files the results of a run are written to, in each supported format
+#[derive(Default)]
+pub struct ReportFiles {
+    pub junit: Option<String>,
+    pub json: Option<String>,
+    pub html: Option<String>,
+    //replace report files that already exist rather than failing to write them
+    pub overwrite: bool,
+}
+
 struct FormattedExecutionResult(String);
 
 impl fmt::Display for FormattedExecutionResult {
@@ -152,92 +183,469 @@ fn formatted_result_to_file<T: ExecutionResultFormatter>(
     formatter: T,
     execution_result: &ExecutionResult,
     file: &str,
+    overwrite: bool,
 ) -> Result<(), std::io::Error> {
-    std::fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
+    let mut options = std::fs::OpenOptions::new();
+    if overwrite {
+        options.write(true).create(true).truncate(true);
+    } else {
+        options.write(true).create_new(true);
+    }
+    options
         .open(file)
-        .and_then(|mut f| f.write(formatter.format(execution_result).0.as_bytes()))
-        .map(|_| ())
+        .and_then(|mut f| f.write_all(formatter.format(execution_result).0.as_bytes()))
+}
+
+//Escapes text for XML content. Characters XML 1.0 cannot represent at all are dropped
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+//Escapes text for an XML attribute value, keeping line breaks that parsers would
+//otherwise normalise to spaces
+fn xml_attribute(text: &str) -> String {
+    xml_escape(text)
+        .replace('\t', "&#9;")
+        .replace('\n', "&#10;")
+        .replace('\r', "&#13;")
+}
+
+fn junit_time(runtime: u32) -> String {
+    format!("{:.3}", f64::from(runtime) / 1000.0)
+}
+
+#[derive(Default)]
+struct JunitCounts {
+    tests: usize,
+    failures: usize,
+    errors: usize,
+    skipped: usize,
+    time: u32,
+}
+
+impl JunitCounts {
+    fn add(&mut self, other: &JunitCounts) {
+        self.tests += other.tests;
+        self.failures += other.failures;
+        self.errors += other.errors;
+        self.skipped += other.skipped;
+        self.time += other.time;
+    }
+
+    fn attributes(&self) -> String {
+        format!(
+            r#"tests="{}" failures="{}" errors="{}" skipped="{}" time="{}""#,
+            self.tests,
+            self.failures,
+            self.errors,
+            self.skipped,
+            junit_time(self.time)
+        )
+    }
 }
 
 struct JunitResultFormatter;
 
-impl ExecutionResultFormatter for JunitResultFormatter {
-    fn format(&self, res: &ExecutionResult) -> FormattedExecutionResult {
-        let mut lines: Vec<String> = Vec::new();
+impl JunitResultFormatter {
+    //The resolved request and the responses of every stage, with the test's secrets redacted
+    fn system_out(definition: &test::Definition, stage_results: &[StageResult]) -> String {
+        let stages: Vec<String> = stage_results
+            .iter()
+            .enumerate()
+            .map(|(stage_number, stage_result)| {
+                let details = serde_json::to_value(&stage_result.details).unwrap_or_default();
+                format!(
+                    "stage_{}:\n{}",
+                    stage_number + 1,
+                    serde_json::to_string_pretty(&redact_json(definition, details))
+                        .unwrap_or_default()
+                )
+            })
+            .collect();
+        format!(
+            "<system-out>{}</system-out>",
+            xml_escape(&stages.join("\n"))
+        )
+    }
 
-        lines.push(r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string());
-        lines.push(r#"<testsuites>"#.to_string());
-        for test in res.test_results.iter() {
-            lines.push(format!(r#"<testsuite name="{}">"#, test.test_name));
-            for iteration_result in test.iteration_results.iter() {
-                let test_iteration_name = format!(
-                    "{}.Iterations.{}",
-                    test.test_name.as_str(),
-                    iteration_result.iteration_number + 1
-                );
+    //Why an iteration failed, each message naming the stage it comes from
+    fn failures(iteration_result: &IterationResult) -> Vec<(String, &'static str)> {
+        match &iteration_result.stage_results {
+            Some(Ok((_, stage_results))) => stage_results
+                .iter()
+                .enumerate()
+                .filter(|(_, stage_result)| stage_result.status == TestStatus::Failed)
+                .flat_map(|(stage_number, stage_result)| {
+                    let mut failures = junit_failures(stage_result);
+                    if failures.is_empty() {
+                        failures.push(("stage failed".to_string(), "AssertionError"));
+                    }
+                    failures.into_iter().map(move |(message, failure_type)| {
+                        (
+                            format!("stage_{}: {}", stage_number + 1, message),
+                            failure_type,
+                        )
+                    })
+                })
+                .collect(),
+            Some(Err(e)) => vec![(e.to_string(), "Error")],
+            None => Vec::new(),
+        }
+    }
+
+    //Earlier failed attempts, of the whole iteration or of single stages
+    fn rerun_failures(iteration_result: &IterationResult) -> Vec<Vec<(String, &'static str)>> {
+        let mut attempts: Vec<Vec<(String, &'static str)>> = iteration_result
+            .previous_attempts
+            .iter()
+            .map(Self::failures)
+            .collect();
+        if let Some(Ok((_, stage_results))) = &iteration_result.stage_results {
+            for (stage_number, stage_result) in stage_results.iter().enumerate() {
+                attempts.extend(stage_result.previous_attempts.iter().map(|attempt| {
+                    junit_failures(attempt)
+                        .into_iter()
+                        .map(|(message, failure_type)| {
+                            (
+                                format!("stage_{}: {}", stage_number + 1, message),
+                                failure_type,
+                            )
+                        })
+                        .collect()
+                }));
+            }
+        }
+        attempts
+    }
+
+    fn iteration(
+        test: &TestResult,
+        iteration_result: &IterationResult,
+    ) -> (JunitCounts, Vec<String>) {
+        let definition = &test.definition;
+        let stage_results = match &iteration_result.stage_results {
+            Some(Ok((_, stage_results))) => &stage_results[..],
+            _ => &[],
+        };
+        let runtime = stage_results.iter().map(|s| s.total_runtime).sum();
+        let mut counts = JunitCounts {
+            tests: 1,
+            time: runtime,
+            ..JunitCounts::default()
+        };
+
+        let mut lines = vec![format!(
+            r#"<testcase name="iteration_{}" classname="{}" time="{}">"#,
+            iteration_result.iteration_number + 1,
+            xml_attribute(&test.test_name),
+            junit_time(runtime)
+        )];
+        match (&iteration_result.status, &iteration_result.stage_results) {
+            (TestStatus::Passed, _) => {}
+            (TestStatus::Skipped, _) => {
+                counts.skipped += 1;
+                lines.push(match &iteration_result.skip_reason {
+                    Some(reason) => format!(r#"<skipped message="{}"/>"#, xml_attribute(reason)),
+                    None => "<skipped/>".to_string(),
+                });
+            }
+            (TestStatus::Failed, Some(Err(e))) => {
+                counts.errors += 1;
                 lines.push(format!(
-                    r#"<testsuite name="{}">"#,
-                    test_iteration_name.as_str(),
+                    r#"<error message="{}"/>"#,
+                    xml_attribute(&definition.redact_secrets(&e.to_string()))
                 ));
-                for stage_result in iteration_result.stage_results.iter() {
-                    match &stage_result {
-                        Ok((_passed, stage_results)) => {
-                            for (stage_number, stage_result) in stage_results.iter().enumerate() {
-                                if stage_result.status == TestStatus::Passed {
-                                    lines.push(format!(
-                                        r#"<testcase name="stage_{}" classname="{}"/>"#,
-                                        stage_number + 1,
-                                        test_iteration_name.as_str()
-                                    ));
-                                } else {
-                                    lines.push(format!(
-                                        r#"<testcase name="stage_{}" classname="{}">"#,
-                                        stage_number + 1,
-                                        test_iteration_name.as_str()
-                                    ));
-
-                                    if let validated::Validated::Fail(nec) =
-                                        &stage_result.validation
-                                    {
-                                        for i in nec {
-                                            lines.push(format!(
-                                                r#"<failure message="{}" type="AssertionError"/>"#,
-                                                i
-                                            ));
-                                        }
-                                    }
-
-                                    lines.push(r#"</testcase>"#.to_string());
-                                }
-                            }
-                        }
-                        Err(_) => {
-                            lines.push(
-                                r#"<testcase name="Initial" classname="Initial" />"#.to_string(),
-                            );
-                        }
-                    }
+            }
+            (TestStatus::Failed, _) => {
+                counts.failures += 1;
+                let mut failures = Self::failures(iteration_result);
+                if failures.is_empty() {
+                    failures.push(("iteration failed".to_string(), "AssertionError"));
+                }
+                for (message, failure_type) in failures {
+                    lines.push(format!(
+                        r#"<failure message="{}" type="{}"/>"#,
+                        xml_attribute(&definition.redact_secrets(&message)),
+                        failure_type
+                    ));
                 }
+            }
+        }
+
+        // earlier attempts use the surefire rerun elements, flaky
+        // when the iteration eventually passed
+        let rerun_element = if iteration_result.status == TestStatus::Passed {
+            "flakyFailure"
+        } else {
+            "rerunFailure"
+        };
+        for attempt in Self::rerun_failures(iteration_result) {
+            let (messages, failure_types): (Vec<String>, Vec<&str>) = attempt.into_iter().unzip();
+            lines.push(format!(
+                r#"<{} message="{}" type="{}"/>"#,
+                rerun_element,
+                xml_attribute(&definition.redact_secrets(&messages.join("; "))),
+                failure_types.first().unwrap_or(&"AssertionError")
+            ));
+        }
+
+        if !stage_results.is_empty() {
+            lines.push(Self::system_out(definition, stage_results));
+        }
+        lines.push("</testcase>".to_string());
+        (counts, lines)
+    }
+}
+
+impl ExecutionResultFormatter for JunitResultFormatter {
+    fn format(&self, res: &ExecutionResult) -> FormattedExecutionResult {
+        let mut total = JunitCounts::default();
+        let mut suites: Vec<String> = Vec::new();
 
-                lines.push("</testsuite>".to_string());
+        for test in res.test_results.iter() {
+            let mut counts = JunitCounts::default();
+            let mut testcases: Vec<String> = Vec::new();
+            for iteration_result in test.iteration_results.iter() {
+                let (iteration_counts, lines) = Self::iteration(test, iteration_result);
+                counts.add(&iteration_counts);
+                testcases.extend(lines);
             }
-            lines.push("</testsuite>".to_string());
+
+            suites.push(format!(
+                r#"<testsuite name="{}" {}>"#,
+                xml_attribute(&test.test_name),
+                counts.attributes()
+            ));
+            suites.extend(testcases);
+            suites.push("</testsuite>".to_string());
+            total.add(&counts);
         }
+        // the run as a whole reports wall clock time, as tests may run concurrently
+        total.time = res.runtime;
+
+        let mut lines: Vec<String> = vec![
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+            format!(r#"<testsuites name="jikken" {}>"#, total.attributes()),
+        ];
+        lines.extend(suites);
         lines.push("</testsuites>".to_string());
 
         FormattedExecutionResult(lines.join("\n"))
     }
 }
 
+struct JsonResultFormatter;
+
+impl JsonResultFormatter {
+    fn status(status: &TestStatus) -> &'static str {
+        match status {
+            TestStatus::Passed => "passed",
+            TestStatus::Failed => "failed",
+            TestStatus::Skipped => "skipped",
+        }
+    }
+
+    fn stage(definition: &test::Definition, stage_result: &StageResult) -> serde_json::Value {
+        let validation: Vec<&String> = match &stage_result.validation {
+            validated::Validated::Fail(nec) => nec.into_iter().collect(),
+            _ => Vec::new(),
+        };
+
+        // responses which aren't JSON have a null body, so what was received is added as
+        // redacted text. Binary content is left out, only its length is reported
+        let mut details = serde_json::to_value(&stage_result.details).unwrap_or_default();
+        let responses = [
+            ("actual", &stage_result.details.actual),
+            ("compare_actual", &stage_result.details.compare_actual),
+        ];
+        for (key, response) in responses {
+            if let Some(r) = response
+                .as_ref()
+                .filter(|r| !r.is_json && !r.raw_body.is_empty())
+            {
+                details[key]["raw_body_length"] = r.raw_body.len().into();
+                if let Ok(text) = std::str::from_utf8(&r.raw_body) {
+                    details[key]["raw_body"] =
+                        raw_body_text(&definition.redact_secrets(text)).into();
+                }
+            }
+        }
+
+        serde_json::json!({
+            "stage": stage_result.stage,
+            "type": match stage_result.stage_type {
+                StageType::Setup => "setup",
+                StageType::Normal => "stage",
+                StageType::Cleanup => "cleanup",
+            },
+            "name": stage_result.stage_name,
+            "status": Self::status(&stage_result.status),
+            "runtime": stage_result.total_runtime,
+            "timeout_reason": stage_result.timeout_reason,
+            "validation": validation,
+            "details": details,
+            "previous_attempts": stage_result
+                .previous_attempts
+                .iter()
+                .map(|attempt| Self::stage(definition, attempt))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    fn iteration(
+        definition: &test::Definition,
+        iteration_result: &IterationResult,
+    ) -> serde_json::Value {
+        let (stages, error) = match &iteration_result.stage_results {
+            Some(Ok((_, stage_results))) => (
+                stage_results
+                    .iter()
+                    .map(|stage_result| Self::stage(definition, stage_result))
+                    .collect::<Vec<_>>(),
+                None,
+            ),
+            Some(Err(e)) => (Vec::new(), Some(e.to_string())),
+            None => (Vec::new(), None),
+        };
+
+        serde_json::json!({
+            "iteration": iteration_result.iteration_number + 1,
+            "status": Self::status(&iteration_result.status),
+            "skip_reason": iteration_result.skip_reason,
+            "error": error,
+            "stages": stages,
+            "previous_attempts": iteration_result
+                .previous_attempts
+                .iter()
+                .map(|attempt| Self::iteration(definition, attempt))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+//Cuts a response body to MAX_RAW_BODY_LENGTH characters, noting how many were left out
+fn raw_body_text(text: &str) -> String {
+    let total = text.chars().count();
+    if total <= MAX_RAW_BODY_LENGTH {
+        return text.to_string();
+    }
+
+    let mut shortened: String = text.chars().take(MAX_RAW_BODY_LENGTH).collect();
+    shortened.push_str(&format!(
+        "\n… {} more characters not shown",
+        total - MAX_RAW_BODY_LENGTH
+    ));
+    shortened
+}
+
+//Applies a test's secret redaction to every string, and key, of a report
+fn redact_json(definition: &test::Definition, value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => serde_json::Value::String(definition.redact_secrets(&s)),
+        serde_json::Value::Array(values) => serde_json::Value::Array(
+            values
+                .into_iter()
+                .map(|v| redact_json(definition, v))
+                .collect(),
+        ),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .map(|(k, v)| (definition.redact_secrets(&k), redact_json(definition, v)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+impl JsonResultFormatter {
+    fn report(res: &ExecutionResult) -> serde_json::Value {
+        let tests: Vec<serde_json::Value> = res
+            .test_results
+            .iter()
+            .map(|test| {
+                let test_json = serde_json::json!({
+                    "name": test.test_name,
+                    "id": test.definition.id,
+                    "file": test.definition.file_data.filename,
+                    "iterations": test
+                        .iteration_results
+                        .iter()
+                        .map(|iteration_result| Self::iteration(&test.definition, iteration_result))
+                        .collect::<Vec<_>>(),
+                });
+                redact_json(&test.definition, test_json)
+            })
+            .collect();
+
+        let counts = |status: TestStatus| {
+            res.test_results
+                .iter()
+                .flat_map(|t| t.iteration_results.iter())
+                .filter(|i| i.status == status)
+                .count()
+        };
+
+        serde_json::json!({
+            "runtime": res.runtime,
+            "passed": counts(TestStatus::Passed),
+            "failed": counts(TestStatus::Failed),
+            "skipped": counts(TestStatus::Skipped),
+            "tests": tests,
+        })
+    }
+}
+
+impl ExecutionResultFormatter for JsonResultFormatter {
+    fn format(&self, res: &ExecutionResult) -> FormattedExecutionResult {
+        FormattedExecutionResult(
+            serde_json::to_string_pretty(&Self::report(res)).unwrap_or_default(),
+        )
+    }
+}
+
+struct HtmlResultFormatter;
+
+impl ExecutionResultFormatter for HtmlResultFormatter {
+    fn format(&self, res: &ExecutionResult) -> FormattedExecutionResult {
+        FormattedExecutionResult(html_report::render(&JsonResultFormatter::report(res)))
+    }
+}
+
+fn junit_failures(stage_result: &StageResult) -> Vec<(String, &'static str)> {
+    if let Some(reason) = &stage_result.timeout_reason {
+        return vec![(reason.clone(), "TimeoutError")];
+    }
+
+    match &stage_result.validation {
+        validated::Validated::Fail(nec) => nec
+            .into_iter()
+            .map(|message| (message.clone(), "AssertionError"))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
 trait ExecutionPolicy {
     fn name(&self) -> String;
     fn new_line(&self) -> bool;
+    fn sends_requests(&self) -> bool;
 
     async fn execute(
-        &mut self,
-        state: &mut State,
+        &self,
+        state: &State,
         telemetry: &Option<telemetry::Session>,
         test: &test::Definition,
         iteration: u32,
@@ -245,7 +653,7 @@ trait ExecutionPolicy {
     ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>>;
 
     async fn skip(
-        &mut self,
+        &self,
         telemetry: &Option<telemetry::Session>,
         test: &test::Definition,
         config: &config::Config,
@@ -263,9 +671,13 @@ impl ExecutionPolicy for DryRunExecutionPolicy {
         true
     }
 
+    fn sends_requests(&self) -> bool {
+        false
+    }
+
     async fn execute(
-        &mut self,
-        state: &mut State,
+        &self,
+        state: &State,
         _telemetry: &Option<telemetry::Session>,
         test: &test::Definition,
         iteration: u32,
@@ -277,7 +689,7 @@ impl ExecutionPolicy for DryRunExecutionPolicy {
     }
 
     async fn skip(
-        &mut self,
+        &self,
         _telemetry: &Option<telemetry::Session>,
         _test: &test::Definition,
         _config: &config::Config,
@@ -297,9 +709,13 @@ impl ExecutionPolicy for ActualRunExecutionPolicy {
         false
     }
 
+    fn sends_requests(&self) -> bool {
+        true
+    }
+
     async fn execute(
-        &mut self,
-        state: &mut State,
+        &self,
+        state: &State,
         telemetry: &Option<telemetry::Session>,
         test: &test::Definition,
         iteration: u32,
@@ -321,7 +737,7 @@ impl ExecutionPolicy for ActualRunExecutionPolicy {
     }
 
     async fn skip(
-        &mut self,
+        &self,
         telemetry: &Option<telemetry::Session>,
         test: &test::Definition,
         config: &config::Config,
@@ -352,56 +768,6 @@ impl ExecutionPolicy for ActualRunExecutionPolicy {
     }
 }
 
-struct FailurePolicy<T: ExecutionPolicy> {
-    wrapped_policy: T,
-    failed: bool,
-}
-
-impl<T: ExecutionPolicy> FailurePolicy<T> {
-    fn new(policy: T) -> FailurePolicy<T> {
-        FailurePolicy {
-            wrapped_policy: policy,
-            failed: false,
-        }
-    }
-}
-
-impl<T: ExecutionPolicy> ExecutionPolicy for FailurePolicy<T> {
-    fn name(&self) -> String {
-        self.wrapped_policy.name()
-    }
-
-    fn new_line(&self) -> bool {
-        self.wrapped_policy.new_line()
-    }
-
-    async fn execute(
-        &mut self,
-        state: &mut State,
-        telemetry: &Option<telemetry::Session>,
-        test: &test::Definition,
-        iteration: u32,
-        config: &config::Config,
-    ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
-        let ret = self
-            .wrapped_policy
-            .execute(state, telemetry, test, iteration, config)
-            .await;
-        let passed = ret.as_ref().map(|(passed, _)| *passed).unwrap_or_default();
-        self.failed = !passed;
-        ret
-    }
-
-    async fn skip(
-        &mut self,
-        telemetry: &Option<telemetry::Session>,
-        test: &test::Definition,
-        config: &config::Config,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        self.wrapped_policy.skip(telemetry, test, config).await
-    }
-}
-
 pub fn runtime_formatter(time_ms: u32) -> String {
     let mut time_left = time_ms;
     let milliseconds = time_left % 1000;
@@ -419,140 +785,349 @@ pub fn runtime_formatter(time_ms: u32) -> String {
     }
 }
 
+// the id of every finished test alongside the variables it held
+type ExtractedVariables = Mutex<Vec<(Option<String>, HashMap<String, serde_json::Value>)>>;
+
+struct RunContext<'a, T: ExecutionPolicy> {
+    state: &'a State,
+    telemetry: &'a Option<telemetry::Session>,
+    exec_policy: &'a T,
+    config: &'a config::Config,
+    total_count: usize,
+    concurrent: bool,
+    any_failures: AtomicBool,
+    message_displayed: AtomicBool,
+    // outcome of every finished test with an id, consulted by the tests requiring it
+    outcomes: Mutex<HashMap<String, TestStatus>>,
+    // variables held by every finished test, in the order they finished
+    extracted: ExtractedVariables,
+}
+
+impl<T: ExecutionPolicy> RunContext<'_, T> {
+    fn unmet_requirement(&self, test: &test::Definition) -> Option<String> {
+        let outcomes = self.outcomes.lock().unwrap();
+        test.requires
+            .iter()
+            .find_map(|required_id| match outcomes.get(required_id) {
+                Some(TestStatus::Failed) => Some(format!("required test `{required_id}` failed")),
+                Some(TestStatus::Skipped) => {
+                    Some(format!("required test `{required_id}` was skipped"))
+                }
+                _ => None,
+            })
+    }
+
+    fn record_outcome(&self, test: &test::Definition, iteration_results: &[IterationResult]) {
+        let Some(id) = &test.id else {
+            return;
+        };
+
+        let status = if iteration_results
+            .iter()
+            .any(|r| r.status == TestStatus::Failed)
+        {
+            TestStatus::Failed
+        } else if !iteration_results.is_empty()
+            && iteration_results
+                .iter()
+                .all(|r| r.status == TestStatus::Passed)
+        {
+            TestStatus::Passed
+        } else {
+            TestStatus::Skipped
+        };
+
+        self.outcomes.lock().unwrap().insert(id.clone(), status);
+    }
+
+    // A test starts from the variables of the tests it requires, which already
+    // hold those of their own requirements. When tests run one at a time every
+    // earlier test has finished, so it also sees what they extracted.
+    fn inherited_variables(&self, test: &test::Definition) -> HashMap<String, serde_json::Value> {
+        let extracted = self.extracted.lock().unwrap();
+        if !self.concurrent {
+            return extracted
+                .iter()
+                .flat_map(|(_, variables)| variables.clone())
+                .collect();
+        }
+
+        test.requires
+            .iter()
+            .filter_map(|required_id| {
+                extracted
+                    .iter()
+                    .find(|(id, _)| id.as_ref() == Some(required_id))
+            })
+            .flat_map(|(_, variables)| variables.clone())
+            .collect()
+    }
+
+    fn record_variables(&self, test: &test::Definition, state: &State) {
+        self.extracted
+            .lock()
+            .unwrap()
+            .push((test.id.clone(), state.variables()));
+    }
+}
+
 async fn run_tests<T: ExecutionPolicy>(
     tests: Vec<Vec<test::Definition>>,
     telemetry: Option<telemetry::Session>,
-    mut exec_policy: T,
+    exec_policy: T,
     config: &config::Config,
-) -> ExecutionResult {
-    let flattened_tests: Vec<test::Definition> = tests.into_iter().flatten().collect();
-    let total_count = flattened_tests.len();
+    parallelism: usize,
+) -> Result<ExecutionResult, Box<dyn Error + Send + Sync>> {
+    let total_count = tests.iter().map(|layer| layer.len()).sum();
+    let parallelism = std::cmp::max(parallelism, 1);
     let mut results: Vec<TestResult> = Vec::new();
 
-    let mut state = State {
-        variables: HashMap::new(),
-        cookies: HashMap::new(),
-        bypass_cert_verification: config.settings.bypass_cert_verification,
+    let state = if exec_policy.sends_requests() {
+        State::new(&config.settings)?
+    } else {
+        State::for_dry_run(&config.settings)
     };
     let start_time = Instant::now();
 
-    let mut any_failures = false;
-    let mut message_displayed = false;
-
-    for (i, test) in flattened_tests.into_iter().enumerate() {
-        if any_failures && !config.settings.continue_on_failure && !message_displayed {
-            warn!("Skipping remaining tests due to continueOnFailure setting.");
-            log::logger().flush();
-            message_displayed = true;
-        }
-
-        //let mut test_result: Vec<Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>>> =
-        //    Vec::new();
-        let mut iteration_results: Vec<IterationResult> = Vec::new();
-        let test_name = test.name.clone().unwrap_or(format!("Test{}", i + 1));
-        for iteration in 0..test.iterate {
-            // TODO: clean this up based on policies
-            // I don't see a clean way to access it without refactoring
-            if any_failures && !config.settings.continue_on_failure {
-                if iteration == 0 {
-                    info!(
-                        "{} Test ({}/{}) `{}` ... \x1b[33mSKIPPED\x1b[0m\n",
-                        exec_policy.name(),
-                        i + 1,
-                        total_count,
-                        &test_name,
-                    );
-                    let _ = exec_policy.skip(&telemetry, &test, config).await;
-                    iteration_results.push(IterationResult::new_skipped(iteration));
-                }
-                break;
-            }
-
-            if test.disabled {
-                info!(
-                    "{} Test ({}/{}) `{}` ... \x1b[33mDISABLED\x1b[0m\n",
-                    exec_policy.name(),
-                    i + 1,
-                    total_count,
-                    &test_name,
-                );
-                let _ = exec_policy.skip(&telemetry, &test, config).await;
-                iteration_results.push(IterationResult::new_skipped(iteration));
-                break;
-            }
-
-            let new_line = if exec_policy.new_line() { "\n" } else { "" };
-
-            info!(
-                "{} Test ({}/{}) `{}` Iteration({}/{}){}",
-                exec_policy.name(),
-                i + 1,
-                total_count,
-                &test_name,
-                iteration + 1,
-                test.iterate,
-                new_line,
-            );
-
-            let result = exec_policy
-                .execute(&mut state, &telemetry, &test, iteration, config)
-                .await;
+    let context = RunContext {
+        state: &state,
+        telemetry: &telemetry,
+        exec_policy: &exec_policy,
+        config,
+        total_count,
+        concurrent: parallelism > 1,
+        any_failures: AtomicBool::new(false),
+        message_displayed: AtomicBool::new(false),
+        outcomes: Mutex::new(HashMap::new()),
+        extracted: Mutex::new(Vec::new()),
+    };
 
-            match &result {
-                Ok(p) => {
-                    let total_runtime: u32 = p.1.iter().map(|r| r.total_runtime).sum();
-                    let runtime_label = runtime_formatter(total_runtime);
-                    if p.0 {
-                        info!(" Runtime({}) ... \x1b[32mPASSED\x1b[0m\n", runtime_label);
-                    } else {
-                        any_failures = true;
-                        info!(" Runtime({}) ... \x1b[31mFAILED\x1b[0m\n", runtime_label);
-                    }
-                }
-                Err(e) => {
-                    any_failures = true;
-                    info!(" ... \x1b[31mFAILED\x1b[0m\n");
-                    error!("{}", e);
-                }
-            }
+    let mut test_number = 0;
 
-            log::logger().flush();
+    // every test in a layer has its requirements satisfied by earlier layers,
+    // so the tests within a layer are free to run concurrently
+    for layer in tests.iter() {
+        let mut layer_results = stream::iter(
+            layer
+                .iter()
+                .enumerate()
+                .map(|(i, test)| run_test(&context, test, test_number + i)),
+        )
+        .buffered(parallelism)
+        .collect::<Vec<TestResult>>()
+        .await;
 
-            iteration_results.push(IterationResult::new(iteration, result));
-        }
-        results.push(TestResult {
-            test_name,
-            iteration_results,
-        });
+        test_number += layer.len();
+        results.append(&mut layer_results);
     }
 
     let runtime = start_time.elapsed().as_millis() as u32;
 
     if let Some(s) = &telemetry {
-        let status = if any_failures { 2 } else { 1 };
+        let status = if context.any_failures.load(Ordering::Relaxed) {
+            2
+        } else {
+            1
+        };
         _ = telemetry::complete_session(s, runtime, status, config).await;
     }
 
-    ExecutionResult {
+    Ok(ExecutionResult {
         test_results: results,
         runtime,
-    }
+    })
 }
 
-struct StateCookie {
-    domain: String,
-    path: String,
-    key: String,
-    value: String,
-    secure: bool,
-}
+async fn run_test<T: ExecutionPolicy>(
+    context: &RunContext<'_, T>,
+    test: &test::Definition,
+    test_number: usize,
+) -> TestResult {
+    let exec_policy = context.exec_policy;
+    let config = context.config;
+    let stop_on_failure = |any_failures: &AtomicBool| {
+        any_failures.load(Ordering::Relaxed) && !config.settings.continue_on_failure
+    };
 
-impl StateCookie {
-    pub fn new(data: String) -> Option<StateCookie> {
-        debug!("cookie new: {}", &data);
-        let segments: Vec<&str> = data.split(';').collect();
-        let cookie_value: Vec<&str> = segments
-            .first()
+    if stop_on_failure(&context.any_failures)
+        && !context.message_displayed.swap(true, Ordering::Relaxed)
+    {
+        warn!("Skipping remaining tests due to continueOnFailure setting.");
+        log::logger().flush();
+    }
+
+    let mut iteration_results: Vec<IterationResult> = Vec::new();
+    let test_name = test
+        .name
+        .clone()
+        .unwrap_or(format!("Test{}", test_number + 1));
+
+    // a test whose requirement failed or was skipped can't be meaningfully run,
+    // skipping it here also carries the skip through to its own dependents
+    if let Some(reason) = context.unmet_requirement(test) {
+        info!(
+            "{} Test ({}/{}) `{}` ... \x1b[33mSKIPPED\x1b[0m ({})\n",
+            exec_policy.name(),
+            test_number + 1,
+            context.total_count,
+            &test_name,
+            reason,
+        );
+        let _ = exec_policy.skip(context.telemetry, test, config).await;
+        iteration_results.push(IterationResult::new_skipped(0, &reason));
+        context.record_outcome(test, &iteration_results);
+        return TestResult {
+            test_name,
+            iteration_results,
+            definition: test.clone(),
+        };
+    }
+
+    let mut state = context.state.scoped(context.inherited_variables(test));
+
+    for iteration in 0..test.iterate {
+        // TODO: clean this up based on policies
+        // I don't see a clean way to access it without refactoring
+        if stop_on_failure(&context.any_failures) {
+            if iteration == 0 {
+                info!(
+                    "{} Test ({}/{}) `{}` ... \x1b[33mSKIPPED\x1b[0m\n",
+                    exec_policy.name(),
+                    test_number + 1,
+                    context.total_count,
+                    &test_name,
+                );
+                let _ = exec_policy.skip(context.telemetry, test, config).await;
+                iteration_results.push(IterationResult::new_skipped(
+                    iteration,
+                    "a previous test failed and continueOnFailure is disabled",
+                ));
+            }
+            break;
+        }
+
+        if test.disabled {
+            info!(
+                "{} Test ({}/{}) `{}` ... \x1b[33mDISABLED\x1b[0m\n",
+                exec_policy.name(),
+                test_number + 1,
+                context.total_count,
+                &test_name,
+            );
+            let _ = exec_policy.skip(context.telemetry, test, config).await;
+            iteration_results.push(IterationResult::new_skipped(iteration, "test is disabled"));
+            break;
+        }
+
+        let new_line = if exec_policy.new_line() { "\n" } else { "" };
+        let test_label = format!(
+            "{} Test ({}/{}) `{}` Iteration({}/{})",
+            exec_policy.name(),
+            test_number + 1,
+            context.total_count,
+            &test_name,
+            iteration + 1,
+            test.iterate,
+        );
+
+        // when tests run concurrently the label is printed together with the
+        // outcome, otherwise lines from different tests would interleave
+        let outcome_prefix = if context.concurrent {
+            test_label.as_str()
+        } else {
+            info!("{}{}", test_label, new_line);
+            ""
+        };
+
+        // every attempt starts from the variables the iteration started with,
+        // not from whatever a failed attempt extracted
+        let iteration_variables = state.variables();
+        let mut result = exec_policy
+            .execute(&state, context.telemetry, test, iteration, config)
+            .await;
+
+        let mut previous_attempts = Vec::new();
+        if let Some(retry) = &test.retry {
+            while (previous_attempts.len() as u32) + 1 < retry.attempts
+                && should_retry_iteration(retry, &result)
+            {
+                let attempt = previous_attempts.len() as u32 + 1;
+                let delay = retry.delay_after(attempt);
+                debug!(
+                    "{} attempt {}/{} failed, retrying in {}ms",
+                    test_label, attempt, retry.attempts, delay
+                );
+                previous_attempts.push(IterationResult::new(iteration, result));
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                state = context.state.scoped(iteration_variables.clone());
+                result = exec_policy
+                    .execute(&state, context.telemetry, test, iteration, config)
+                    .await;
+            }
+        }
+
+        match &result {
+            Ok(p) => {
+                let total_runtime: u32 = p.1.iter().map(|r| r.total_runtime).sum();
+                let runtime_label = runtime_formatter(total_runtime);
+                let retries = previous_attempts.len()
+                    + p.1.iter().map(|r| r.previous_attempts.len()).sum::<usize>();
+                let retry_label = match retries {
+                    0 => "".to_string(),
+                    1 => " (1 retry)".to_string(),
+                    n => format!(" ({} retries)", n),
+                };
+                if p.0 {
+                    info!(
+                        "{} Runtime({}) ... \x1b[32mPASSED\x1b[0m{}\n",
+                        outcome_prefix, runtime_label, retry_label
+                    );
+                } else {
+                    context.any_failures.store(true, Ordering::Relaxed);
+                    info!(
+                        "{} Runtime({}) ... \x1b[31mFAILED\x1b[0m{}\n",
+                        outcome_prefix, runtime_label, retry_label
+                    );
+                }
+            }
+            Err(e) => {
+                context.any_failures.store(true, Ordering::Relaxed);
+                info!("{} ... \x1b[31mFAILED\x1b[0m\n", outcome_prefix);
+                error!("{}", e);
+            }
+        }
+
+        log::logger().flush();
+
+        let mut iteration_result = IterationResult::new(iteration, result);
+        iteration_result.previous_attempts = previous_attempts;
+        iteration_results.push(iteration_result);
+    }
+
+    context.record_variables(test, &state);
+    context.record_outcome(test, &iteration_results);
+
+    TestResult {
+        test_name,
+        iteration_results,
+        definition: test.clone(),
+    }
+}
+
+struct StateCookie {
+    domain: String,
+    path: String,
+    key: String,
+    value: String,
+    secure: bool,
+}
+
+impl StateCookie {
+    pub fn new(data: String) -> Option<StateCookie> {
+        debug!("cookie new: {}", &data);
+        let segments: Vec<&str> = data.split(';').collect();
+        let cookie_value: Vec<&str> = segments
+            .first()
             .expect("cookie should have segments")
             .split('=')
             .collect();
@@ -590,10 +1165,88 @@ impl StateCookie {
     }
 }
 
+type HttpClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+// Session is shared by every test in the run, so its cookies and
+// connections are guarded for concurrent access
+struct Session {
+    cookies: Mutex<HashMap<String, HashMap<String, StateCookie>>>,
+    // built once per run so connections, TLS sessions and HTTP/2 streams are reused,
+    // dry runs send nothing and go without
+    client: Option<HttpClient>,
+    max_connections_per_host: Option<u32>,
+    host_permits: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl Session {
+    //Held while a request to the url's host is in flight, so no more than
+    //max_connections_per_host requests are open to one host at a time
+    async fn host_permit(&self, url: &str) -> Option<OwnedSemaphorePermit> {
+        let limit = self.max_connections_per_host?;
+        let url = Url::parse(url).ok()?;
+        let host = format!(
+            "{}://{}:{}",
+            url.scheme(),
+            url.host_str()?,
+            url.port_or_known_default()?
+        );
+        let semaphore = self
+            .host_permits
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(limit.max(1) as usize)))
+            .clone();
+        semaphore.acquire_owned().await.ok()
+    }
+}
+
+// State of a single test. The variables it extracts are its own, so tests
+// running side by side can't see or overwrite each other's values
 struct State {
-    variables: HashMap<String, String>,
-    cookies: HashMap<String, HashMap<String, StateCookie>>,
-    bypass_cert_verification: bool,
+    variables: RwLock<HashMap<String, serde_json::Value>>,
+    session: Arc<Session>,
+}
+
+impl State {
+    fn new(settings: &config::Settings) -> Result<State, Box<dyn Error + Send + Sync>> {
+        Ok(State::with_client(
+            settings,
+            Some(build_http_client(settings)?),
+        ))
+    }
+
+    fn for_dry_run(settings: &config::Settings) -> State {
+        State::with_client(settings, None)
+    }
+
+    fn with_client(settings: &config::Settings, client: Option<HttpClient>) -> State {
+        State {
+            variables: RwLock::new(HashMap::new()),
+            session: Arc::new(Session {
+                cookies: Mutex::new(HashMap::new()),
+                client,
+                max_connections_per_host: settings.max_connections_per_host,
+                host_permits: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    // a state in the same session, starting from the given variables
+    fn scoped(&self, variables: HashMap<String, serde_json::Value>) -> State {
+        State {
+            variables: RwLock::new(variables),
+            session: self.session.clone(),
+        }
+    }
+
+    fn variables(&self) -> HashMap<String, serde_json::Value> {
+        self.variables.read().unwrap().clone()
+    }
+
+    fn insert_variable(&self, name: String, value: serde_json::Value) {
+        self.variables.write().unwrap().insert(name, value);
+    }
 }
 
 #[derive(PartialEq, Eq, Clone)]
@@ -614,11 +1267,45 @@ pub enum TestStatus {
 pub struct ResponseResultData {
     pub headers: Vec<http::Header>,
     pub status: u16,
+    //Null when the response isn't JSON, raw_body always holds what was received
     pub body: serde_json::Value,
+    #[serde(skip_serializing)]
+    pub raw_body: Vec<u8>,
+    pub content_type: Option<String>,
+    pub is_json: bool,
 }
 
 impl ResponseResultData {
-    pub async fn from_response(resp: hyper::Response<Incoming>) -> Option<ResponseResultData> {
+    pub fn new(
+        status: u16,
+        headers: Vec<http::Header>,
+        content_type: Option<String>,
+        raw_body: Vec<u8>,
+    ) -> ResponseResultData {
+        let parsed = serde_json::from_slice::<serde_json::Value>(&raw_body)
+            .map_err(|e| debug!("response is not valid JSON data: {}", e))
+            .ok();
+
+        match &parsed {
+            Some(data) => debug!("Body is {data}"),
+            None => debug!("{}", String::from_utf8_lossy(&raw_body)),
+        }
+
+        ResponseResultData {
+            headers,
+            status,
+            is_json: parsed.is_some(),
+            body: parsed.unwrap_or(serde_json::Value::Null),
+            raw_body,
+            content_type,
+        }
+    }
+
+    //Reads the whole body. A body that fails mid-stream is an error, so it is treated
+    //like any other connection failure
+    pub async fn from_response(
+        resp: hyper::Response<Incoming>,
+    ) -> Result<ResponseResultData, Box<dyn Error + Send + Sync>> {
         debug!("Received response : {resp:?}");
 
         let response_status = resp.status();
@@ -628,47 +1315,40 @@ impl ResponseResultData {
             .iter()
             .map(|h| http::Header::new(h.0.to_string(), h.1.to_str().unwrap_or("").to_string()))
             .collect();
+        let content_type = resp
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .map(|ct| ct.to_string());
         let (_, mut body) = resp.into_parts();
 
         let mut response_bytes = BytesMut::new();
 
         while let Some(next) = body.frame().await {
-            let frame = next.unwrap();
+            let frame = next?;
             if let Some(chunk) = frame.data_ref() {
                 response_bytes.extend(chunk);
             }
         }
 
-        match serde_json::from_slice(&response_bytes) {
-            Ok(data) => {
-                debug!("Body is {data}");
-                Some(ResponseResultData {
-                    headers,
-                    status: response_status.as_u16(),
-                    body: data,
-                })
-            }
-            Err(e) => {
-                // TODO: add support for non JSON responses
-                debug!("response is not valid JSON data: {}", e);
-                debug!("{}", std::str::from_utf8(&response_bytes).unwrap_or(""));
-                Some(ResponseResultData {
-                    headers,
-                    status: response_status.as_u16(),
-                    body: serde_json::Value::Null,
-                })
-            }
-        }
+        Ok(ResponseResultData::new(
+            response_status.as_u16(),
+            headers,
+            content_type,
+            response_bytes.to_vec(),
+        ))
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ExpectedResultData {
-    pub headers: Vec<http::Header>,
+    pub headers: Vec<definition::ResponseHeaderDescriptor>,
     pub status: Option<ValueOrNumericSpecification<u16>>,
     pub response_time: Option<NumericSpecification<u32>>,
     pub body: Option<BodyOrSchema>,
     pub strict: bool,
+    pub content: Option<ContentSpecification>,
+    pub assertions: Vec<definition::ResponseAssertion>,
 }
 
 impl ExpectedResultData {
@@ -679,6 +1359,8 @@ impl ExpectedResultData {
             response_time: Option::default(),
             body: Option::default(),
             strict: true,
+            content: Option::default(),
+            assertions: Vec::default(),
         }
     }
     //Consider making get_body a static method that
@@ -686,16 +1368,23 @@ impl ExpectedResultData {
     pub fn from_request(
         req: Option<ResponseDescriptor>,
         td: &test::Definition,
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
         iteration: u32,
     ) -> ExpectedResultData {
         req.map(|r| ExpectedResultData {
-            headers: r.headers,
+            headers: td.get_expected_headers(&r.headers, state_variables, variables, iteration),
             status: r.status,
             response_time: r.response_time,
             body: td.get_expected_request_body(&r.body, state_variables, variables, iteration), //.unwrap_or(serde_json::Value::Null),
             strict: r.strict,
+            content: td.get_expected_content(&r.content, state_variables, variables, iteration),
+            assertions: td.get_expected_assertions(
+                &r.assertions,
+                state_variables,
+                variables,
+                iteration,
+            ),
         })
         .unwrap_or(ExpectedResultData::new())
     }
@@ -731,17 +1420,24 @@ pub struct StageResult {
     pub validation: Validated<Vec<()>, String>,
     pub project: Option<String>,
     pub environment: Option<String>,
+    pub timeout_reason: Option<String>,
+    //failed attempts made before this one when the stage has a retry policy
+    pub previous_attempts: Vec<StageResult>,
 }
 
-fn load_test_from_path(filename: &str) -> Option<test::File> {
-    let load_result = test::file::load(filename);
-    match load_result {
-        Ok(file) => Some(file),
-        Err(e) => {
-            error!("unable to load test file ({}) data: {}", filename, e);
-            None
-        }
-    }
+fn load_test_from_path(filename: &str) -> Result<test::File, Box<validation::Diagnostic>> {
+    test::file::load(filename).map_err(|e| {
+        error!("unable to load test file ({}) data: {}", filename, e);
+        let position = e
+            .downcast_ref::<serde_yaml::Error>()
+            .and_then(|e| e.location())
+            .map(|l| (l.line(), l.column()));
+        Box::new(validation::Diagnostic::load_error(
+            filename,
+            e.to_string(),
+            position,
+        ))
+    })
 }
 
 fn validate_test_file(
@@ -750,19 +1446,22 @@ fn validate_test_file(
     project: Option<String>,
     environment: Option<String>,
     index: usize,
-) -> Option<test::Definition> {
+) -> Result<test::Definition, Box<validation::Diagnostic>> {
     let name = test_file
         .name
         .clone()
         .unwrap_or_else(|| test_file.filename.clone());
-    let res = validation::validate_file(test_file, global_variables, project, environment, index);
-    match res {
-        Ok(file) => Some(file),
-        Err(e) => {
+    let filename = test_file.filename.clone();
+    validation::validate_file(test_file, global_variables, project, environment, index).map_err(
+        |e| {
             error!("Test \"{}\" failed validation: {}.", name, e);
-            None
-        }
-    }
+            Box::new(validation::Diagnostic::load_error(
+                &filename,
+                e.to_string(),
+                None,
+            ))
+        },
+    )
 }
 
 //consider using a set for tags and leverage set operations
@@ -801,9 +1500,97 @@ fn ignored_due_to_tag_filter(
             }
             false
         }
+        TagMode::Expression(expression) => {
+            if expression.matches(&test_definition.tags) {
+                return false;
+            }
+
+            debug!(
+                "test `{}` doesn't match tag expression: {}",
+                test_name, expression
+            );
+            true
+        }
     }
 }
 
+//Tests picked by `--id` or `--name-regex`; with neither given every test is selected
+#[derive(Default)]
+pub struct Selection {
+    pub ids: Vec<String>,
+    pub name_regex: Option<regex::Regex>,
+}
+
+impl Selection {
+    //Test ids are lowercased when loaded, so the requested ones are too
+    pub fn new(ids: Vec<String>, name_regex: Option<regex::Regex>) -> Self {
+        Self {
+            ids: ids.iter().map(|id| id.to_lowercase()).collect(),
+            name_regex,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.name_regex.is_none()
+    }
+
+    fn matches(&self, test_definition: &test::Definition) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        let id_matches = test_definition
+            .id
+            .as_ref()
+            .is_some_and(|id| self.ids.contains(id));
+        let name_matches = match (&self.name_regex, &test_definition.name) {
+            (Some(regex), Some(name)) => regex.is_match(name),
+            _ => false,
+        };
+        id_matches || name_matches
+    }
+}
+
+//Moves the tests that the selected ones transitively require back into the run,
+//so filtering never leaves a test without its prerequisites
+pub fn include_prerequisites(
+    mut tests_to_run: Vec<test::Definition>,
+    mut tests_to_ignore: Vec<test::Definition>,
+) -> (Vec<test::Definition>, Vec<test::Definition>) {
+    let mut pending: Vec<String> = tests_to_run
+        .iter()
+        .flat_map(|td| td.requires.clone())
+        .collect();
+    let mut included: Vec<test::Definition> = Vec::new();
+    while let Some(required_id) = pending.pop() {
+        if let Some(position) = tests_to_ignore
+            .iter()
+            .position(|td| td.id.as_ref() == Some(&required_id))
+        {
+            let td = tests_to_ignore.remove(position);
+            pending.extend(td.requires.clone());
+            included.push(td);
+        }
+    }
+
+    if !included.is_empty() {
+        info!(
+            "Including {} required test{}: {}\n",
+            included.len(),
+            if included.len() == 1 { "" } else { "s" },
+            included
+                .iter()
+                .map(test_label)
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+
+    tests_to_run.extend(included);
+    tests_to_run.sort_by_key(|td| td.index);
+    (tests_to_run, tests_to_ignore)
+}
+
 fn schedule_impl(
     graph: &BTreeMap<usize, BTreeSet<usize>>,
     scheduled_nodes: &BTreeSet<usize>,
@@ -825,150 +1612,365 @@ fn schedule_impl(
         .collect();
 }
 
-fn construct_test_execution_graph_v2(
-    tests_to_run: Vec<test::Definition>,
-    tests_to_ignore: Vec<test::Definition>,
-) -> Vec<Vec<Definition>> {
-    let tests_by_id: HashMap<String, test::Definition> = tests_to_run
+fn test_label(td: &Definition) -> String {
+    td.name
         .clone()
-        .into_iter()
-        .chain(tests_to_ignore)
-        .filter(|td| td.id.is_some())
-        .map(|td| (td.id.clone().unwrap(), td))
-        .collect();
+        .unwrap_or(td.id.clone().unwrap_or(td.index.to_string()))
+}
 
-    trace!("determine test execution order based on dependency graph");
+//Walks the requirement edges looking for a path back to an ancestor; returns the
+//ids forming the first cycle found, e.g. ["a", "b", "a"]
+fn find_requirement_cycle(
+    graph: &BTreeMap<usize, BTreeSet<usize>>,
+    tests_to_run: &[Definition],
+) -> Option<Vec<String>> {
+    fn visit(
+        node: usize,
+        graph: &BTreeMap<usize, BTreeSet<usize>>,
+        path: &mut Vec<usize>,
+        visited: &mut BTreeSet<usize>,
+    ) -> Option<Vec<usize>> {
+        if let Some(start) = path.iter().position(|n| *n == node) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(node);
+            return Some(cycle);
+        }
 
-    //Nodes are IDs ; Directed edges imply ordering; i.e. A -> B; B depends on A
-    let mut graph: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
-    tests_to_run
-        .iter()
-        .map(|td| (td.id.clone(), td))
-        .for_each(|(id, definition)| {
-            if let Some(required_id) = definition.requires.as_ref() {
-                let Some(required_def) = tests_by_id.get(required_id) else {
-                    return;
-                };
+        if !visited.insert(node) {
+            return None;
+        }
 
-                if required_def.disabled {
-                    warn!(
-                        "Test \"{}\" requires a disabled test: \"{}\"",
-                        definition
-                            .name
-                            .as_deref()
-                            .unwrap_or(&id.clone().unwrap_or(definition.index.to_string())),
-                        required_id
-                    );
-                    //should we do transitive disablement?
-                }
+        path.push(node);
+        let cycle = graph
+            .get(&node)
+            .into_iter()
+            .flatten()
+            .find_map(|n| visit(*n, graph, path, visited));
+        path.pop();
+        cycle
+    }
 
-                if let Some(edges) = graph.get_mut(&required_def.index) {
-                    edges.insert(definition.index);
-                } else {
-                    graph.insert(required_def.index, BTreeSet::from([definition.index]));
-                }
-            }
+    let mut visited: BTreeSet<usize> = BTreeSet::new();
+    graph.keys().find_map(|node| {
+        visit(*node, graph, &mut Vec::new(), &mut visited).map(|cycle| {
+            cycle
+                .into_iter()
+                .map(|n| test_label(&tests_to_run[n]))
+                .collect()
+        })
+    })
+}
 
-            let node_for_index = graph.get(&definition.index);
-            if node_for_index.is_none() {
-                graph.insert(definition.index, BTreeSet::new());
-            }
-            //intution: if it already has a dependent, its simply a test
-            //depended on by multiple other tests and not a duplicate ID made in error
-            else if node_for_index.unwrap().is_empty() {
-                warn!(
-                    "Skipping test, found duplicate test id: {}",
-                    &id.clone().unwrap()
-                );
+//Nodes are positions in tests_to_run
+struct RequirementGraph {
+    //Directed edges imply ordering; i.e. A -> B; B depends on A
+    edges: BTreeMap<usize, BTreeSet<usize>>,
+    //tests whose requirements are missing, or themselves can't be scheduled
+    unschedulable: BTreeSet<usize>,
+    duplicates: BTreeSet<usize>,
+    //required ids which no loaded test has, by the position of the test requiring them
+    missing: BTreeMap<usize, Vec<String>>,
+}
+
+fn build_requirement_graph(
+    tests_to_run: &[test::Definition],
+    tests_to_ignore: &[test::Definition],
+) -> RequirementGraph {
+    let ignored_ids: HashSet<String> = tests_to_ignore
+        .iter()
+        .filter_map(|td| td.id.clone())
+        .collect();
+
+    let mut positions_by_id: HashMap<String, usize> = HashMap::new();
+    let mut duplicates: BTreeSet<usize> = BTreeSet::new();
+    for (position, definition) in tests_to_run.iter().enumerate() {
+        if let Some(id) = &definition.id {
+            if positions_by_id.contains_key(id) {
+                warn!("Skipping test, found duplicate test id: {}", id);
+                duplicates.insert(position);
+            } else {
+                positions_by_id.insert(id.clone(), position);
             }
-        });
+        }
+    }
 
-    let mut jobs: Vec<BTreeSet<usize>> = Vec::new();
-    let mut scheduled_nodes: BTreeSet<usize> = BTreeSet::new();
-    while graph.len() != scheduled_nodes.len() {
-        let job = schedule_impl(&graph, &scheduled_nodes);
-        job.iter().for_each(|n| _ = scheduled_nodes.insert(*n));
-        jobs.push(job);
+    let mut missing: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (position, definition) in tests_to_run.iter().enumerate() {
+        for required_id in &definition.requires {
+            if !positions_by_id.contains_key(required_id) && !ignored_ids.contains(required_id) {
+                missing
+                    .entry(position)
+                    .or_default()
+                    .push(required_id.clone());
+            }
+        }
     }
 
-    let job_definitions: Vec<Vec<Definition>> = jobs
-        .into_iter()
-        .map(|hs| {
-            hs.into_iter()
-                .map(|index| tests_to_run.get(index).unwrap().clone())
-                .collect::<Vec<Definition>>()
-        })
+    //A test can't be scheduled if any of its requirements are missing, or
+    //themselves can't be scheduled
+    let mut unschedulable: BTreeSet<usize> = duplicates
+        .union(&missing.keys().copied().collect())
+        .copied()
         .collect();
-
-    let flattened_jobs = job_definitions
-        .iter()
-        .flatten()
-        .collect::<Vec<&Definition>>();
-
-    if tests_to_run.len() != flattened_jobs.len() {
-        //not smart enough on rust to write generic lambda in order to not repeat myself here
-        let s1: HashSet<String> = tests_to_run
-            .iter()
-            .map(|td| {
-                td.name
-                    .clone()
-                    .unwrap_or(td.id.clone().unwrap_or(td.index.to_string()))
-            })
-            .collect();
-        let s2: HashSet<String> = flattened_jobs
+    loop {
+        let newly_unschedulable: Vec<usize> = tests_to_run
             .iter()
-            .map(|td| {
-                td.name
-                    .clone()
-                    .unwrap_or(td.id.clone().unwrap_or(td.index.to_string()))
+            .enumerate()
+            .filter(|(position, _)| !unschedulable.contains(position))
+            .filter(|(_, definition)| {
+                definition.requires.iter().any(|required_id| {
+                    positions_by_id
+                        .get(required_id)
+                        .is_some_and(|required_position| unschedulable.contains(required_position))
+                })
             })
+            .map(|(position, _)| position)
             .collect();
-        let missing_tests = (&s1 - &s2)
-            .into_iter()
-            .filter(|s| !s.is_empty())
-            .map(|s| format!("\"{}\"", s))
-            .collect::<Vec<String>>()
-            .join(",");
-
-        if !missing_tests.is_empty() {
-            warn!("Warning: Required tests not found.");
-            warn!(
-                "Check the 'requires' tag in the following test definition(s): {}.\n\n",
-                missing_tests
-            );
+
+        if newly_unschedulable.is_empty() {
+            break;
         }
+
+        unschedulable.extend(newly_unschedulable);
     }
 
-    for (count, job) in job_definitions.iter().enumerate() {
-        trace!(
-            "Job {count}: {}",
-            job.iter()
-                .enumerate()
-                .map(|(i, t)| t
-                    .name
-                    .clone()
-                    .unwrap_or(t.id.clone().unwrap_or(i.to_string())))
-                .collect::<Vec<String>>()
-                .join(", ")
-        )
+    let mut edges: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+    for (position, definition) in tests_to_run.iter().enumerate() {
+        if duplicates.contains(&position) {
+            continue;
+        }
+
+        edges.entry(position).or_default();
+        for required_id in &definition.requires {
+            //requirements filtered out by tags aren't run, so there's nothing to wait on
+            let Some(required_position) = positions_by_id.get(required_id) else {
+                continue;
+            };
+
+            if tests_to_run[*required_position].disabled && !unschedulable.contains(&position) {
+                warn!(
+                    "Test \"{}\" requires a disabled test: \"{}\"",
+                    test_label(definition),
+                    required_id
+                );
+            }
+
+            edges
+                .entry(*required_position)
+                .or_default()
+                .insert(position);
+        }
     }
 
-    job_definitions
+    RequirementGraph {
+        edges,
+        unschedulable,
+        duplicates,
+        missing,
+    }
 }
 
-pub fn tests_from_files(
-    config: &config::Config,
-    files: Vec<String>,
-    tags: Vec<String>,
-    project: Option<String>,
-    environment: Option<String>,
-    tag_mode: TagMode,
-) -> (Vec<test::Definition>, Vec<test::Definition>) {
-    let global_variables = config.generate_global_variables();
-    let mut tests_to_ignore: Vec<test::Definition> = Vec::new();
-    let tests_to_run: Vec<test::Definition> = files
-        .into_iter()
-        .filter_map(|s| load_test_from_path(s.as_str()))
+//Renders the requirement graph for Graphviz or Mermaid. Edges point from a required
+//test to the tests depending on it. Disabled tests are marked and ids that no test
+//has are drawn as "not found" nodes
+pub fn requirement_graph(
+    tests_to_run: &[test::Definition],
+    tests_to_ignore: &[test::Definition],
+    format: GraphFormat,
+) -> String {
+    let graph = build_requirement_graph(tests_to_run, tests_to_ignore);
+    let mut missing_ids: Vec<&String> = graph.missing.values().flatten().collect();
+    missing_ids.sort();
+    missing_ids.dedup();
+    let missing_node = |id: &String| {
+        format!(
+            "m{}",
+            missing_ids
+                .iter()
+                .position(|m| *m == id)
+                .unwrap_or_default()
+        )
+    };
+
+    let mut lines: Vec<String> = Vec::new();
+    match format {
+        GraphFormat::Dot => {
+            let escape = |label: &str| label.replace('\\', "\\\\").replace('"', "\\\"");
+            lines.push("digraph requirements {".to_string());
+            lines.push("  rankdir=LR;".to_string());
+            lines.push("  node [shape=box];".to_string());
+            for position in graph.edges.keys() {
+                let td = &tests_to_run[*position];
+                lines.push(format!(
+                    "  t{} [label=\"{}\"{}];",
+                    position,
+                    escape(&test_label(td)),
+                    if td.disabled { ", style=dashed" } else { "" }
+                ));
+            }
+            for (i, id) in missing_ids.iter().enumerate() {
+                lines.push(format!(
+                    "  m{} [label=\"{} (not found)\", style=dotted];",
+                    i,
+                    escape(id)
+                ));
+            }
+            for (position, dependants) in &graph.edges {
+                for dependant in dependants {
+                    lines.push(format!("  t{} -> t{};", position, dependant));
+                }
+            }
+            for (position, ids) in &graph.missing {
+                for id in ids {
+                    lines.push(format!(
+                        "  {} -> t{} [style=dotted];",
+                        missing_node(id),
+                        position
+                    ));
+                }
+            }
+            lines.push("}".to_string());
+        }
+        GraphFormat::Mermaid => {
+            let escape = |label: &str| label.replace('"', "#quot;");
+            lines.push("flowchart LR".to_string());
+            for position in graph.edges.keys() {
+                let td = &tests_to_run[*position];
+                lines.push(format!(
+                    "  t{}[\"{}{}\"]",
+                    position,
+                    escape(&test_label(td)),
+                    if td.disabled { " (disabled)" } else { "" }
+                ));
+            }
+            for (i, id) in missing_ids.iter().enumerate() {
+                lines.push(format!("  m{}[\"{} (not found)\"]", i, escape(id)));
+            }
+            for (position, dependants) in &graph.edges {
+                for dependant in dependants {
+                    lines.push(format!("  t{} --> t{}", position, dependant));
+                }
+            }
+            for (position, ids) in &graph.missing {
+                for id in ids {
+                    lines.push(format!("  {} -.-> t{}", missing_node(id), position));
+                }
+            }
+        }
+    }
+
+    lines.join("\n") + "\n"
+}
+
+fn construct_test_execution_graph_v2(
+    tests_to_run: Vec<test::Definition>,
+    tests_to_ignore: Vec<test::Definition>,
+) -> Result<Vec<Vec<Definition>>, Box<dyn Error + Send + Sync>> {
+    trace!("determine test execution order based on dependency graph");
+
+    let RequirementGraph {
+        edges,
+        unschedulable,
+        duplicates,
+        ..
+    } = build_requirement_graph(&tests_to_run, &tests_to_ignore);
+
+    let graph: BTreeMap<usize, BTreeSet<usize>> = edges
+        .into_iter()
+        .filter(|(position, _)| !unschedulable.contains(position))
+        .map(|(position, dependants)| {
+            let dependants = dependants
+                .into_iter()
+                .filter(|d| !unschedulable.contains(d))
+                .collect();
+            (position, dependants)
+        })
+        .collect();
+
+    if let Some(cycle) = find_requirement_cycle(&graph, &tests_to_run) {
+        return Err(Box::from(validation::Error {
+            reason: format!(
+                "circular test requirements detected: {}",
+                cycle.join(" -> ")
+            ),
+        }));
+    }
+
+    let mut jobs: Vec<BTreeSet<usize>> = Vec::new();
+    let mut scheduled_nodes: BTreeSet<usize> = BTreeSet::new();
+    while graph.len() != scheduled_nodes.len() {
+        let job = schedule_impl(&graph, &scheduled_nodes);
+        job.iter().for_each(|n| _ = scheduled_nodes.insert(*n));
+        jobs.push(job);
+    }
+
+    let job_definitions: Vec<Vec<Definition>> = jobs
+        .into_iter()
+        .map(|hs| {
+            hs.into_iter()
+                .map(|position| tests_to_run[position].clone())
+                .collect::<Vec<Definition>>()
+        })
+        .collect();
+
+    let missing_tests = unschedulable
+        .difference(&duplicates)
+        .map(|position| format!("\"{}\"", test_label(&tests_to_run[*position])))
+        .collect::<Vec<String>>()
+        .join(",");
+
+    if !missing_tests.is_empty() {
+        warn!("Warning: Required tests not found.");
+        warn!(
+            "Check the 'requires' tag in the following test definition(s): {}.\n\n",
+            missing_tests
+        );
+    }
+
+    for (count, job) in job_definitions.iter().enumerate() {
+        trace!(
+            "Job {count}: {}",
+            job.iter()
+                .enumerate()
+                .map(|(i, t)| t
+                    .name
+                    .clone()
+                    .unwrap_or(t.id.clone().unwrap_or(i.to_string())))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+
+    Ok(job_definitions)
+}
+
+//Files which fail to load are reported, and also returned as diagnostics
+pub fn tests_from_files(
+    config: &config::Config,
+    files: Vec<String>,
+    tags: Vec<String>,
+    project: Option<String>,
+    environment: Option<String>,
+    tag_mode: TagMode,
+    selection: &Selection,
+) -> (
+    Vec<test::Definition>,
+    Vec<test::Definition>,
+    Vec<validation::Diagnostic>,
+) {
+    let global_variables = config.generate_global_variables();
+    let mut load_errors: Vec<validation::Diagnostic> = Vec::new();
+    let mut tests_to_ignore: Vec<test::Definition> = Vec::new();
+    let loaded: Vec<test::File> = files
+        .into_iter()
+        .filter_map(|s| {
+            load_test_from_path(s.as_str())
+                .map_err(|d| load_errors.push(*d))
+                .ok()
+        })
+        .collect();
+    let tests_to_run: Vec<test::Definition> = loaded
+        .into_iter()
         .enumerate()
         .filter_map(|(i, f)| {
             validate_test_file(
@@ -978,9 +1980,11 @@ pub fn tests_from_files(
                 environment.clone(),
                 i,
             )
+            .map_err(|d| load_errors.push(*d))
+            .ok()
         })
         .filter_map(|f| {
-            if !ignored_due_to_tag_filter(&f, &tags, &tag_mode) {
+            if !ignored_due_to_tag_filter(&f, &tags, &tag_mode) && selection.matches(&f) {
                 Some(f)
             } else {
                 tests_to_ignore.push(f);
@@ -988,7 +1992,7 @@ pub fn tests_from_files(
             }
         })
         .collect();
-    (tests_to_run, tests_to_ignore)
+    (tests_to_run, tests_to_ignore, load_errors)
 }
 
 pub fn print_validation_failures(
@@ -1016,14 +2020,43 @@ pub fn print_validation_failures(
     }
 }
 
+pub fn unresolved_variables(tests: &[Definition]) -> Vec<(&Definition, test::UnresolvedVariable)> {
+    tests
+        .iter()
+        .enumerate()
+        .flat_map(|(position, td)| {
+            td.unresolved_variables(&validation::upstream_extractions(tests, position, &[]))
+                .into_iter()
+                .map(move |u| (td, u))
+        })
+        .collect()
+}
+
+pub fn print_unresolved_variables(
+    unresolved: &[(&Definition, test::UnresolvedVariable)],
+    as_errors: bool,
+) {
+    for (definition, variable) in unresolved {
+        let message = format!(
+            "Test at path \"{}\" uses variable `{}` in {}, which no test, stage, global, secret or extracted variable provides.",
+            definition.file_data.filename, variable.name, variable.location
+        );
+        if as_errors {
+            error!("{}", message);
+        } else {
+            warn!("{}", message);
+        }
+    }
+}
+
 pub async fn execute_tests(
     config: config::Config,
     tests_to_run: Vec<test::Definition>,
     mode_dryrun: bool,
     tests_to_ignore: Vec<test::Definition>,
-    junit_file: Option<String>,
+    reports: ReportFiles,
     cli_args: Box<serde_json::Value>,
-) -> Report {
+) -> Result<Report, Box<dyn Error + Send + Sync>> {
     if !tests_to_ignore.is_empty() {
         trace!("filtering out tests which don't match the tag pattern")
     }
@@ -1031,7 +2064,7 @@ pub async fn execute_tests(
     trace!("determine test execution order based on dependency graph");
 
     let tests_to_run_with_dependencies =
-        construct_test_execution_graph_v2(tests_to_run.clone(), tests_to_ignore.clone());
+        construct_test_execution_graph_v2(tests_to_run.clone(), tests_to_ignore.clone())?;
     let all_tests: Vec<&Definition> = tests_to_run_with_dependencies.iter().flatten().collect();
 
     let mut session: Option<telemetry::Session> = None;
@@ -1055,7 +2088,7 @@ pub async fn execute_tests(
                     }
                     Err(failures) => {
                         print_validation_failures(failures, true);
-                        return Report::default();
+                        return Ok(Report::default());
                     }
                 }
             } else if let Err(failures) = validation_results {
@@ -1067,44 +2100,82 @@ pub async fn execute_tests(
     }
 
     let execution_result = if mode_dryrun {
+        // dry runs print every request they would make, keep them sequential
         run_tests(
             tests_to_run_with_dependencies,
             session,
-            FailurePolicy::new(DryRunExecutionPolicy),
+            DryRunExecutionPolicy,
             &config,
+            1,
         )
-        .await
+        .await?
     } else {
         run_tests(
             tests_to_run_with_dependencies,
             session,
-            FailurePolicy::new(ActualRunExecutionPolicy),
+            ActualRunExecutionPolicy,
             &config,
+            config.settings.parallelism as usize,
         )
-        .await
+        .await?
     };
 
-    _ = junit_file.and_then(|f| {
-        formatted_result_to_file(JunitResultFormatter {}, &execution_result, f.as_str())
-            .map_err(|e| {
-                error!("Error writing junit report to {f} : {e}");
-                e
-            })
-            .ok()
+    _ = reports.junit.and_then(|f| {
+        formatted_result_to_file(
+            JunitResultFormatter {},
+            &execution_result,
+            f.as_str(),
+            reports.overwrite,
+        )
+        .map_err(|e| {
+            error!("Error writing junit report to {f} : {e}");
+            e
+        })
+        .ok()
+    });
+
+    _ = reports.json.and_then(|f| {
+        formatted_result_to_file(
+            JsonResultFormatter {},
+            &execution_result,
+            f.as_str(),
+            reports.overwrite,
+        )
+        .map_err(|e| {
+            error!("Error writing json report to {f} : {e}");
+            e
+        })
+        .ok()
+    });
+
+    _ = reports.html.and_then(|f| {
+        formatted_result_to_file(
+            HtmlResultFormatter {},
+            &execution_result,
+            f.as_str(),
+            reports.overwrite,
+        )
+        .map_err(|e| {
+            error!("Error writing html report to {f} : {e}");
+            e
+        })
+        .ok()
     });
 
-    Report::from(execution_result)
+    Ok(Report::from(execution_result))
 }
 
 async fn run(
-    state: &mut State,
+    state: &State,
     td: &test::Definition,
     iteration: u32,
     test: Option<telemetry::Test>,
     config: &config::Config,
 ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
     let mut results = Vec::new();
-    let mut setup_result = validate_setup(state, td, iteration).await?;
+    let test_deadline = deadline_after(td.timeout);
+    let mut setup_result =
+        validate_setup(state, td, iteration, config.settings.timeout, test_deadline).await?;
 
     if let Some(test_telemetry) = &test {
         if !setup_result.1.is_empty() {
@@ -1125,7 +2196,8 @@ async fn run(
     let mut success = setup_result.0;
 
     if success {
-        let td_results = validate_td(state, td, iteration, test.clone(), config).await;
+        let td_results =
+            validate_td(state, td, iteration, test.clone(), config, test_deadline).await;
 
         match td_results {
             Ok(mut r) => {
@@ -1138,7 +2210,16 @@ async fn run(
             }
         }
 
-        let cleanup_result = run_cleanup(state, td, iteration, success, results.len() as u32).await;
+        // cleanup isn't bound by the test's timeout, a timed out test still cleans up after itself
+        let cleanup_result = run_cleanup(
+            state,
+            td,
+            iteration,
+            success,
+            results.len() as u32,
+            config.settings.timeout,
+        )
+        .await;
         match cleanup_result {
             Ok(mut r) => {
                 if let Some(test_telemetry) = &test {
@@ -1178,16 +2259,26 @@ async fn dry_run(
 }
 
 async fn validate_td(
-    state: &mut State,
+    state: &State,
     td: &test::Definition,
     iteration: u32,
     test: Option<telemetry::Test>,
     config: &config::Config,
+    test_deadline: Option<Instant>,
 ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
     let mut results = Vec::new();
 
     for (stage_index, stage) in td.stages.iter().enumerate() {
-        let stage_result = validate_stage(state, td, stage, stage_index, iteration).await?;
+        let stage_result = validate_stage(
+            state,
+            td,
+            stage,
+            stage_index,
+            iteration,
+            stage.timeout.or(config.settings.timeout),
+            test_deadline,
+        )
+        .await?;
 
         if let Some(test_telemetry) = &test {
             let telemetry_result =
@@ -1230,17 +2321,27 @@ fn process_response(
         validation: Validated::Good(vec![()]),
         project,
         environment,
+        timeout_reason: None,
+        previous_attempts: Vec::new(),
     };
 
     let validate_headers = |validation_type: &str,
-                            _expected: &Vec<Header>,
-                            _actual: &Vec<Header>|
-     -> Validated<(), String> {
-        if !_expected.is_empty() {
-            //no logic currently
-            trace!("validating {}headers", validation_type);
-        }
-        Good(())
+                            expected: &Vec<definition::ResponseHeaderDescriptor>,
+                            actual: &Vec<Header>|
+     -> Vec<Validated<(), String>> {
+        if expected.is_empty() {
+            return vec![Good(())];
+        }
+
+        trace!("validating {}headers", validation_type);
+        expected
+            .iter()
+            .flat_map(|h| {
+                h.check(actual, &|expected, actual| -> String {
+                    format!("Expected {validation_type}{expected} but received {actual}")
+                })
+            })
+            .collect()
     };
 
     let validate_status_code = |validation_type: &str,
@@ -1279,19 +2380,27 @@ fn process_response(
 
     let validate_body = |validation_type: &str,
                          expected: &std::option::Option<BodyOrSchema>,
-                         actual: &serde_json::Value,
+                         actual: &ResponseResultData,
                          ignore_body: &[String],
                          strict: bool|
      -> Vec<Validated<(), String>> {
         trace!("In validate body({:?})", expected);
         if let Some(exp) = expected {
+            if !actual.is_json {
+                return vec![Validated::fail(format!(
+                    "Expected {}JSON body but response was not JSON (content type {})",
+                    validation_type,
+                    actual.content_type.as_deref().unwrap_or("not specified")
+                ))];
+            }
+
             trace!("validating body");
             BodyOrSchemaChecker {
                 value_or_schema: exp,
                 ignore_values: ignore_body,
                 strict,
             }
-            .check(actual, &|e, a| {
+            .check(&actual.body, &|e, a| {
                 format!(
                     "Expected {}{} did not match actual {}",
                     validation_type, e, a
@@ -1302,14 +2411,50 @@ fn process_response(
         }
     };
 
+    let validate_content = |validation_type: &str,
+                            expected: &Option<ContentSpecification>,
+                            actual: &Vec<u8>|
+     -> Vec<Validated<(), String>> {
+        match expected {
+            None => vec![Good(())],
+            Some(c) => {
+                trace!("validating {}content", validation_type);
+                c.check(actual, &|e, a| {
+                    format!("Expected {}{} but received {}", validation_type, e, a)
+                })
+            }
+        }
+    };
+
+    let validate_assertions = |expected: &Vec<definition::ResponseAssertion>,
+                               actual: &ResponseResultData|
+     -> Vec<Validated<(), String>> {
+        if expected.is_empty() {
+            return vec![Good(())];
+        }
+
+        if !actual.is_json {
+            return vec![Validated::fail(format!(
+                "Expected JSON body for assertions but response was not JSON (content type {})",
+                actual.content_type.as_deref().unwrap_or("not specified")
+            ))];
+        }
+
+        trace!("validating assertions");
+        expected
+            .iter()
+            .flat_map(|a| {
+                a.check(&actual.body, &|e, a| {
+                    format!("Expected {e} but received {a}")
+                })
+            })
+            .collect()
+    };
+
     if let Some(resp) = &details.actual {
         let mut validation: Vec<Validated<(), String>> = vec![Good(())];
 
-        validation.push(validate_headers(
-            "",
-            &details.expected.headers,
-            &resp.headers,
-        ));
+        validation.append(validate_headers("", &details.expected.headers, &resp.headers).as_mut());
         validation.append(validate_status_code("", &details.expected.status, resp.status).as_mut());
         validation.append(
             validate_response_time("", &details.expected.response_time, details.request_runtime)
@@ -1319,12 +2464,14 @@ fn process_response(
             validate_body(
                 "",
                 &details.expected.body,
-                &resp.body,
+                resp,
                 ignore_body,
                 details.expected.strict,
             )
             .as_mut(),
         );
+        validation.append(validate_content("", &details.expected.content, &resp.raw_body).as_mut());
+        validation.append(validate_assertions(&details.expected.assertions, resp).as_mut());
 
         validation.append(
             //if a compare request was specified, validate it
@@ -1332,6 +2479,31 @@ fn process_response(
                 .compare_actual
                 .map(|compare_request_result| {
                     let mut ret = vec![];
+                    //only the headers the response asserts on are compared, the rest
+                    //(dates, request ids, ...) are expected to differ between calls
+                    let compare_headers = details
+                        .expected
+                        .headers
+                        .iter()
+                        .map(|h| {
+                            let compare_value = compare_request_result
+                                .headers
+                                .iter()
+                                .find(|ch| ch.header.eq_ignore_ascii_case(&h.header))
+                                .map(|ch| ch.value.clone());
+                            definition::ResponseHeaderDescriptor {
+                                header: h.header.clone(),
+                                absent: compare_value.is_none(),
+                                specification: compare_value.map(|v| StringSpecification {
+                                    specification: Some(Specification::Value(v)),
+                                    ..Default::default()
+                                }),
+                            }
+                        })
+                        .collect();
+                    ret.append(
+                        validate_headers("compare ", &compare_headers, &resp.headers).as_mut(),
+                    );
                     ret.append(
                         validate_status_code(
                             "compare ",
@@ -1350,16 +2522,29 @@ fn process_response(
                         )
                         .as_mut(),
                     );
-                    ret.append(
-                        validate_body(
-                            "compare ",
-                            &Some(BodyOrSchema::Body(compare_request_result.body)),
-                            &resp.body,
-                            ignore_body,
-                            details.expected.strict,
-                        )
-                        .as_mut(),
-                    );
+                    if compare_request_result.is_json {
+                        ret.append(
+                            validate_body(
+                                "compare ",
+                                &Some(BodyOrSchema::Body(compare_request_result.body)),
+                                resp,
+                                ignore_body,
+                                details.expected.strict,
+                            )
+                            .as_mut(),
+                        );
+                    } else {
+                        ret.append(
+                            validate_content(
+                                "compare ",
+                                &Some(ContentSpecification::same_as(
+                                    &compare_request_result.raw_body,
+                                )),
+                                &resp.raw_body,
+                            )
+                            .as_mut(),
+                        );
+                    }
                     ret
                 })
                 .unwrap_or(vec![Good(())])
@@ -1379,45 +2564,64 @@ fn process_response(
         result.status = TestStatus::Failed;
     }
 
+    result
+}
+
+fn log_validation_failures(result: &StageResult) {
     if let validated::Validated::Fail(nec) = &result.validation {
         let error_str = nec
             .into_iter()
             .fold("Response Validation Error(s):".to_string(), |acc, curr| {
                 format!("{acc}\n{curr}")
             });
-        error!("{error_str}\n");
+        if std::io::stdout().is_terminal() {
+            error!("{}\n", json::diff::colourise(&error_str));
+        } else {
+            error!("{error_str}\n");
+        }
     }
-
-    result
 }
 
 async fn validate_setup(
-    state: &mut State,
+    state: &State,
     td: &test::Definition,
     iteration: u32,
+    timeout: Option<u64>,
+    test_deadline: Option<Instant>,
 ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
+    let state_variables = state.variables();
     if let Some(setup) = &td.setup {
         let req_method = setup.request.method.as_method();
         let req_url = td.get_url(
             iteration,
             &setup.request.url,
             &setup.request.params,
-            &state.variables,
+            &state_variables,
             &td.variables,
         );
         let req_headers = td.get_setup_request_headers(iteration);
         let req_body = td.get_request_body(
             &setup.request.body,
-            &state.variables,
+            &state_variables,
+            &td.variables,
+            iteration,
+        );
+        let req_payload = td.get_request_payload(
+            &setup.request.payload,
+            &state_variables,
             &td.variables,
             iteration,
         );
 
-        let resolved_request = test::definition::ResolvedRequest::new(
-            req_url.clone(),
-            req_method.clone(),
-            req_headers.clone(),
-            req_body.clone(),
+        let resolved_request = resolve_headers(
+            state,
+            test::definition::ResolvedRequest::new(
+                req_url.clone(),
+                req_method,
+                req_headers,
+                req_body,
+            )
+            .with_payload(req_payload),
         );
 
         debug!("executing setup stage: {}", req_url);
@@ -1425,23 +2629,35 @@ async fn validate_setup(
         let expected = ExpectedResultData::from_request(
             setup.response.clone(),
             td,
-            &state.variables,
+            &state_variables,
             &td.variables,
             iteration,
         );
-        let start_time = Instant::now();
-        let req_response = process_request(state, resolved_request).await?;
-        let runtime = start_time.elapsed().as_millis() as u32;
-        let actual = ResponseResultData::from_response(req_response).await;
+        let request = request_details(&resolved_request);
 
-        let request = RequestDetails {
-            headers: req_headers
-                .iter()
-                .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-                .collect(),
-            url: req_url.to_string(),
-            method: req_method,
-            body: req_body.unwrap_or(serde_json::Value::Null),
+        let start_time = Instant::now();
+        let deadline = earliest_deadline(test_deadline, deadline_after(timeout));
+        let outcome = match send_request(state, resolved_request, deadline).await {
+            Err(e) if e.is::<UnresolvedVariableError>() => {
+                let result = errored_stage_result(0, StageType::Setup, None, td, request, &*e);
+                log_validation_failures(&result);
+                return Ok((false, vec![result]));
+            }
+            outcome => outcome?,
+        };
+        let Some((runtime, actual)) = outcome else {
+            let result = timed_out_stage_result(
+                0,
+                StageType::Setup,
+                None,
+                td,
+                request,
+                expected,
+                start_time,
+                test_deadline,
+            );
+            log_validation_failures(&result);
+            return Ok((false, vec![result]));
         };
 
         let details = ResultDetails {
@@ -1467,244 +2683,344 @@ async fn validate_setup(
         // extract variables and add them to the state
         if let Some(r) = &setup.response {
             if let Some(a) = &result.details.actual {
-                for v in &r.extract {
-                    match extract_json(&v.field, 0, a.body.clone()) {
-                        Ok(result) => {
-                            let converted_result = match result {
-                                serde_json::Value::Bool(b) => b.to_string(),
-                                serde_json::Value::Number(n) => n.to_string(),
-                                serde_json::Value::String(s) => s.to_string(),
-                                _ => "".to_string(),
-                            };
-                            state.variables.insert(v.name.clone(), converted_result);
-                        }
-                        Err(error) => {
-                            error!("no json result found: {}", error);
-                        }
-                    }
-                }
+                extract_variables(state, &r.extract, a);
             }
         }
 
+        log_validation_failures(&result);
         return Ok((result.status == TestStatus::Passed, vec![result]));
     }
 
     Ok((true, Vec::new()))
 }
 
+//Runs the cleanup requests for the outcome of the iteration. A cleanup stage that fails,
+//because its placeholders couldn't be resolved or it timed out, fails the test
 async fn run_cleanup(
-    state: &mut State,
+    state: &State,
     td: &test::Definition,
     iteration: u32,
     succeeded: bool,
     stage_count: u32,
+    timeout: Option<u64>,
 ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
-    let mut results = Vec::new();
-    let mut counter = stage_count;
-
-    if td.cleanup.always.is_some()
-        || td.cleanup.onsuccess.is_some()
-        || td.cleanup.onfailure.is_some()
-    {
-        debug!("running test cleanup");
+    let outcome_request = if succeeded {
+        td.cleanup.onsuccess.as_ref().map(|r| ("onsuccess", r))
     } else {
-        return Ok((true, results));
+        td.cleanup.onfailure.as_ref().map(|r| ("onfailure", r))
+    };
+    let requests: Vec<(&str, &definition::RequestDescriptor)> = outcome_request
+        .into_iter()
+        .chain(td.cleanup.always.as_ref().map(|r| ("cleanup", r)))
+        .collect();
+
+    if requests.is_empty() {
+        return Ok((true, Vec::new()));
     }
 
-    if succeeded {
-        if let Some(onsuccess) = &td.cleanup.onsuccess {
-            debug!("execute onsuccess request");
-            let success_method = onsuccess.method.as_method();
-            let success_url = &td.get_url(
-                iteration,
-                &onsuccess.url,
-                &onsuccess.params,
-                &state.variables,
-                &td.variables,
-            );
-            let success_headers = td.get_headers(&onsuccess.headers, iteration);
-            let success_body =
-                td.get_request_body(&onsuccess.body, &state.variables, &td.variables, iteration);
-            let resolved_request = test::definition::ResolvedRequest::new(
-                success_url.clone(),
-                success_method.clone(),
-                success_headers.clone(),
-                success_body.clone(),
-            );
+    debug!("running test cleanup");
+    let mut results = Vec::new();
+    for (name, request) in requests {
+        debug!("execute {} request", name);
+        let counter = stage_count + results.len() as u32;
+        results.push(run_cleanup_request(state, td, request, iteration, counter, timeout).await?);
+    }
 
-            let expected = ExpectedResultData::from_request(
-                None,
-                td,
-                &state.variables,
-                &td.variables,
-                iteration,
-            );
-            let start_time = Instant::now();
-            let req_response = process_request(state, resolved_request).await?;
-            let runtime = start_time.elapsed().as_millis() as u32;
-            let actual = ResponseResultData::from_response(req_response).await;
+    results.iter().for_each(log_validation_failures);
+    let passed = results.iter().all(|r| r.status != TestStatus::Failed);
+    Ok((passed, results))
+}
 
-            let request = RequestDetails {
-                headers: success_headers
-                    .iter()
-                    .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-                    .collect(),
-                url: success_url.to_string(),
-                method: success_method,
-                body: success_body.unwrap_or(serde_json::Value::Null),
-            };
+async fn run_cleanup_request(
+    state: &State,
+    td: &test::Definition,
+    request: &definition::RequestDescriptor,
+    iteration: u32,
+    counter: u32,
+    timeout: Option<u64>,
+) -> Result<StageResult, Box<dyn Error + Send + Sync>> {
+    let state_variables = state.variables();
+    let req_url = td.get_url(
+        iteration,
+        &request.url,
+        &request.params,
+        &state_variables,
+        &td.variables,
+    );
+    let req_headers = td.get_headers(&request.headers, iteration);
+    let req_body = td.get_request_body(&request.body, &state_variables, &td.variables, iteration);
+    let req_payload =
+        td.get_request_payload(&request.payload, &state_variables, &td.variables, iteration);
+    let resolved_request = resolve_headers(
+        state,
+        test::definition::ResolvedRequest::new(
+            req_url,
+            request.method.as_method(),
+            req_headers,
+            req_body,
+        )
+        .with_payload(req_payload),
+    );
 
-            let details = ResultDetails {
-                request,
-                expected,
-                request_runtime: runtime,
-                actual,
-                compare_request: None,
-                compare_request_runtime: None,
-                compare_actual: None,
-            };
+    let expected =
+        ExpectedResultData::from_request(None, td, &state_variables, &td.variables, iteration);
+    let request = request_details(&resolved_request);
 
-            let result = process_response(
+    let start_time = Instant::now();
+    Ok(
+        match send_request(state, resolved_request, deadline_after(timeout)).await {
+            Err(e) if e.is::<UnresolvedVariableError>() => {
+                errored_stage_result(counter, StageType::Cleanup, None, td, request, &*e)
+            }
+            Err(e) => return Err(e),
+            Ok(Some((runtime, actual))) => process_response(
                 counter,
                 StageType::Cleanup,
                 None,
-                details,
+                ResultDetails {
+                    request,
+                    expected,
+                    request_runtime: runtime,
+                    actual,
+                    compare_request: None,
+                    compare_request_runtime: None,
+                    compare_actual: None,
+                },
                 &Vec::new(),
                 td.project.clone(),
                 td.environment.clone(),
-            );
-            counter += 1;
-            results.push(result);
-        }
-    } else if let Some(onfailure) = &td.cleanup.onfailure {
-        debug!("execute onfailure request");
-        let failure_method = onfailure.method.as_method();
-        let failure_url = &td.get_url(
-            iteration,
-            &onfailure.url,
-            &onfailure.params,
-            &state.variables,
-            &td.variables,
-        );
-        let failure_headers = td.get_headers(&onfailure.headers, iteration);
-        let failure_body =
-            td.get_request_body(&onfailure.body, &state.variables, &td.variables, iteration);
-        let resolved_request = test::definition::ResolvedRequest::new(
-            failure_url.clone(),
-            failure_method.clone(),
-            failure_headers.clone(),
-            failure_body.clone(),
-        );
-
-        let expected =
-            ExpectedResultData::from_request(None, td, &state.variables, &td.variables, iteration);
-        let start_time = Instant::now();
-        let req_response = process_request(state, resolved_request).await?;
-        let runtime = start_time.elapsed().as_millis() as u32;
-        let actual = ResponseResultData::from_response(req_response).await;
-
-        let request = RequestDetails {
-            headers: failure_headers
-                .iter()
-                .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-                .collect(),
-            url: failure_url.to_string(),
-            method: failure_method,
-            body: failure_body.unwrap_or(serde_json::Value::Null),
-        };
+            ),
+            Ok(None) => timed_out_stage_result(
+                counter,
+                StageType::Cleanup,
+                None,
+                td,
+                request,
+                expected,
+                start_time,
+                None,
+            ),
+        },
+    )
+}
 
-        let details = ResultDetails {
-            request,
-            expected,
-            request_runtime: runtime,
-            actual,
-            compare_request: None,
-            compare_request_runtime: None,
-            compare_actual: None,
+async fn validate_stage(
+    state: &State,
+    td: &test::Definition,
+    stage: &definition::StageDescriptor,
+    stage_index: usize,
+    iteration: u32,
+    timeout: Option<u64>,
+    test_deadline: Option<Instant>,
+) -> Result<StageResult, Box<dyn Error + Send + Sync>> {
+    let mut previous_attempts = Vec::new();
+    let mut attempt = 1;
+    let mut result = loop {
+        let outcome = poll_stage(
+            state,
+            td,
+            stage,
+            stage_index,
+            iteration,
+            timeout,
+            test_deadline,
+        )
+        .await;
+        // placeholders left unresolved fail the stage without sending its request
+        let outcome = match outcome.map_err(|e| StageRequestError::split(e, stage)) {
+            Err((request, e)) if e.is::<UnresolvedVariableError>() => Ok(errored_stage_result(
+                stage_index as u32,
+                StageType::Normal,
+                stage.name.clone(),
+                td,
+                request,
+                &*e,
+            )),
+            outcome => outcome,
+        };
+        let Some(retry) = &stage.retry else {
+            break outcome.map_err(|(_, e)| e)?;
         };
 
-        let result = process_response(
-            counter,
-            StageType::Cleanup,
-            None,
-            details,
-            &Vec::new(),
-            td.project.clone(),
-            td.environment.clone(),
+        // with a retry policy errors become failed attempts, so every attempt is reported
+        let result = outcome.unwrap_or_else(|(request, e)| {
+            errored_stage_result(
+                stage_index as u32,
+                StageType::Normal,
+                stage.name.clone(),
+                td,
+                request,
+                &*e,
+            )
+        });
+        if attempt >= retry.attempts || !should_retry_stage(retry, &result) {
+            break result;
+        }
+
+        let delay = retry.delay_after(attempt);
+        if outlasts(delay, test_deadline) {
+            break test_timed_out(result, td);
+        }
+
+        log_validation_failures(&result);
+        debug!(
+            "stage {} attempt {}/{} failed, retrying in {}ms",
+            stage_index + 1,
+            attempt,
+            retry.attempts,
+            delay
         );
-        counter += 1;
-        results.push(result);
+        previous_attempts.push(result);
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        attempt += 1;
+    };
+    result.previous_attempts = previous_attempts;
+
+    // rather than wait out a delay that passes the test's deadline the stage fails
+    let delay = stage.delay.unwrap_or_default();
+    let cut_short = delay > 0 && outlasts(delay, test_deadline);
+    if cut_short {
+        result = test_timed_out(result, td);
     }
+    log_validation_failures(&result);
 
-    if let Some(request) = &td.cleanup.always {
-        debug!("execute cleanup request");
-        let req_method = request.method.as_method();
-        let req_url = &td.get_url(
+    if delay > 0 && !cut_short {
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+
+    Ok(result)
+}
+
+//Re-issues the stage request until the poll condition holds or its max duration passes,
+//the stage's own response checks then apply to the last response
+async fn poll_stage(
+    state: &State,
+    td: &test::Definition,
+    stage: &definition::StageDescriptor,
+    stage_index: usize,
+    iteration: u32,
+    timeout: Option<u64>,
+    test_deadline: Option<Instant>,
+) -> Result<StageResult, Box<dyn Error + Send + Sync>> {
+    let request_deadline = || earliest_deadline(test_deadline, deadline_after(timeout));
+    let Some(poll) = &stage.poll else {
+        return attempt_stage(
+            state,
+            td,
+            stage,
+            stage_index,
             iteration,
-            &request.url,
-            &request.params,
-            &state.variables,
-            &td.variables,
-        );
-        let req_headers = td.get_cleanup_request_headers(iteration);
-        let req_body =
-            td.get_request_body(&request.body, &state.variables, &td.variables, iteration);
-        let resolved_request = test::definition::ResolvedRequest::new(
-            req_url.clone(),
-            req_method.clone(),
-            req_headers.clone(),
-            req_body.clone(),
-        );
+            request_deadline(),
+            test_deadline,
+        )
+        .await;
+    };
 
-        let expected =
-            ExpectedResultData::from_request(None, td, &state.variables, &td.variables, iteration);
-        let start_time = Instant::now();
-        let req_response = process_request(state, resolved_request).await?;
-        let runtime = start_time.elapsed().as_millis() as u32;
-        let actual = ResponseResultData::from_response(req_response).await;
+    let start_time = Instant::now();
+    let poll_deadline = earliest_deadline(
+        test_deadline,
+        Some(start_time + Duration::from_millis(poll.max_duration)),
+    );
+    let mut polls = 1;
+    loop {
+        let deadline = earliest_deadline(poll_deadline, request_deadline());
+        let mut result = attempt_stage(
+            state,
+            td,
+            stage,
+            stage_index,
+            iteration,
+            deadline,
+            test_deadline,
+        )
+        .await?;
+        let condition = poll_condition(state, td, stage, iteration, poll, &result);
+        if condition.is_good() {
+            debug!(
+                "stage {} poll condition met after {} requests",
+                stage_index + 1,
+                polls
+            );
+            return Ok(result);
+        }
 
-        let request = RequestDetails {
-            headers: req_headers
-                .iter()
-                .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-                .collect(),
-            url: req_url.to_string(),
-            method: req_method,
-            body: req_body.unwrap_or(serde_json::Value::Null),
-        };
+        let next_poll = Instant::now() + Duration::from_millis(poll.interval);
+        if poll_deadline.is_some_and(|d| next_poll >= d) {
+            let mut failures = vec![format!(
+                "Poll condition not met after {} requests in {} milliseconds",
+                polls,
+                start_time.elapsed().as_millis()
+            )];
+            if let validated::Validated::Fail(nec) = condition {
+                failures.extend(nec);
+            }
+            if let validated::Validated::Fail(nec) = result.validation {
+                failures.extend(nec);
+            }
 
-        let details = ResultDetails {
-            request,
-            expected,
-            request_runtime: runtime,
-            actual,
-            compare_request: None,
-            compare_request_runtime: None,
-            compare_actual: None,
-        };
+            result.status = TestStatus::Failed;
+            result.validation = failures
+                .into_iter()
+                .map(Validated::fail)
+                .collect::<Validated<Vec<()>, String>>();
+            // the poll may have been cut short by the test's deadline rather than its own
+            if outlasts(poll.interval, test_deadline) {
+                return Ok(test_timed_out(result, td));
+            }
+            return Ok(result);
+        }
 
-        let result = process_response(
-            counter,
-            StageType::Cleanup,
-            None,
-            details,
-            &Vec::new(),
-            td.project.clone(),
-            td.environment.clone(),
+        debug!(
+            "stage {} poll condition not met, polling again in {}ms",
+            stage_index + 1,
+            poll.interval
         );
-        results.push(result);
+        tokio::time::sleep(Duration::from_millis(poll.interval)).await;
+        polls += 1;
     }
+}
 
-    Ok((true, results))
+fn poll_condition(
+    state: &State,
+    td: &test::Definition,
+    stage: &definition::StageDescriptor,
+    iteration: u32,
+    poll: &definition::PollDescriptor,
+    result: &StageResult,
+) -> Validated<Vec<()>, String> {
+    let expected = ExpectedResultData::from_request(
+        Some(poll.until.clone()),
+        td,
+        &state.variables(),
+        &[&stage.variables[..], &td.variables[..]].concat(),
+        iteration,
+    );
+
+    process_response(
+        result.stage,
+        result.stage_type.clone(),
+        None,
+        ResultDetails {
+            expected,
+            ..result.details.clone()
+        },
+        &poll.until.ignore,
+        None,
+        None,
+    )
+    .validation
 }
 
-async fn validate_stage(
-    state: &mut State,
+async fn attempt_stage(
+    state: &State,
     td: &test::Definition,
     stage: &definition::StageDescriptor,
     stage_index: usize,
     iteration: u32,
+    deadline: Option<Instant>,
+    test_deadline: Option<Instant>,
 ) -> Result<StageResult, Box<dyn Error + Send + Sync>> {
+    let state_variables = state.variables();
     let stage_name = stage.name.clone().unwrap_or((stage_index + 1).to_string());
     debug!("execute stage {stage_name}");
 
@@ -1713,46 +3029,56 @@ async fn validate_stage(
         iteration,
         &stage.request.url,
         &stage.request.params,
-        &state.variables,
+        &state_variables,
         &[&stage.variables[..], &td.variables[..]].concat(),
     );
     let req_headers = td.get_headers(&stage.request.headers, iteration);
     let req_body = td.get_request_body(
         &stage.request.body,
-        &state.variables,
+        &state_variables,
+        &[&stage.variables[..], &td.variables[..]].concat(),
+        iteration,
+    );
+    let req_payload = td.get_request_payload(
+        &stage.request.payload,
+        &state_variables,
         &[&stage.variables[..], &td.variables[..]].concat(),
         iteration,
     );
 
-    let resolved_request = test::definition::ResolvedRequest::new(
-        req_url.clone(),
-        req_method.clone(),
-        req_headers.clone(),
-        req_body.clone(),
+    let resolved_request = resolve_headers(
+        state,
+        test::definition::ResolvedRequest::new(req_url.clone(), req_method, req_headers, req_body)
+            .with_payload(req_payload),
     );
     debug!("executing test stage {stage_name}: {req_url}");
     let expected = ExpectedResultData::from_request(
         stage.response.clone(),
         td,
-        &state.variables,
+        &state_variables,
         &[&stage.variables[..], &td.variables[..]].concat(),
         iteration,
     );
-    let request = RequestDetails {
-        headers: req_headers
-            .iter()
-            .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-            .collect(),
-        url: req_url.to_string(),
-        method: req_method,
-        body: req_body.unwrap_or(serde_json::Value::Null),
-    };
-    let mut compare_response_opt = None;
+    let request = request_details(&resolved_request);
+    let mut compare_actual = None;
     let mut compare_request = None;
 
-    let req_start_time = Instant::now();
-    let req_response = process_request(state, resolved_request).await?;
-    let req_runtime = req_start_time.elapsed().as_millis() as u32;
+    let start_time = Instant::now();
+    let sent = send_request(state, resolved_request, deadline)
+        .await
+        .map_err(|error| StageRequestError::new(&request, error))?;
+    let Some((req_runtime, actual)) = sent else {
+        return Ok(timed_out_stage_result(
+            stage_index as u32,
+            StageType::Normal,
+            stage.name.clone(),
+            td,
+            request,
+            expected,
+            start_time,
+            test_deadline,
+        ));
+    };
 
     let compare_start_time = Instant::now();
 
@@ -1765,44 +3091,50 @@ async fn validate_stage(
             iteration,
             &compare.url,
             &params,
-            &state.variables,
+            &state_variables,
             &[&stage.variables[..], &td.variables[..]].concat(),
         );
         let compare_headers = td.get_stage_compare_headers(stage_index, iteration);
         let compare_body = td.get_compare_body(
             compare,
-            &state.variables,
+            &state_variables,
             &[&stage.variables[..], &td.variables[..]].concat(),
             iteration,
         );
 
-        let resolved_compare_request = test::definition::ResolvedRequest::new(
-            compare_url.clone(),
-            compare_method.clone(),
-            compare_headers.clone(),
-            compare_body.clone(),
+        let resolved_compare_request = resolve_headers(
+            state,
+            test::definition::ResolvedRequest::new(
+                compare_url.clone(),
+                compare_method,
+                compare_headers,
+                compare_body,
+            ),
         );
 
-        compare_request = Some(RequestDetails {
-            headers: compare_headers
-                .iter()
-                .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-                .collect(),
-            url: compare_url.to_string(),
-            method: compare_method,
-            body: compare_body.unwrap_or(serde_json::Value::Null),
-        });
+        compare_request = Some(request_details(&resolved_compare_request));
 
-        compare_response_opt = Some(process_request(state, resolved_compare_request).await?);
+        let compared = send_request(state, resolved_compare_request, deadline)
+            .await
+            .map_err(|error| StageRequestError::new(&request, error))?;
+        match compared {
+            Some((_, compare_response)) => compare_actual = compare_response,
+            None => {
+                return Ok(timed_out_stage_result(
+                    stage_index as u32,
+                    StageType::Normal,
+                    stage.name.clone(),
+                    td,
+                    request,
+                    expected,
+                    start_time,
+                    test_deadline,
+                ))
+            }
+        }
     }
 
     let compare_runtime = compare_start_time.elapsed().as_millis() as u32;
-    let actual = ResponseResultData::from_response(req_response).await;
-    let mut compare_actual = None;
-
-    if let Some(compare_response) = compare_response_opt {
-        compare_actual = ResponseResultData::from_response(compare_response).await;
-    }
 
     let details = ResultDetails {
         request,
@@ -1827,50 +3159,129 @@ async fn validate_stage(
     // extract variables and add them to the state
     if let Some(r) = &stage.response {
         if let Some(a) = &result.details.actual {
-            for v in &r.extract {
-                match extract_json(&v.field, 0, a.body.clone()) {
-                    Ok(result) => {
-                        let converted_result = match result {
-                            serde_json::Value::Bool(b) => b.to_string(),
-                            serde_json::Value::Number(n) => n.to_string(),
-                            serde_json::Value::String(s) => s.to_string(),
-                            _ => "".to_string(),
-                        };
-                        debug!("extracting variable: {} = {}", v.name, converted_result);
-                        state.variables.insert(v.name.clone(), converted_result);
-                    }
-                    Err(error) => {
-                        error!("no json result found: {}", error);
-                    }
-                }
-            }
+            extract_variables(state, &r.extract, a);
         }
     }
 
-    if stage.delay.is_some_and(|d| d > 0) {
-        tokio::time::sleep(tokio::time::Duration::from_millis(stage.delay.unwrap())).await;
+    Ok(result)
+}
+
+//Values keep their JSON type, so a later `${x}` body value is only a number, boolean
+//or structure when the extracted value was one
+fn extract_variable(
+    extraction: &definition::ResponseExtraction,
+    actual: &ResponseResultData,
+) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    match extraction.source {
+        definition::ExtractionSource::Body => {
+            if extraction.field.is_empty() {
+                return Ok(actual.body.clone());
+            }
+
+            extract_json(&extraction.field, 0, actual.body.clone())
+        }
+        definition::ExtractionSource::Header => actual
+            .headers
+            .iter()
+            .find(|h| h.header.eq_ignore_ascii_case(&extraction.field))
+            .map(|h| serde_json::Value::String(h.value.clone()))
+            .ok_or_else(|| Box::from(format!("header {} not found", extraction.field))),
+        definition::ExtractionSource::Status => Ok(serde_json::Value::from(actual.status)),
+        definition::ExtractionSource::Cookie => actual
+            .headers
+            .iter()
+            .filter(|h| h.header.eq_ignore_ascii_case("set-cookie"))
+            .filter_map(|h| StateCookie::new(h.value.clone()))
+            .find(|c| c.key == extraction.field)
+            .map(|c| serde_json::Value::String(c.value))
+            .ok_or_else(|| Box::from(format!("cookie {} not found", extraction.field))),
     }
+}
 
-    Ok(result)
+fn extract_variables(
+    state: &State,
+    extractions: &[definition::ResponseExtraction],
+    actual: &ResponseResultData,
+) {
+    for v in extractions {
+        match extract_variable(v, actual) {
+            Ok(value) => {
+                debug!("extracting variable: {} = {}", v.name, value);
+                state.insert_variable(v.name.clone(), value);
+            }
+            Err(error) => {
+                error!(
+                    "unable to extract {} from {}: {}",
+                    v.name,
+                    v.describe(),
+                    error
+                );
+            }
+        }
+    }
 }
 
-fn http_request_from_test_spec(
+//Headers are resolved against the state variables right before sending, so the
+//request recorded in the results holds the values sent
+fn resolve_headers(
     state: &State,
     resolved_request: test::definition::ResolvedRequest,
-) -> Result<Request<Full<Bytes>>, Box<dyn Error + Send + Sync>> {
-    let vars: Vec<(String, &String)> = state
-        .variables
+) -> test::definition::ResolvedRequest {
+    let state_variables = state.variables();
+    let vars: Vec<(String, String)> = state_variables
         .iter()
-        .map(|(k, v)| (format!("${{{}}}", k), v))
+        .map(|(k, v)| (format!("${{{}}}", k), test::variable_text(v)))
         .collect();
 
     //Where all can we resolve variables? May be worth making an external function
     let variable_resolver = |variable: String| -> String {
-        vars.iter().fold(variable, |acc, (var_name, var_value)| {
-            acc.replace(var_name, var_value)
+        let replaced = vars.iter().fold(variable, |acc, (var_name, var_value)| {
+            test::expression::replace_placeholder(&acc, var_name, var_value)
+        });
+        test::expression::resolve_expressions(&replaced, &|name| {
+            state_variables.get(name).map(test::variable_text)
         })
     };
 
+    let headers = resolved_request
+        .headers
+        .iter()
+        .map(|(k, v)| (k.clone(), variable_resolver(v.clone())))
+        .collect();
+    test::definition::ResolvedRequest {
+        headers,
+        ..resolved_request
+    }
+}
+
+//The request as it is sent to the server, with escaped placeholders as literal text
+fn request_details(resolved_request: &test::definition::ResolvedRequest) -> RequestDetails {
+    let sent = resolved_request.clone().unescape_placeholders();
+    RequestDetails {
+        headers: sent
+            .headers
+            .into_iter()
+            .map(|(header, value)| http::Header::new(header, value))
+            .collect(),
+        url: sent.url,
+        method: sent.method,
+        body: request_body_details(sent.body, sent.payload),
+    }
+}
+
+async fn http_request_from_test_spec(
+    state: &State,
+    resolved_request: test::definition::ResolvedRequest,
+) -> Result<Request<Full<Bytes>>, Box<dyn Error + Send + Sync>> {
+    let unresolved = resolved_request.unresolved_variables();
+    if !unresolved.is_empty() {
+        return Err(Box::new(UnresolvedVariableError {
+            variables: unresolved,
+        }));
+    }
+
+    let resolved_request = resolved_request.unescape_placeholders();
+
     let (tld_prefix, is_secure) = if resolved_request.url.starts_with("http://") {
         (resolved_request.url[7..].to_string().to_lowercase(), false)
     } else if resolved_request.url.starts_with("https://") {
@@ -1880,7 +3291,10 @@ fn http_request_from_test_spec(
     };
 
     let cookies = state
+        .session
         .cookies
+        .lock()
+        .unwrap()
         .iter()
         .filter(|(k, _)| tld_prefix.starts_with(&k.to_lowercase()))
         .flat_map(|(_, v)| {
@@ -1902,34 +3316,59 @@ fn http_request_from_test_spec(
 
     debug!("matched cookies: {:?}", cookies);
 
-    let maybe_body = resolved_request
-        .body
-        .as_ref()
-        .map(|b| serde_json::to_string(&b).unwrap());
+    let (content_type, maybe_body) = match &resolved_request.payload {
+        Some(payload) => payload
+            .encode()
+            .await
+            .map(|(content_type, data)| (content_type, Some(data)))?,
+        None => (
+            "application/json".to_string(),
+            resolved_request
+                .body
+                .as_ref()
+                .map(|b| serde_json::to_vec(&b).unwrap()),
+        ),
+    };
+
+    //an explicitly provided content type wins over the one implied by the body
+    let has_content_type = resolved_request
+        .headers
+        .iter()
+        .any(|(k, _)| k.eq_ignore_ascii_case("content-type"));
 
     Url::parse(&resolved_request.url)
         .map_err(|e| Box::<dyn Error + Send + Sync>::from(format!("invalid request url: {}", e)))
         .and_then(|url| {
-            let builder = Request::builder()
+            let mut builder = Request::builder()
                 .uri(url.as_str())
                 .method(resolved_request.method.to_hyper())
-                .header("Content-Type", HeaderValue::from_static("application/json"))
                 .header(
                     "Content-Length",
                     HeaderValue::from(maybe_body.as_ref().map(|s| s.len()).unwrap_or_default()),
                 );
 
+            if !has_content_type {
+                builder = builder.header("Content-Type", content_type);
+            }
+
             cookies
                 .iter()
                 .chain(resolved_request.headers.iter())
-                .fold(builder, |builder, (k, v)| {
-                    builder.header(k, variable_resolver(v.clone()))
-                })
+                .fold(builder, |builder, (k, v)| builder.header(k, v))
                 .body(maybe_body.map(|b| b.into()).unwrap_or_default())
                 .map_err(|e| Box::from(format!("bad request result: {}", e)))
         })
 }
 
+//Non-JSON payloads are reported using their JSON representation
+fn request_body_details(
+    body: Option<serde_json::Value>,
+    payload: Option<definition::RequestPayload>,
+) -> serde_json::Value {
+    body.or(payload.and_then(|p| serde_json::to_value(p).ok()))
+        .unwrap_or(serde_json::Value::Null)
+}
+
 pub fn get_rustls_config_dangerous() -> Result<ClientConfig, Box<dyn Error + Send + Sync>> {
     let config = ClientConfig::builder()
         .dangerous() // The `Verifier` we're using is actually safe
@@ -1939,11 +3378,10 @@ pub fn get_rustls_config_dangerous() -> Result<ClientConfig, Box<dyn Error + Sen
     Ok(config)
 }
 
-async fn process_request(
-    state: &mut State,
-    resolved_request: test::definition::ResolvedRequest,
-) -> Result<hyper::Response<Incoming>, Box<dyn Error + Send + Sync>> {
-    let connection = if state.bypass_cert_verification {
+fn build_http_client(
+    settings: &config::Settings,
+) -> Result<HttpClient, Box<dyn Error + Send + Sync>> {
+    let connection = if settings.bypass_cert_verification {
         debug!("WARNING: bypassing cert verification");
         HttpsConnectorBuilder::new()
             .with_tls_config(get_rustls_config_dangerous()?)
@@ -1958,76 +3396,395 @@ async fn process_request(
             .build()
     };
 
-    let client = Client::builder(TokioExecutor::new()).build(connection);
-    debug!("url({})", resolved_request.url);
+    let mut builder = Client::builder(TokioExecutor::new());
+    builder.pool_idle_timeout(Duration::from_secs(settings.pool_idle_timeout));
+    if let Some(max_idle) = settings.pool_max_idle_per_host {
+        builder.pool_max_idle_per_host(max_idle as usize);
+    }
 
-    match http_request_from_test_spec(state, resolved_request) {
-        Ok(req) => {
-            debug!("sending request: {req:?}");
-            let response = client.request(req).await?;
+    Ok(builder.build(connection))
+}
 
-            let cookies = response.headers().get_all("Set-Cookie");
-            for c in cookies.iter() {
-                let cookie_raw = StateCookie::new(c.to_str().unwrap().to_string());
-                if let Some(cookie) = cookie_raw {
-                    let cookie_fullpath: String = format!("{}{}", cookie.domain, cookie.path);
+fn deadline_after(timeout: Option<u64>) -> Option<Instant> {
+    timeout.map(|t| Instant::now() + Duration::from_millis(t))
+}
 
-                    debug!("cookie in response: {}", &cookie_fullpath);
+fn earliest_deadline(lhs: Option<Instant>, rhs: Option<Instant>) -> Option<Instant> {
+    match (lhs, rhs) {
+        (Some(l), Some(r)) => Some(l.min(r)),
+        (l, r) => l.or(r),
+    }
+}
 
-                    if !state.cookies.contains_key(&cookie_fullpath) {
-                        state
-                            .cookies
-                            .insert(cookie_fullpath.clone(), HashMap::new());
-                    }
+//Sends the request and reads its response, returning the request runtime in milliseconds
+//alongside it. Returns None when the deadline passes first
+async fn send_request(
+    state: &State,
+    resolved_request: test::definition::ResolvedRequest,
+    deadline: Option<Instant>,
+) -> Result<Option<(u32, Option<ResponseResultData>)>, Box<dyn Error + Send + Sync>> {
+    let exchange = async {
+        // waiting for a connection to the host counts towards the deadline, not the runtime
+        let _permit = state.session.host_permit(&resolved_request.url).await;
+        let start_time = Instant::now();
+        let response = process_request(state, resolved_request).await?;
+        let runtime = start_time.elapsed().as_millis() as u32;
+        let response = ResponseResultData::from_response(response).await?;
+        Ok((runtime, Some(response)))
+    };
 
-                    let sub_map = state.cookies.get_mut(&cookie_fullpath).unwrap();
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), exchange)
+            .await
+            .ok()
+            .transpose(),
+        None => exchange.await.map(Some),
+    }
+}
 
-                    if !sub_map.contains_key(&cookie.key) {
-                        sub_map.insert(cookie.key.clone(), cookie);
-                    } else {
-                        sub_map.get_mut(&cookie.key).unwrap().update(cookie);
-                    }
-                }
-            }
-            Ok(response)
-        }
-        Err(error) => Err(Box::from(format!("bad request result: {}", error))),
+fn unanswered_stage_result(
+    stage: u32,
+    stage_type: StageType,
+    stage_name: Option<String>,
+    td: &test::Definition,
+    details: ResultDetails,
+    reason: String,
+) -> StageResult {
+    StageResult {
+        stage,
+        stage_type,
+        stage_name,
+        total_runtime: details.request_runtime,
+        status: TestStatus::Failed,
+        details,
+        validation: Validated::fail(reason),
+        project: td.project.clone(),
+        environment: td.environment.clone(),
+        timeout_reason: None,
+        previous_attempts: Vec::new(),
     }
 }
 
-fn validate_dry_run(
-    state: &State,
+//A stage whose request was cut short, by its own timeout or by the test's deadline
+#[allow(clippy::too_many_arguments)]
+fn timed_out_stage_result(
+    stage: u32,
+    stage_type: StageType,
+    stage_name: Option<String>,
     td: &test::Definition,
-    iteration: u32,
-) -> Result<bool, Box<dyn Error + Send + Sync>> {
-    // construct request block
+    request: RequestDetails,
+    expected: ExpectedResultData,
+    start_time: Instant,
+    test_deadline: Option<Instant>,
+) -> StageResult {
+    let runtime = start_time.elapsed().as_millis() as u32;
+    let reason = if test_deadline.is_some_and(|d| Instant::now() >= d) {
+        test_timeout_reason(td)
+    } else {
+        format!("Request timed out after {} milliseconds", runtime)
+    };
+    let details = ResultDetails {
+        request,
+        request_runtime: runtime,
+        expected,
+        actual: None,
+        compare_request: None,
+        compare_request_runtime: None,
+        compare_actual: None,
+    };
 
-    if let Some(setup) = &td.setup {
-        let setup_method = setup.request.method.as_method();
-        let setup_url = &td.get_url(
-            iteration,
-            &setup.request.url,
-            &setup.request.params,
-            &state.variables,
-            &td.variables,
-        );
-        let setup_headers = td.get_setup_request_headers(iteration);
-        let setup_body = td.get_request_body(
-            &setup.request.body,
-            &state.variables,
-            &td.variables,
-            iteration,
-        );
-        info!("setup: {} {}\n", setup_method, setup_url);
-        if !setup_headers.is_empty() {
-            info!("setup_headers:\n");
-            for (key, value) in setup_headers.iter() {
-                info!("-- {}: {}\n", key, value);
-            }
-        }
+    StageResult {
+        timeout_reason: Some(reason.clone()),
+        ..unanswered_stage_result(stage, stage_type, stage_name, td, details, reason)
+    }
+}
 
-        if let Some(body) = setup_body {
-            info!("setup_body: {}\n", body);
+fn test_timeout_reason(td: &test::Definition) -> String {
+    format!(
+        "Test timed out after {} milliseconds",
+        td.timeout.unwrap_or_default()
+    )
+}
+
+//Fails a stage which the test's deadline cut short while it was waiting to retry,
+//poll again or continue after its delay. Its own failures, if any, are kept
+fn test_timed_out(result: StageResult, td: &test::Definition) -> StageResult {
+    let reason = test_timeout_reason(td);
+    let mut failures = vec![reason.clone()];
+    if let validated::Validated::Fail(nec) = result.validation {
+        failures.extend(nec);
+    }
+
+    StageResult {
+        status: TestStatus::Failed,
+        validation: failures
+            .into_iter()
+            .map(Validated::fail)
+            .collect::<Validated<Vec<()>, String>>(),
+        timeout_reason: Some(reason),
+        ..result
+    }
+}
+
+//Whether waiting `delay` milliseconds from now would pass the deadline
+fn outlasts(delay: u64, deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|d| Instant::now() + Duration::from_millis(delay) >= d)
+}
+
+//A stage request which couldn't be sent or answered, along with the request as it
+//was resolved so the failed stage reports what was actually attempted. Headers are
+//kept as pairs, as http::Header can't be shared between threads
+#[derive(Debug)]
+struct StageRequestError {
+    url: String,
+    method: http::Method,
+    headers: Vec<(String, String)>,
+    body: serde_json::Value,
+    error: Box<dyn Error + Send + Sync>,
+}
+
+impl Error for StageRequestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+impl fmt::Display for StageRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl StageRequestError {
+    fn new(request: &RequestDetails, error: Box<dyn Error + Send + Sync>) -> StageRequestError {
+        StageRequestError {
+            url: request.url.clone(),
+            method: request.method.clone(),
+            headers: request
+                .headers
+                .iter()
+                .map(|h| (h.header.clone(), h.value.clone()))
+                .collect(),
+            body: request.body.clone(),
+            error,
+        }
+    }
+
+    //The request to report for a failed attempt and the error behind it. Errors raised
+    //before the request was resolved report the stage's request as written
+    fn split(
+        error: Box<dyn Error + Send + Sync>,
+        stage: &definition::StageDescriptor,
+    ) -> (RequestDetails, Box<dyn Error + Send + Sync>) {
+        match error.downcast::<StageRequestError>() {
+            Ok(e) => (
+                RequestDetails {
+                    headers: e
+                        .headers
+                        .into_iter()
+                        .map(|(header, value)| http::Header::new(header, value))
+                        .collect(),
+                    url: e.url,
+                    method: e.method,
+                    body: e.body,
+                },
+                e.error,
+            ),
+            Err(error) => (
+                RequestDetails {
+                    headers: stage.request.headers.clone(),
+                    url: stage.request.url.clone(),
+                    method: stage.request.method.as_method(),
+                    body: serde_json::Value::Null,
+                },
+                error,
+            ),
+        }
+    }
+}
+
+//A stage attempt whose request couldn't be sent or answered, e.g. a refused connection
+fn errored_stage_result(
+    stage: u32,
+    stage_type: StageType,
+    stage_name: Option<String>,
+    td: &test::Definition,
+    request: RequestDetails,
+    error: &(dyn Error + Send + Sync + 'static),
+) -> StageResult {
+    let details = ResultDetails {
+        request,
+        request_runtime: 0,
+        expected: ExpectedResultData::new(),
+        actual: None,
+        compare_request: None,
+        compare_request_runtime: None,
+        compare_actual: None,
+    };
+
+    unanswered_stage_result(
+        stage,
+        stage_type,
+        stage_name,
+        td,
+        details,
+        if error.is::<UnresolvedVariableError>() {
+            error.to_string()
+        } else {
+            format!("Request failed: {}", error)
+        },
+    )
+}
+
+//A failed stage is retried when the policy has no conditions, when its status is listed,
+//or when no response was received and connection errors are retried
+fn should_retry_stage(retry: &definition::RetryDescriptor, result: &StageResult) -> bool {
+    if result.status != TestStatus::Failed {
+        return false;
+    }
+
+    if retry.retries_any_failure() {
+        return true;
+    }
+
+    match &result.details.actual {
+        Some(actual) => retry.on_status.contains(&actual.status),
+        None => retry.on_connection_error,
+    }
+}
+
+fn should_retry_iteration(
+    retry: &definition::RetryDescriptor,
+    result: &Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>>,
+) -> bool {
+    match result {
+        Err(_) => retry.retries_any_failure() || retry.on_connection_error,
+        Ok((true, _)) => false,
+        Ok((false, stage_results)) => {
+            retry.retries_any_failure()
+                || stage_results.iter().any(|r| should_retry_stage(retry, r))
+        }
+    }
+}
+
+async fn process_request(
+    state: &State,
+    resolved_request: test::definition::ResolvedRequest,
+) -> Result<hyper::Response<Incoming>, Box<dyn Error + Send + Sync>> {
+    debug!("url({})", resolved_request.url);
+
+    // fails without sending anything when placeholders are left unresolved
+    let req = http_request_from_test_spec(state, resolved_request).await?;
+    debug!("sending request: {req:?}");
+    let client = state
+        .session
+        .client
+        .as_ref()
+        .ok_or("no HTTP client, dry runs don't send requests")?;
+    let response = client.request(req).await?;
+
+    let cookies = response.headers().get_all("Set-Cookie");
+    let mut state_cookies = state.session.cookies.lock().unwrap();
+    for c in cookies.iter() {
+        let cookie_raw = StateCookie::new(c.to_str().unwrap().to_string());
+        if let Some(cookie) = cookie_raw {
+            let cookie_fullpath: String = format!("{}{}", cookie.domain, cookie.path);
+
+            debug!("cookie in response: {}", &cookie_fullpath);
+
+            if !state_cookies.contains_key(&cookie_fullpath) {
+                state_cookies.insert(cookie_fullpath.clone(), HashMap::new());
+            }
+
+            let sub_map = state_cookies.get_mut(&cookie_fullpath).unwrap();
+
+            if !sub_map.contains_key(&cookie.key) {
+                sub_map.insert(cookie.key.clone(), cookie);
+            } else {
+                sub_map.get_mut(&cookie.key).unwrap().update(cookie);
+            }
+        }
+    }
+    drop(state_cookies);
+    Ok(response)
+}
+
+fn print_dry_run_payload(prefix: &str, payload: &definition::RequestPayload) {
+    let describe = |source: &definition::PayloadSource| match source {
+        definition::PayloadSource::Value(v) => v.clone(),
+        definition::PayloadSource::File(f) => format!("contents of file {}", f),
+    };
+
+    match payload {
+        definition::RequestPayload::Form(fields) => {
+            info!("{}form:\n", prefix);
+            for f in fields {
+                info!("-- {}: {}\n", f.name, f.value);
+            }
+        }
+        definition::RequestPayload::Multipart(parts) => {
+            info!("{}multipart:\n", prefix);
+            for p in parts {
+                match &p.content_type {
+                    Some(ct) => info!("-- {} ({}): {}\n", p.name, ct, describe(&p.source)),
+                    None => info!("-- {}: {}\n", p.name, describe(&p.source)),
+                }
+            }
+        }
+        definition::RequestPayload::Raw(raw) => {
+            info!(
+                "{}raw_body ({}): {}\n",
+                prefix,
+                raw.content_type,
+                describe(&raw.source)
+            );
+        }
+    }
+}
+
+fn validate_dry_run(
+    state: &State,
+    td: &test::Definition,
+    iteration: u32,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let state_variables = state.variables();
+    // construct request block
+
+    if let Some(setup) = &td.setup {
+        let setup_method = setup.request.method.as_method();
+        let setup_url = &td.get_url(
+            iteration,
+            &setup.request.url,
+            &setup.request.params,
+            &state_variables,
+            &td.variables,
+        );
+        let setup_headers = td.get_setup_request_headers(iteration);
+        let setup_body = td.get_request_body(
+            &setup.request.body,
+            &state_variables,
+            &td.variables,
+            iteration,
+        );
+        info!("setup: {} {}\n", setup_method, setup_url);
+        if !setup_headers.is_empty() {
+            info!("setup_headers:\n");
+            for (key, value) in setup_headers.iter() {
+                info!("-- {}: {}\n", key, value);
+            }
+        }
+
+        if let Some(body) = setup_body {
+            info!("setup_body: {}\n", body);
+        }
+
+        if let Some(payload) = td.get_request_payload(
+            &setup.request.payload,
+            &state_variables,
+            &td.variables,
+            iteration,
+        ) {
+            print_dry_run_payload("setup_", &payload);
         }
 
         if let Some(r) = &setup.response {
@@ -2039,10 +3796,23 @@ fn validate_dry_run(
                 );
             }
 
+            for h in &r.headers {
+                match (&h.specification, h.absent) {
+                    (_, true) => info!("validate setup_response_header {} is absent\n", h.header),
+                    (Some(spec), _) => info!(
+                        "validate setup_response_header {} with defined specification: {}\n",
+                        h.header,
+                        serde_json::to_string(spec).unwrap_or_default()
+                    ),
+                    (None, _) => info!("validate setup_response_header {} is present\n", h.header),
+                }
+            }
+
             for v in &r.extract {
                 info!(
                     "attempt to extract value from response: {} = valueOf({})\n",
-                    v.name, v.field
+                    v.name,
+                    v.describe()
                 );
             }
 
@@ -2066,6 +3836,17 @@ fn validate_dry_run(
                     );
                 }
             }
+
+            if let Some(c) = &r.content {
+                info!(
+                    "validate setup_response_content with defined specification: {}\n",
+                    serde_json::to_string(c).unwrap_or_default()
+                );
+            }
+
+            for a in &r.assertions {
+                info!("validate setup_response_body assertion: {}\n", a.describe());
+            }
         }
     }
 
@@ -2075,13 +3856,13 @@ fn validate_dry_run(
             iteration,
             &stage.request.url,
             &stage.request.params,
-            &state.variables,
+            &state_variables,
             &[&stage.variables[..], &td.variables[..]].concat(),
         );
         let stage_headers = td.get_headers(&stage.request.headers, iteration);
         let stage_body = td.get_request_body(
             &stage.request.body,
-            &state.variables,
+            &state_variables,
             &[&stage.variables[..], &td.variables[..]].concat(),
             iteration,
         );
@@ -2102,6 +3883,15 @@ fn validate_dry_run(
             info!("body: {}\n", body);
         }
 
+        if let Some(payload) = td.get_request_payload(
+            &stage.request.payload,
+            &state_variables,
+            &[&stage.variables[..], &td.variables[..]].concat(),
+            iteration,
+        ) {
+            print_dry_run_payload("", &payload);
+        }
+
         if let Some(r) = &stage.response {
             // compare to response definition
             if let Some(stage_response_status) = &r.status {
@@ -2111,10 +3901,23 @@ fn validate_dry_run(
                 );
             }
 
+            for h in &r.headers {
+                match (&h.specification, h.absent) {
+                    (_, true) => info!("validate response_header {} is absent\n", h.header),
+                    (Some(spec), _) => info!(
+                        "validate response_header {} with defined specification: {}\n",
+                        h.header,
+                        serde_json::to_string(spec).unwrap_or_default()
+                    ),
+                    (None, _) => info!("validate response_header {} is present\n", h.header),
+                }
+            }
+
             for v in &r.extract {
                 info!(
                     "attempt to extract value from response: {} = valueOf({})\n",
-                    v.name, v.field
+                    v.name,
+                    v.describe()
                 );
             }
 
@@ -2138,6 +3941,26 @@ fn validate_dry_run(
                     );
                 }
             }
+
+            if let Some(c) = &r.content {
+                info!(
+                    "validate response_content with defined specification: {}\n",
+                    serde_json::to_string(c).unwrap_or_default()
+                );
+            }
+
+            for a in &r.assertions {
+                info!("validate response_body assertion: {}\n", a.describe());
+            }
+        }
+
+        if let Some(poll) = &stage.poll {
+            info!(
+                "poll every {}ms for up to {}ms until response matches: {}\n",
+                poll.interval,
+                poll.max_duration,
+                serde_json::to_string(&poll.until).unwrap_or_default()
+            );
         }
 
         if let Some(stage_compare) = &stage.compare {
@@ -2148,7 +3971,7 @@ fn validate_dry_run(
                 iteration,
                 &stage_compare.url,
                 &params,
-                &state.variables,
+                &state_variables,
                 &[&stage.variables[..], &td.variables[..]].concat(),
             );
 
@@ -2165,9 +3988,13 @@ fn validate_dry_run(
             for header in td.get_stage_compare_headers(stage_index, iteration) {
                 let mut header_value: String = header.1;
 
-                for gv in state.variables.iter() {
+                for gv in state_variables.iter() {
                     let key_search = format!("${{{}}}", gv.0);
-                    header_value = header_value.replace(&key_search, gv.1);
+                    header_value = test::expression::replace_placeholder(
+                        &header_value,
+                        &key_search,
+                        &test::variable_text(gv.1),
+                    );
                 }
 
                 stage_compare_headers.insert(header.0, header_value);
@@ -2230,12 +4057,12 @@ fn validate_dry_run(
             iteration,
             &onsuccess.url,
             &onsuccess.params,
-            &state.variables,
+            &state_variables,
             &td.variables,
         );
         let onsuccess_headers = td.get_setup_request_headers(iteration);
         let onsuccess_body =
-            td.get_request_body(&onsuccess.body, &state.variables, &td.variables, iteration);
+            td.get_request_body(&onsuccess.body, &state_variables, &td.variables, iteration);
         info!("onsuccess: {} {}\n", onsuccess_method, onsuccess_url);
         if !onsuccess_headers.is_empty() {
             info!("onsuccess_headers:\n");
@@ -2247,6 +4074,15 @@ fn validate_dry_run(
         if let Some(body) = onsuccess_body {
             info!("onsuccess_body: {}\n", body);
         }
+
+        if let Some(payload) = td.get_request_payload(
+            &onsuccess.payload,
+            &state_variables,
+            &td.variables,
+            iteration,
+        ) {
+            print_dry_run_payload("onsuccess_", &payload);
+        }
     }
 
     if let Some(onfailure) = &td.cleanup.onfailure {
@@ -2256,12 +4092,12 @@ fn validate_dry_run(
             iteration,
             &onfailure.url,
             &onfailure.params,
-            &state.variables,
+            &state_variables,
             &td.variables,
         );
         let onfailure_headers = td.get_setup_request_headers(iteration);
         let onfailure_body =
-            td.get_request_body(&onfailure.body, &state.variables, &td.variables, iteration);
+            td.get_request_body(&onfailure.body, &state_variables, &td.variables, iteration);
         info!("onfailure: {} {}\n", onfailure_method, onfailure_url);
         if !onfailure_headers.is_empty() {
             info!("onfailure_headers:\n");
@@ -2273,6 +4109,15 @@ fn validate_dry_run(
         if let Some(body) = onfailure_body {
             info!("onfailure_body: {}\n", body);
         }
+
+        if let Some(payload) = td.get_request_payload(
+            &onfailure.payload,
+            &state_variables,
+            &td.variables,
+            iteration,
+        ) {
+            print_dry_run_payload("onfailure_", &payload);
+        }
     }
 
     if let Some(request) = &td.cleanup.always {
@@ -2282,12 +4127,12 @@ fn validate_dry_run(
             iteration,
             &request.url,
             &request.params,
-            &state.variables,
+            &state_variables,
             &td.variables,
         );
         let cleanup_headers = td.get_setup_request_headers(iteration);
         let cleanup_body =
-            td.get_request_body(&request.body, &state.variables, &td.variables, iteration);
+            td.get_request_body(&request.body, &state_variables, &td.variables, iteration);
         info!("cleanup: {} {}\n", cleanup_method, cleanup_url);
         if !cleanup_headers.is_empty() {
             info!("cleanup_headers:\n");
@@ -2299,6 +4144,12 @@ fn validate_dry_run(
         if let Some(body) = cleanup_body {
             info!("cleanup_body: {}\n", body);
         }
+
+        if let Some(payload) =
+            td.get_request_payload(&request.payload, &state_variables, &td.variables, iteration)
+        {
+            print_dry_run_payload("cleanup_", &payload);
+        }
     }
 
     Ok(true)
@@ -2345,6 +4196,9 @@ mod tests {
                 actual: Option::from(ResponseResultData {
                     body: serde_json::Value::default(),
                     status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
                     headers: Vec::default(),
                 }),
                 compare_request: Some(RequestDetails {
@@ -2359,6 +4213,9 @@ mod tests {
                         "Name" : "Bob"
                     }),
                     status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
                     headers: Vec::default(),
                 }),
             },
@@ -2368,8 +4225,8 @@ mod tests {
         );
         assert_eq!(actual.status, TestStatus::Failed);
         assert_eq!(actual.validation, Validated::Fail(nev![
-            String::from("Expected body {\"Name\":\"Bob\"} did not match actual body null ; json atoms at path \"(root)\" are not equal:\n    lhs:\n        null\n    rhs:\n        {\n          \"Name\": \"Bob\"\n        }"),
-            String::from("Expected compare body {\"Name\":\"Bob\"} did not match actual body null ; json atoms at path \"(root)\" are not equal:\n    lhs:\n        null\n    rhs:\n        {\n          \"Name\": \"Bob\"\n        }")
+            String::from("Expected body did not match actual body, 1 difference(s) (- expected but missing, + unexpected, ~ expected → actual):\n  ~ $: {\"Name\":\"Bob\"} → null"),
+            String::from("Expected compare body did not match actual body, 1 difference(s) (- expected but missing, + unexpected, ~ expected → actual):\n  ~ $: {\"Name\":\"Bob\"} → null")
         ]));
     }
 
@@ -2413,7 +4270,174 @@ mod tests {
         );
     }
 
-    //note : no test for headers, we don't currently support it
+    fn header_descriptor(
+        header: &str,
+        specification: Option<Specification<String>>,
+        absent: bool,
+    ) -> definition::ResponseHeaderDescriptor {
+        definition::ResponseHeaderDescriptor {
+            header: header.to_string(),
+            specification: specification.map(|s| StringSpecification {
+                specification: Some(s),
+                ..Default::default()
+            }),
+            absent,
+        }
+    }
+
+    fn process_response_with_headers(
+        expected_headers: Vec<definition::ResponseHeaderDescriptor>,
+        actual_headers: Vec<Header>,
+        compare_headers: Option<Vec<Header>>,
+    ) -> StageResult {
+        let ignore_body: [String; 0] = [];
+        process_response(
+            0,
+            StageType::Normal,
+            None,
+            ResultDetails {
+                request: RequestDetails {
+                    body: serde_json::Value::default(),
+                    headers: Vec::default(),
+                    method: http::Verb::Get.as_method(),
+                    url: "".to_string(),
+                },
+                request_runtime: 100,
+                compare_request_runtime: None,
+                expected: ExpectedResultData {
+                    headers: expected_headers,
+                    ..ExpectedResultData::new()
+                },
+                actual: Some(ResponseResultData {
+                    body: serde_json::Value::default(),
+                    status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
+                    headers: actual_headers,
+                }),
+                compare_request: None,
+                compare_actual: compare_headers.map(|headers| ResponseResultData {
+                    body: serde_json::Value::default(),
+                    status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
+                    headers,
+                }),
+            },
+            &ignore_body,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn process_response_header_matches() {
+        let actual = process_response_with_headers(
+            vec![
+                header_descriptor(
+                    "content-type",
+                    Some(Specification::Value("application/json".to_string())),
+                    false,
+                ),
+                header_descriptor(
+                    "Cache-Control",
+                    Some(Specification::OneOf(vec![
+                        "no-cache".to_string(),
+                        "no-store".to_string(),
+                    ])),
+                    false,
+                ),
+                header_descriptor(
+                    "set-cookie",
+                    Some(Specification::Value("b=2".to_string())),
+                    false,
+                ),
+                header_descriptor("X-Request-Id", None, false),
+                header_descriptor("Server", None, true),
+            ],
+            vec![
+                Header::new("Content-Type".to_string(), "application/json".to_string()),
+                Header::new("cache-control".to_string(), "no-store".to_string()),
+                Header::new("set-cookie".to_string(), "a=1".to_string()),
+                Header::new("set-cookie".to_string(), "b=2".to_string()),
+                Header::new("x-request-id".to_string(), "1234".to_string()),
+            ],
+            None,
+        );
+
+        assert_eq!(actual.status, TestStatus::Passed);
+    }
+
+    #[test]
+    fn process_response_header_mismatches() {
+        let actual = process_response_with_headers(
+            vec![
+                header_descriptor(
+                    "Content-Type",
+                    Some(Specification::Value("application/json".to_string())),
+                    false,
+                ),
+                header_descriptor(
+                    "Cache-Control",
+                    Some(Specification::NoneOf(vec!["no-store".to_string()])),
+                    false,
+                ),
+                header_descriptor("X-Request-Id", None, false),
+                header_descriptor("Server", None, true),
+            ],
+            vec![
+                Header::new("content-type".to_string(), "text/html".to_string()),
+                Header::new("cache-control".to_string(), "no-store".to_string()),
+                Header::new("server".to_string(), "nginx".to_string()),
+            ],
+            None,
+        );
+
+        assert_eq!(actual.status, TestStatus::Failed);
+        assert_eq!(
+            actual.validation,
+            Validated::Fail(nev![
+                String::from(
+                    "Expected header Content-Type \"application/json\" but received \"text/html\""
+                ),
+                String::from(
+                    "Expected header Cache-Control none of [\"no-store\"] but received \"no-store\""
+                ),
+                String::from("Expected header X-Request-Id but received none"),
+                String::from("Expected header Server to be absent but received \"nginx\"")
+            ])
+        );
+    }
+
+    #[test]
+    fn process_response_compare_header_mismatch() {
+        let actual = process_response_with_headers(
+            vec![
+                header_descriptor("Content-Type", None, false),
+                header_descriptor("X-Version", None, false),
+            ],
+            vec![
+                Header::new("content-type".to_string(), "application/json".to_string()),
+                Header::new("x-version".to_string(), "2".to_string()),
+                Header::new("date".to_string(), "today".to_string()),
+            ],
+            Some(vec![
+                Header::new("Content-Type".to_string(), "application/json".to_string()),
+                Header::new("date".to_string(), "yesterday".to_string()),
+            ]),
+        );
+
+        assert_eq!(actual.status, TestStatus::Failed);
+        assert_eq!(
+            actual.validation,
+            Validated::fail(
+                "Expected compare header X-Version to be absent but received \"2\"".to_string()
+            )
+        );
+    }
+
     #[test]
     fn process_response_body_mismatch() {
         let expected = ExpectedResultData {
@@ -2444,6 +4468,9 @@ mod tests {
                     body: serde_json::Value::default(),
                     headers: Vec::default(),
                     status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
                 }),
                 compare_request: None,
                 compare_actual: None,
@@ -2454,7 +4481,7 @@ mod tests {
         );
         assert_eq!(actual.status, TestStatus::Failed);
         assert_eq!(actual.validation, Validated::fail(
-            String::from("Expected body {\"Name\":\"Bob\"} did not match actual body null ; json atoms at path \"(root)\" are not equal:\n    lhs:\n        null\n    rhs:\n        {\n          \"Name\": \"Bob\"\n        }"
+            String::from("Expected body did not match actual body, 1 difference(s) (- expected but missing, + unexpected, ~ expected → actual):\n  ~ $: {\"Name\":\"Bob\"} → null"
         )));
     }
 
@@ -2485,6 +4512,9 @@ mod tests {
                 request_runtime: 100,
                 actual: Some(ResponseResultData {
                     status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
                     body: json!({
                         "Name": "Bob"
                     }),
@@ -2503,20 +4533,33 @@ mod tests {
     }
 
     #[test]
-    fn process_response_status_match() {
-        let expected = ExpectedResultData {
-            status: Some(ValueOrNumericSpecification::Schema(NumericSpecification {
-                specification: Some(Specification::OneOf(vec![200, 201, 202])),
-                min: None,
-                max: None,
-            })),
-            body: None,
-            headers: Vec::default(),
-            ..ExpectedResultData::new()
-        };
+    fn response_result_data_keeps_non_json_bodies() {
+        let text = ResponseResultData::new(
+            200,
+            Vec::default(),
+            Some("text/plain".to_string()),
+            b"hello".to_vec(),
+        );
+        assert!(!text.is_json);
+        assert_eq!(text.body, serde_json::Value::Null);
+        assert_eq!(text.raw_body, b"hello".to_vec());
+
+        let json = ResponseResultData::new(200, Vec::default(), None, br#"{"a":1}"#.to_vec());
+        assert!(json.is_json);
+        assert_eq!(json.body, json!({"a": 1}));
+    }
+
+    fn process_text_response(expected: ExpectedResultData, body: &str) -> StageResult {
+        process_body_response(expected, "text/html", body)
+    }
 
+    fn process_body_response(
+        expected: ExpectedResultData,
+        content_type: &str,
+        body: &str,
+    ) -> StageResult {
         let ignore_body: [String; 0] = [];
-        let actual = process_response(
+        process_response(
             0,
             StageType::Normal,
             None,
@@ -2524,16 +4567,17 @@ mod tests {
                 request: RequestDetails {
                     body: serde_json::Value::default(),
                     headers: Vec::default(),
-                    method: http::Verb::Post.as_method(),
+                    method: http::Verb::Get.as_method(),
                     url: "".to_string(),
                 },
+                expected,
                 request_runtime: 100,
-                expected: expected.clone(),
-                actual: Some(ResponseResultData {
-                    body: serde_json::Value::default(),
-                    headers: Vec::default(),
-                    status: 200,
-                }),
+                actual: Some(ResponseResultData::new(
+                    200,
+                    Vec::default(),
+                    Some(content_type.to_string()),
+                    body.as_bytes().to_vec(),
+                )),
                 compare_request: None,
                 compare_request_runtime: None,
                 compare_actual: None,
@@ -2541,9 +4585,149 @@ mod tests {
             &ignore_body,
             None,
             None,
-        );
-        assert_eq!(actual.status, TestStatus::Passed);
-        assert!(actual.validation.is_good());
+        )
+    }
+
+    #[test]
+    fn process_response_content_match() {
+        let expected = ExpectedResultData {
+            content: Some(ContentSpecification {
+                contains: Some("<title>".to_string()),
+                pattern: Some("^<html>.*</html>$".to_string()),
+                byte_length: Some(ValueOrNumericSpecification::Value(35)),
+                ..Default::default()
+            }),
+            ..ExpectedResultData::new()
+        };
+
+        let actual = process_text_response(expected, "<html><title>hi</title></html>");
+        assert_eq!(actual.status, TestStatus::Failed);
+        assert_eq!(
+            actual.validation,
+            Validated::Fail(nev![
+                "Expected body byte length 35 but received 30".to_string()
+            ])
+        );
+
+        let expected = ExpectedResultData {
+            content: Some(ContentSpecification {
+                text: Some("<html><title>hi</title></html>".to_string()),
+                byte_length: Some(ValueOrNumericSpecification::Value(30)),
+                ..Default::default()
+            }),
+            ..ExpectedResultData::new()
+        };
+        let actual = process_text_response(expected, "<html><title>hi</title></html>");
+        assert_eq!(actual.status, TestStatus::Passed);
+    }
+
+    #[test]
+    fn process_response_assertions_report_each_failure() {
+        let assertions: Vec<test::file::UnvalidatedAssertion> = serde_yaml::from_str(
+            r#"
+- path: $.items[?@.name == 'x'].id
+  equals: [2]
+- path: total
+  greaterThan: 5
+- path: items
+  length: 3
+- path: $.items[0].name
+  matches: ^w
+- path: $.items[0].deleted
+  absent: true
+- path: owner
+  type: "null"
+- path: items.name
+  contains: y
+"#,
+        )
+        .unwrap();
+        let expected = ExpectedResultData {
+            assertions: assertions
+                .into_iter()
+                .map(|a| definition::ResponseAssertion::new(a).unwrap())
+                .collect(),
+            ..ExpectedResultData::new()
+        };
+
+        let actual = process_body_response(
+            expected,
+            "application/json",
+            r#"{"total": 2, "owner": null, "items": [{"id": 1, "name": "w"}, {"id": 2, "name": "x"}]}"#,
+        );
+        assert_eq!(actual.status, TestStatus::Failed);
+        assert_eq!(
+            actual.validation,
+            Validated::Fail(nev![
+                "Expected `total` to be greater than 5 but received 2".to_string(),
+                "Expected `items` length 3 but received 2".to_string(),
+                r#"Expected `items.name` to contain "y" but received ["w","x"]"#.to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn process_response_json_body_against_text_response() {
+        let expected = ExpectedResultData {
+            body: Some(BodyOrSchema::Body(json!({"Name": "Bob"}))),
+            ..ExpectedResultData::new()
+        };
+
+        let actual = process_text_response(expected, "<html></html>");
+        assert_eq!(actual.status, TestStatus::Failed);
+        assert_eq!(
+            actual.validation,
+            Validated::Fail(nev![
+                "Expected JSON body but response was not JSON (content type text/html)".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn process_response_status_match() {
+        let expected = ExpectedResultData {
+            status: Some(ValueOrNumericSpecification::Schema(NumericSpecification {
+                specification: Some(Specification::OneOf(vec![200, 201, 202])),
+                min: None,
+                max: None,
+            })),
+            body: None,
+            headers: Vec::default(),
+            ..ExpectedResultData::new()
+        };
+
+        let ignore_body: [String; 0] = [];
+        let actual = process_response(
+            0,
+            StageType::Normal,
+            None,
+            ResultDetails {
+                request: RequestDetails {
+                    body: serde_json::Value::default(),
+                    headers: Vec::default(),
+                    method: http::Verb::Post.as_method(),
+                    url: "".to_string(),
+                },
+                request_runtime: 100,
+                expected: expected.clone(),
+                actual: Some(ResponseResultData {
+                    body: serde_json::Value::default(),
+                    headers: Vec::default(),
+                    status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
+                }),
+                compare_request: None,
+                compare_request_runtime: None,
+                compare_actual: None,
+            },
+            &ignore_body,
+            None,
+            None,
+        );
+        assert_eq!(actual.status, TestStatus::Passed);
+        assert!(actual.validation.is_good());
     }
 
     #[test]
@@ -2571,6 +4755,9 @@ mod tests {
                 expected: expected.clone(),
                 actual: Option::from(ResponseResultData {
                     status: 500,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
                     body: serde_json::Value::default(),
                     headers: Vec::default(),
                 }),
@@ -2618,6 +4805,9 @@ mod tests {
                 expected: expected.clone(),
                 actual: Option::from(ResponseResultData {
                     status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
                     body: serde_json::Value::default(),
                     headers: Vec::default(),
                 }),
@@ -2661,6 +4851,9 @@ mod tests {
                 expected: expected.clone(),
                 actual: Option::from(ResponseResultData {
                     status: 200,
+                    raw_body: vec![],
+                    content_type: None,
+                    is_json: true,
                     body: serde_json::Value::default(),
                     headers: Vec::default(),
                 }),
@@ -2731,33 +4924,29 @@ mod tests {
     //     assert!(result.as_ref().unwrap().body.is_null());
     // }
 
-    #[test]
-    fn http_request_from_test_spec_post() {
-        let mut state = State {
-            variables: HashMap::new(),
-            cookies: HashMap::new(),
-            bypass_cert_verification: false,
-        };
-        state
-            .variables
-            .insert("MY_VARIABLE".to_string(), "foo".to_string());
-        state
-            .variables
-            .insert("MY_VARIABLE2".to_string(), "bar".to_string());
+    #[tokio::test]
+    async fn http_request_from_test_spec_post() {
+        let state = State::new(&config::Config::default().settings).unwrap();
+        state.insert_variable("MY_VARIABLE".to_string(), json!("foo"));
+        state.insert_variable("MY_VARIABLE2".to_string(), json!("bar"));
 
         let body = serde_json::json!({ "an": "object" });
         let res = http_request_from_test_spec(
             &state,
-            ResolvedRequest::new(
-                "https://google.com".to_string(),
-                http::Verb::Post.as_method(),
-                vec![(
-                    "header".to_string(),
-                    "${MY_VARIABLE}-${MY_VARIABLE2}".to_string(),
-                )],
-                Some(body),
+            resolve_headers(
+                &state,
+                ResolvedRequest::new(
+                    "https://google.com".to_string(),
+                    http::Verb::Post.as_method(),
+                    vec![(
+                        "header".to_string(),
+                        "${MY_VARIABLE}-${MY_VARIABLE2}".to_string(),
+                    )],
+                    Some(body),
+                ),
             ),
-        );
+        )
+        .await;
         let expected: Request<()> = Request::default();
         assert_ne!(expected.type_id(), res.as_ref().unwrap().body().type_id());
 
@@ -2769,6 +4958,134 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn http_request_from_test_spec_form() {
+        let state = State::new(&config::Config::default().settings).unwrap();
+        let res = http_request_from_test_spec(
+            &state,
+            ResolvedRequest::new(
+                "https://google.com".to_string(),
+                http::Verb::Post.as_method(),
+                vec![],
+                None,
+            )
+            .with_payload(Some(definition::RequestPayload::Form(vec![
+                definition::FormField {
+                    name: "user name".to_string(),
+                    value: "bob&alice".to_string(),
+                },
+                definition::FormField {
+                    name: "password".to_string(),
+                    value: "p=1".to_string(),
+                },
+            ]))),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            "application/x-www-form-urlencoded",
+            res.headers().get("Content-Type").unwrap()
+        );
+        assert_eq!("36", res.headers().get("Content-Length").unwrap());
+    }
+
+    #[tokio::test]
+    async fn http_request_from_test_spec_raw_keeps_explicit_content_type() {
+        let state = State::new(&config::Config::default().settings).unwrap();
+        let res = http_request_from_test_spec(
+            &state,
+            ResolvedRequest::new(
+                "https://google.com".to_string(),
+                http::Verb::Post.as_method(),
+                vec![("content-type".to_string(), "text/csv".to_string())],
+                None,
+            )
+            .with_payload(Some(definition::RequestPayload::Raw(definition::RawBody {
+                content_type: "text/plain".to_string(),
+                source: definition::PayloadSource::Value("a,b\n1,2".to_string()),
+            }))),
+        )
+        .await
+        .unwrap();
+
+        let content_types: Vec<&HeaderValue> =
+            res.headers().get_all("Content-Type").iter().collect();
+        assert_eq!(vec!["text/csv"], content_types);
+        assert_eq!("7", res.headers().get("Content-Length").unwrap());
+    }
+
+    #[tokio::test]
+    async fn multipart_payload_encoding() {
+        let (content_type, data) = definition::RequestPayload::Multipart(vec![
+            definition::MultipartPart {
+                name: "description".to_string(),
+                source: definition::PayloadSource::Value("hello".to_string()),
+                filename: None,
+                content_type: None,
+            },
+            definition::MultipartPart {
+                name: "upload".to_string(),
+                source: definition::PayloadSource::Value("{}".to_string()),
+                filename: Some("data.json".to_string()),
+                content_type: Some("application/json".to_string()),
+            },
+            definition::MultipartPart {
+                name: "note\r\nX-Injected: 1".to_string(),
+                source: definition::PayloadSource::Value("x".to_string()),
+                filename: Some("a\"b\n.txt".to_string()),
+                content_type: None,
+            },
+        ])
+        .encode()
+        .await
+        .unwrap();
+
+        let boundary = content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap();
+        assert_eq!(
+            format!(
+                "--{boundary}\r\n\
+                Content-Disposition: form-data; name=\"description\"\r\n\r\n\
+                hello\r\n\
+                --{boundary}\r\n\
+                Content-Disposition: form-data; name=\"upload\"; filename=\"data.json\"\r\n\
+                Content-Type: application/json\r\n\r\n\
+                {{}}\r\n\
+                --{boundary}\r\n\
+                Content-Disposition: form-data; name=\"note%0D%0AX-Injected: 1\"; filename=\"a%22b%0A.txt\"\r\n\r\n\
+                x\r\n\
+                --{boundary}--\r\n"
+            ),
+            String::from_utf8(data).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_payload_file_is_an_error() {
+        let state = State::new(&config::Config::default().settings).unwrap();
+        let res = http_request_from_test_spec(
+            &state,
+            ResolvedRequest::new(
+                "https://google.com".to_string(),
+                http::Verb::Post.as_method(),
+                vec![],
+                None,
+            )
+            .with_payload(Some(definition::RequestPayload::Raw(definition::RawBody {
+                content_type: "application/octet-stream".to_string(),
+                source: definition::PayloadSource::File("does/not/exist.bin".to_string()),
+            }))),
+        )
+        .await;
+
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .starts_with("unable to read request file (does/not/exist.bin)"));
+    }
+
     fn construct_definition_for_dependency_graph(
         id: &str,
         requires: Option<String>,
@@ -2781,9 +5098,11 @@ mod tests {
             platform_id: None,
             project: None,
             environment: None,
-            requires,
+            requires: requires.into_iter().collect(),
             tags: vec![String::from("myTag"), String::from("myTag2")],
             iterate: 0,
+            timeout: None,
+            retry: None,
             variables: Vec::new(),
             global_variables: Vec::new(),
             stages: Vec::new(),
@@ -2810,7 +5129,8 @@ mod tests {
         let actual = construct_test_execution_graph_v2(
             defs,
             vec![construct_definition_for_dependency_graph("E", None, 4)],
-        );
+        )
+        .unwrap();
         assert_eq!(1, actual.len());
         assert_eq!(4, actual.get(0).unwrap().len());
     }
@@ -2830,7 +5150,8 @@ mod tests {
         let actual = construct_test_execution_graph_v2(
             defs,
             vec![construct_definition_for_dependency_graph("E", None, 5)],
-        );
+        )
+        .unwrap();
 
         assert_eq!(2, actual.len());
         assert_eq!(1, actual.get(0).unwrap().len());
@@ -2841,6 +5162,43 @@ mod tests {
         assert_eq!(4, actual.get(1).unwrap().len());
     }
 
+    #[test]
+    fn requirement_graph_renders_dot_and_mermaid() {
+        let mut disabled = construct_definition_for_dependency_graph("B", Some("A".to_string()), 1);
+        disabled.disabled = true;
+        let defs = vec![
+            construct_definition_for_dependency_graph("A", None, 0),
+            disabled,
+            construct_definition_for_dependency_graph("C", Some("missing".to_string()), 2),
+        ];
+
+        assert_eq!(
+            "digraph requirements {
+  rankdir=LR;
+  node [shape=box];
+  t0 [label=\"A\"];
+  t1 [label=\"B\", style=dashed];
+  t2 [label=\"C\"];
+  m0 [label=\"missing (not found)\", style=dotted];
+  t0 -> t1;
+  m0 -> t2 [style=dotted];
+}
+",
+            requirement_graph(&defs, &[], GraphFormat::Dot)
+        );
+        assert_eq!(
+            "flowchart LR
+  t0[\"A\"]
+  t1[\"B (disabled)\"]
+  t2[\"C\"]
+  m0[\"missing (not found)\"]
+  t0 --> t1
+  m0 -.-> t2
+",
+            requirement_graph(&defs, &[], GraphFormat::Mermaid)
+        );
+    }
+
     #[test]
     fn straight_line_dependency_is_node_chain() {
         let defs = vec!["A", "B", "C", "D"]
@@ -2864,11 +5222,74 @@ mod tests {
             .flatten()
             .collect::<Vec<Definition>>();
 
-        let actual = construct_test_execution_graph_v2(defs, Vec::new());
+        let actual = construct_test_execution_graph_v2(defs, Vec::new()).unwrap();
 
         assert_eq!(4, actual.len());
     }
 
+    #[test]
+    fn multiple_requirements_wait_for_every_prerequisite() {
+        let mut c = construct_definition_for_dependency_graph("C", None, 2);
+        c.requires = vec!["A".to_string(), "B".to_string()];
+        let defs = vec![
+            construct_definition_for_dependency_graph("D", Some("C".to_string()), 3),
+            c,
+            construct_definition_for_dependency_graph("A", None, 0),
+            construct_definition_for_dependency_graph("B", None, 1),
+        ];
+
+        let actual = construct_test_execution_graph_v2(defs, Vec::new())
+            .unwrap()
+            .into_iter()
+            .map(|layer| {
+                layer
+                    .into_iter()
+                    .map(|td| td.id.unwrap())
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<Vec<String>>>();
+
+        assert_eq!(
+            vec![
+                vec!["A".to_string(), "B".to_string()],
+                vec!["C".to_string()],
+                vec!["D".to_string()]
+            ],
+            actual
+        );
+    }
+
+    #[test]
+    fn missing_requirement_excludes_dependents() {
+        let defs = vec![
+            construct_definition_for_dependency_graph("A", Some("Missing".to_string()), 0),
+            construct_definition_for_dependency_graph("B", Some("A".to_string()), 1),
+            construct_definition_for_dependency_graph("C", None, 2),
+        ];
+
+        let actual = construct_test_execution_graph_v2(defs, Vec::new()).unwrap();
+
+        assert_eq!(1, actual.len());
+        assert_eq!("C", actual[0][0].id.clone().unwrap());
+    }
+
+    #[test]
+    fn circular_requirements_are_an_error() {
+        let defs = vec![
+            construct_definition_for_dependency_graph("A", Some("C".to_string()), 0),
+            construct_definition_for_dependency_graph("B", Some("A".to_string()), 1),
+            construct_definition_for_dependency_graph("C", Some("B".to_string()), 2),
+            construct_definition_for_dependency_graph("D", None, 3),
+        ];
+
+        let actual = construct_test_execution_graph_v2(defs, Vec::new());
+
+        assert_eq!(
+            "circular test requirements detected: A -> B -> C -> A",
+            actual.unwrap_err().to_string()
+        );
+    }
+
     fn default_definition_for_filtering() -> test::Definition {
         test::Definition {
             name: None,
@@ -2877,9 +5298,11 @@ mod tests {
             platform_id: None,
             project: None,
             environment: None,
-            requires: None,
+            requires: vec![],
             tags: vec![String::from("myTag"), String::from("myTag2")],
             iterate: 0,
+            timeout: None,
+            retry: None,
             variables: Vec::new(),
             global_variables: Vec::new(),
             stages: Vec::new(),
@@ -2961,12 +5384,136 @@ mod tests {
         );
     }
 
+    #[test]
+    fn expression_filter() {
+        let test_definition = default_definition_for_filtering();
+        let matching = TagMode::Expression(
+            crate::tags::parse("(myTag or other) and not (slow or nonexistant)").unwrap(),
+        );
+        let excluding = TagMode::Expression(crate::tags::parse("not myTag2").unwrap());
+        assert!(!ignored_due_to_tag_filter(&test_definition, &[], &matching));
+        assert!(ignored_due_to_tag_filter(&test_definition, &[], &excluding));
+    }
+
+    #[test]
+    fn selection_matches_ids_and_names() {
+        let test_definition = test::Definition {
+            id: Some("create_order".to_string()),
+            name: Some("Create an order".to_string()),
+            ..default_definition_for_filtering()
+        };
+        let by_id = Selection {
+            ids: vec!["create_order".to_string()],
+            name_regex: None,
+        };
+        let by_name = Selection {
+            ids: vec![],
+            name_regex: Some(regex::Regex::new("order$").unwrap()),
+        };
+        let other = Selection {
+            ids: vec!["login".to_string()],
+            name_regex: Some(regex::Regex::new("^Login").unwrap()),
+        };
+        assert!(Selection::default().matches(&test_definition));
+        assert!(by_id.matches(&test_definition));
+        assert!(by_name.matches(&test_definition));
+        assert!(!other.matches(&test_definition));
+    }
+
+    #[test]
+    fn validation_resolves_requirements_outside_tag_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, yaml: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, yaml).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let files = vec![
+            write(
+                "login.jkt",
+                r#"
+id: login
+request:
+  url: http://localhost/login
+response:
+  extract:
+  - name: token
+    field: token
+"#,
+            ),
+            write(
+                "orders.jkt",
+                r#"
+id: orders
+tags: smoke
+requires: login
+request:
+  url: http://localhost/orders
+  headers:
+  - header: Authorization
+    value: Bearer ${token}
+"#,
+            ),
+        ];
+
+        let (run, ignore, load_errors) = tests_from_files(
+            &config::Config::default(),
+            files,
+            vec!["smoke".to_string()],
+            None,
+            None,
+            TagMode::AND,
+            &Selection::default(),
+        );
+        assert!(load_errors.is_empty());
+        assert_eq!(1, run.len());
+        assert_eq!(1, ignore.len());
+
+        let diagnostics = validation::validate_definitions(&run, &ignore);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert!(!validation::validate_definitions(&run, &[]).is_empty());
+    }
+
+    #[test]
+    fn selection_ids_are_case_insensitive() {
+        let test_definition = test::Definition {
+            id: Some("createorder".to_string()),
+            ..default_definition_for_filtering()
+        };
+        assert!(Selection::new(vec!["createOrder".to_string()], None).matches(&test_definition));
+        assert!(Selection::new(vec!["CREATEORDER".to_string()], None).matches(&test_definition));
+    }
+
+    #[test]
+    fn prerequisites_are_included_transitively() {
+        let definition = |index: usize, id: &str, requires: &[&str]| test::Definition {
+            id: Some(id.to_string()),
+            requires: requires.iter().map(|r| r.to_string()).collect(),
+            index,
+            ..default_definition_for_filtering()
+        };
+        let (run, ignore) = include_prerequisites(
+            vec![definition(3, "create_order", &["cart"])],
+            vec![
+                definition(0, "login", &[]),
+                definition(1, "unrelated", &[]),
+                definition(2, "cart", &["login"]),
+            ],
+        );
+        let ids = |tests: &[test::Definition]| -> Vec<String> {
+            tests.iter().filter_map(|td| td.id.clone()).collect()
+        };
+        assert_eq!(vec!["login", "cart", "create_order"], ids(&run));
+        assert_eq!(vec!["unrelated"], ids(&ignore));
+    }
+
     #[test]
     fn empty_execution_result_is_all_skips() {
         let execution_result = ExecutionResult {
             test_results: vec![TestResult {
                 test_name: "name".to_string(),
                 iteration_results: vec![],
+                definition: default_definition_for_filtering(),
             }],
             runtime: 0,
         };
@@ -2978,4 +5525,1431 @@ mod tests {
 
         assert_eq!(1, report.skipped);
     }
+
+    #[tokio::test]
+    async fn concurrent_layers_preserve_result_order() {
+        let layers = vec![
+            vec!["A", "B", "C"]
+                .into_iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    let mut td = construct_definition_for_dependency_graph(id, None, i);
+                    td.name = Some(id.to_string());
+                    td.iterate = 1;
+                    td
+                })
+                .collect::<Vec<Definition>>(),
+            vec![{
+                let mut td =
+                    construct_definition_for_dependency_graph("D", Some("A".to_string()), 3);
+                td.name = Some("D".to_string());
+                td.iterate = 1;
+                td
+            }],
+        ];
+
+        let result = run_tests(
+            layers,
+            None,
+            DryRunExecutionPolicy,
+            &config::Config::default(),
+            4,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec!["A", "B", "C", "D"],
+            result
+                .test_results
+                .iter()
+                .map(|tr| tr.test_name.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert!(result
+            .test_results
+            .iter()
+            .all(|tr| tr.iteration_results[0].status == TestStatus::Passed));
+    }
+
+    #[tokio::test]
+    async fn independent_tests_overlap_and_dependents_wait() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // when each request arrived at the server and when its response was sent
+        let timings: Arc<Mutex<HashMap<String, (Instant, Instant)>>> = Arc::default();
+        let recorded = timings.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let mut buffer = vec![0; 8192];
+                    let read = socket.read(&mut buffer).await.unwrap_or(0);
+                    let arrived = Instant::now();
+                    let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                    let path = request
+                        .split_whitespace()
+                        .nth(1)
+                        .unwrap_or_default()
+                        .to_string();
+                    if path.starts_with("/slow") {
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                    }
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .insert(path, (arrived, Instant::now()));
+                    _ = socket
+                        .write_all(status_response("200 OK").unwrap().as_bytes())
+                        .await;
+                });
+            }
+        });
+
+        let test = |yaml: &str| definition_from_yaml(&yaml.replace("{addr}", &addr.to_string()));
+        let tests = vec![
+            test("name: A\nid: slow-a\nrequest:\n  url: http://{addr}/slow-a\n"),
+            test("name: B\nid: slow-b\nrequest:\n  url: http://{addr}/slow-b\n"),
+            test("name: C\nrequires: slow-a\nrequest:\n  url: http://{addr}/after\n"),
+        ];
+        let layers = construct_test_execution_graph_v2(tests, Vec::new()).unwrap();
+
+        let result = run_tests(
+            layers,
+            None,
+            ActualRunExecutionPolicy,
+            &config::Config::default(),
+            2,
+        )
+        .await
+        .unwrap();
+
+        assert!(result
+            .test_results
+            .iter()
+            .all(|tr| tr.iteration_results[0].status == TestStatus::Passed));
+        let timings = timings.lock().unwrap();
+        let (a_start, a_end) = timings["/slow-a"];
+        let (b_start, b_end) = timings["/slow-b"];
+        let (c_start, _) = timings["/after"];
+        assert!(a_start < b_end && b_start < a_end);
+        assert!(c_start >= a_end);
+    }
+
+    #[tokio::test]
+    async fn concurrent_tests_keep_their_own_variables() {
+        let addr = spawn_test_server(|request_line| {
+            let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+            match path {
+                "/login-a" => json_response(r#"{"token":"a"}"#),
+                "/login-b" => json_response(r#"{"token":"b"}"#),
+                "/use-a/a" | "/use-b/b" => status_response("200 OK"),
+                _ => status_response("404 Not Found"),
+            }
+        })
+        .await;
+
+        let test = |yaml: &str| definition_from_yaml(&yaml.replace("{addr}", &addr.to_string()));
+        let login = |id: &str| {
+            test(&format!(
+                "name: {id}\nid: {id}\nrequest:\n  url: http://{{addr}}/login-{id}\nresponse:\n  extract:\n  - name: token\n    field: token\n"
+            ))
+        };
+        let using = |id: &str| {
+            test(&format!(
+                "name: use {id}\nrequires: {id}\nrequest:\n  url: http://{{addr}}/use-{id}/${{token}}\nresponse:\n  status: 200\n"
+            ))
+        };
+        let tests = vec![login("a"), login("b"), using("a"), using("b")];
+        let layers = construct_test_execution_graph_v2(tests, Vec::new()).unwrap();
+
+        let result = run_tests(
+            layers,
+            None,
+            ActualRunExecutionPolicy,
+            &config::Config::default(),
+            4,
+        )
+        .await
+        .unwrap();
+
+        assert!(result
+            .test_results
+            .iter()
+            .all(|tr| tr.iteration_results[0].status == TestStatus::Passed));
+    }
+
+    #[tokio::test]
+    async fn skipped_requirement_skips_dependents_transitively() {
+        let named = |id: &str, requires: Option<String>, index: usize| {
+            let mut td = construct_definition_for_dependency_graph(id, requires, index);
+            td.name = Some(id.to_string());
+            td.iterate = 1;
+            td
+        };
+        let mut a = named("A", None, 0);
+        a.disabled = true;
+        let layers = vec![
+            vec![a, named("D", None, 3)],
+            vec![named("B", Some("A".to_string()), 1)],
+            vec![named("C", Some("B".to_string()), 2)],
+        ];
+
+        let result = run_tests(
+            layers,
+            None,
+            DryRunExecutionPolicy,
+            &config::Config::default(),
+            1,
+        )
+        .await
+        .unwrap();
+
+        let outcomes = result
+            .test_results
+            .iter()
+            .map(|tr| {
+                (
+                    tr.test_name.as_str(),
+                    tr.iteration_results[0].status.clone(),
+                    tr.iteration_results[0].skip_reason.clone(),
+                )
+            })
+            .collect::<Vec<(&str, TestStatus, Option<String>)>>();
+
+        assert_eq!(
+            vec![
+                (
+                    "A",
+                    TestStatus::Skipped,
+                    Some("test is disabled".to_string())
+                ),
+                ("D", TestStatus::Passed, None),
+                (
+                    "B",
+                    TestStatus::Skipped,
+                    Some("required test `A` was skipped".to_string())
+                ),
+                (
+                    "C",
+                    TestStatus::Skipped,
+                    Some("required test `B` was skipped".to_string())
+                ),
+            ],
+            outcomes
+        );
+    }
+
+    #[tokio::test]
+    async fn client_connections_are_reused_across_stages() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let requests = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let accepted = connections.clone();
+        let answered = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::Relaxed);
+                let answered = answered.clone();
+                tokio::spawn(async move {
+                    let mut buffer = vec![0; 8192];
+                    while let Ok(read) = socket.read(&mut buffer).await {
+                        if read == 0 {
+                            break;
+                        }
+                        answered.fetch_add(1, Ordering::Relaxed);
+                        let response = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}";
+                        if socket.write_all(response.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let file: test::File = serde_yaml::from_str(&format!(
+            r#"
+name: keep alive
+stages:
+- request:
+    url: http://{addr}/one
+- request:
+    url: http://{addr}/two
+- request:
+    url: http://{addr}/three
+"#
+        ))
+        .unwrap();
+        let td = validation::validate_file(file, &[], None, None, 0).unwrap();
+        let config = config::Config::default();
+        let state = State::new(&config.settings).unwrap();
+        let (passed, _) = run(&state, &td, 0, None, &config).await.unwrap();
+
+        assert!(passed);
+        assert_eq!(3, requests.load(Ordering::Relaxed));
+        assert_eq!(1, connections.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn host_permits_limit_requests_per_host() {
+        let mut settings = config::Config::default().settings;
+        settings.max_connections_per_host = Some(1);
+        let state = State::new(&settings).unwrap();
+        let wait = Duration::from_millis(50);
+
+        let first = state.session.host_permit("http://localhost:8080/a").await;
+        assert!(first.is_some());
+        assert!(
+            tokio::time::timeout(wait, state.session.host_permit("http://localhost:8080/b"))
+                .await
+                .is_err()
+        );
+        assert!(
+            tokio::time::timeout(wait, state.session.host_permit("http://localhost:9090/a"))
+                .await
+                .is_ok()
+        );
+
+        drop(first);
+        assert!(
+            tokio::time::timeout(wait, state.session.host_permit("http://localhost:8080/b"))
+                .await
+                .is_ok_and(|permit| permit.is_some())
+        );
+
+        let unlimited = State::new(&config::Config::default().settings).unwrap();
+        assert!(unlimited
+            .session
+            .host_permit("http://localhost:8080/a")
+            .await
+            .is_none());
+    }
+
+    //Serves HTTP/1.1 on a local port. `respond` gets the request line and returns the full
+    //response to write, or None to leave the request hanging
+    async fn spawn_test_server<F>(respond: F) -> std::net::SocketAddr
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let respond = Arc::new(respond);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let mut buffer = vec![0; 8192];
+                    let read = socket.read(&mut buffer).await.unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                    let request_line = request.lines().next().unwrap_or_default();
+                    match respond(request_line) {
+                        Some(response) => _ = socket.write_all(response.as_bytes()).await,
+                        None => tokio::time::sleep(Duration::from_secs(30)).await,
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    fn definition_from_yaml(yaml: &str) -> test::Definition {
+        let file: test::File = serde_yaml::from_str(yaml).unwrap();
+        validation::validate_file(file, &[], None, None, 0).unwrap()
+    }
+
+    #[derive(Default)]
+    struct RunOptions {
+        config: config::Config,
+        secrets: Vec<test::Variable>,
+        variables: Vec<(&'static str, &'static str)>,
+    }
+
+    struct ServerRun {
+        addr: std::net::SocketAddr,
+        definition: test::Definition,
+        state: State,
+        passed: bool,
+        results: Vec<StageResult>,
+    }
+
+    //Serves `respond` locally and loads the test in `yaml`, with `{addr}` replaced
+    //by the server's address
+    async fn serve_yaml<F>(
+        respond: F,
+        yaml: &str,
+        secrets: &[test::Variable],
+    ) -> (std::net::SocketAddr, test::Definition)
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        let addr = spawn_test_server(respond).await;
+        let file: test::File =
+            serde_yaml::from_str(&yaml.replace("{addr}", &addr.to_string())).unwrap();
+        let definition = validation::validate_file(file, secrets, None, None, 0).unwrap();
+        (addr, definition)
+    }
+
+    async fn run_definition(
+        addr: std::net::SocketAddr,
+        definition: test::Definition,
+        options: RunOptions,
+    ) -> ServerRun {
+        let state = State::new(&options.config.settings).unwrap();
+        for (name, value) in options.variables {
+            state.insert_variable(name.to_string(), json!(value));
+        }
+        let (passed, results) = run(&state, &definition, 0, None, &options.config)
+            .await
+            .unwrap();
+        ServerRun {
+            addr,
+            definition,
+            state,
+            passed,
+            results,
+        }
+    }
+
+    async fn run_yaml_against<F>(respond: F, yaml: &str) -> ServerRun
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        run_yaml_against_with(respond, yaml, RunOptions::default()).await
+    }
+
+    async fn run_yaml_against_with<F>(respond: F, yaml: &str, options: RunOptions) -> ServerRun
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        let (addr, definition) = serve_yaml(respond, yaml, &options.secrets).await;
+        run_definition(addr, definition, options).await
+    }
+
+    fn status_response(status: &str) -> Option<String> {
+        Some(format!(
+            "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        ))
+    }
+
+    #[tokio::test]
+    async fn stage_timeout_fails_stage_and_still_runs_cleanup() {
+        let cleaned_up = Arc::new(AtomicBool::new(false));
+        let cleanup_flag = cleaned_up.clone();
+        let run = run_yaml_against(
+            move |request_line| {
+                if request_line.contains("/slow") {
+                    return None;
+                }
+                cleanup_flag.store(true, Ordering::Relaxed);
+                status_response("200 OK")
+            },
+            r#"
+name: slow
+stages:
+- request:
+    url: http://{addr}/slow
+  timeout: 100
+cleanup:
+  always:
+    url: http://{addr}/cleanup
+"#,
+        )
+        .await;
+
+        assert!(!run.passed);
+        assert_eq!(2, run.results.len());
+        assert_eq!(TestStatus::Failed, run.results[0].status);
+        assert!(run.results[0]
+            .timeout_reason
+            .as_ref()
+            .is_some_and(|r| r.starts_with("Request timed out after")));
+        assert!(run.results[1].stage_type == StageType::Cleanup);
+        assert_eq!(TestStatus::Passed, run.results[1].status);
+        assert!(cleaned_up.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_bounds_every_stage() {
+        let mut options = RunOptions::default();
+        options.config.settings.timeout = Some(60000);
+        let start = Instant::now();
+        let run = run_yaml_against_with(
+            |request_line| {
+                if request_line.contains("/slow") {
+                    return None;
+                }
+                status_response("200 OK")
+            },
+            r#"
+name: slow
+timeout: 100
+stages:
+- request:
+    url: http://{addr}/fast
+- request:
+    url: http://{addr}/slow
+"#,
+            options,
+        )
+        .await;
+
+        assert!(!run.passed);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(TestStatus::Passed, run.results[0].status);
+        assert_eq!(
+            Some("Test timed out after 100 milliseconds"),
+            run.results[1].timeout_reason.as_deref()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_bounds_delays_and_retry_backoff() {
+        let start = Instant::now();
+        let run = run_yaml_against(
+            |_| status_response("200 OK"),
+            r#"
+name: waiting
+timeout: 500
+stages:
+- request:
+    url: http://{addr}/delayed
+  delay: 60000
+"#,
+        )
+        .await;
+
+        assert!(!run.passed);
+        assert_eq!(1, run.results.len());
+        assert_eq!(
+            Some("Test timed out after 500 milliseconds"),
+            run.results[0].timeout_reason.as_deref()
+        );
+
+        let run = run_yaml_against(
+            |_| status_response("503 Service Unavailable"),
+            r#"
+name: waiting
+timeout: 500
+stages:
+- request:
+    url: http://{addr}/unavailable
+  response:
+    status: 200
+  retry:
+    attempts: 3
+    delay: 60000
+    onStatus: [503]
+"#,
+        )
+        .await;
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!run.passed);
+        assert!(run.results[0].previous_attempts.is_empty());
+        assert_eq!(
+            Some("Test timed out after 500 milliseconds"),
+            run.results[0].timeout_reason.as_deref()
+        );
+        // the unexpected status is still reported alongside the timeout
+        assert!(matches!(&run.results[0].validation, Validated::Fail(nec) if nec.len().get() == 2));
+    }
+
+    //Counts the requests the server receives
+    fn call_counter() -> (
+        Arc<std::sync::atomic::AtomicU32>,
+        Arc<std::sync::atomic::AtomicU32>,
+    ) {
+        let calls = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        (calls.clone(), calls)
+    }
+
+    #[tokio::test]
+    async fn stage_retries_listed_statuses_and_records_attempts() {
+        let (calls, server_calls) = call_counter();
+        let run = run_yaml_against(
+            move |_| match server_calls.fetch_add(1, Ordering::Relaxed) {
+                0 | 1 => status_response("503 Service Unavailable"),
+                _ => status_response("200 OK"),
+            },
+            r#"
+name: flaky
+stages:
+- request:
+    url: http://{addr}/flaky
+  response:
+    status: 200
+  retry:
+    attempts: 3
+    delay: 0
+    onStatus: [503]
+"#,
+        )
+        .await;
+
+        assert!(run.passed);
+        assert_eq!(3, calls.load(Ordering::Relaxed));
+        assert_eq!(2, run.results[0].previous_attempts.len());
+        assert!(run.results[0]
+            .previous_attempts
+            .iter()
+            .all(|a| a.status == TestStatus::Failed));
+
+        let execution_result = ExecutionResult {
+            test_results: vec![TestResult {
+                test_name: "flaky".to_string(),
+                iteration_results: vec![IterationResult::new(0, Ok((run.passed, run.results)))],
+                definition: run.definition,
+            }],
+            runtime: 0,
+        };
+        let junit = JunitResultFormatter {}.format(&execution_result).0;
+        assert_eq!(2, junit.matches("<flakyFailure").count());
+        assert!(!junit.contains("<failure"));
+    }
+
+    #[tokio::test]
+    async fn stage_retry_ignores_unlisted_statuses() {
+        let (calls, server_calls) = call_counter();
+        let run = run_yaml_against(
+            move |_| {
+                server_calls.fetch_add(1, Ordering::Relaxed);
+                status_response("500 Internal Server Error")
+            },
+            r#"
+name: broken
+stages:
+- request:
+    url: http://{addr}/broken
+  response:
+    status: 200
+  retry:
+    attempts: 3
+    delay: 0
+    onStatus: [503]
+"#,
+        )
+        .await;
+
+        assert!(!run.passed);
+        assert_eq!(1, calls.load(Ordering::Relaxed));
+        assert!(run.results[0].previous_attempts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stage_retries_connection_errors() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let td = definition_from_yaml(&format!(
+            r#"
+name: unreachable
+stages:
+- request:
+    url: http://{addr}/down
+  retry:
+    attempts: 2
+    delay: 0
+    onConnectionError: true
+"#
+        ));
+        let run = run_definition(addr, td, RunOptions::default()).await;
+
+        assert!(!run.passed);
+        assert_eq!(1, run.results[0].previous_attempts.len());
+        assert!(run.results[0].details.actual.is_none());
+    }
+
+    #[tokio::test]
+    async fn stage_retries_truncated_bodies_as_connection_errors() {
+        let (calls, server_calls) = call_counter();
+        let run = run_yaml_against(
+            move |_| match server_calls.fetch_add(1, Ordering::Relaxed) {
+                0 => Some("HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n{}".to_string()),
+                _ => status_response("200 OK"),
+            },
+            r#"
+name: truncated
+stages:
+- request:
+    url: http://{addr}/items
+  retry:
+    attempts: 2
+    delay: 0
+    onConnectionError: true
+"#,
+        )
+        .await;
+
+        assert!(run.passed);
+        assert_eq!(2, calls.load(Ordering::Relaxed));
+        assert!(run.results[0].previous_attempts[0].details.actual.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retry_reruns_failed_iterations() {
+        let (_, server_calls) = call_counter();
+        let (_, td) = serve_yaml(
+            move |_| match server_calls.fetch_add(1, Ordering::Relaxed) {
+                0 => status_response("500 Internal Server Error"),
+                _ => status_response("200 OK"),
+            },
+            r#"
+name: flaky
+retry:
+  attempts: 2
+  delay: 0
+request:
+  url: http://{addr}/flaky
+response:
+  status: 200
+"#,
+            &[],
+        )
+        .await;
+
+        let result = run_tests(
+            vec![vec![td]],
+            None,
+            ActualRunExecutionPolicy,
+            &config::Config::default(),
+            1,
+        )
+        .await
+        .unwrap();
+
+        let iteration_result = &result.test_results[0].iteration_results[0];
+        assert_eq!(TestStatus::Passed, iteration_result.status);
+        assert_eq!(1, iteration_result.previous_attempts.len());
+        assert_eq!(
+            TestStatus::Failed,
+            iteration_result.previous_attempts[0].status
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_attempts_start_from_fresh_variables() {
+        let (_, logins) = call_counter();
+        let (_, td) = serve_yaml(
+            move |request_line| {
+                if request_line.contains("/login") {
+                    return match logins.fetch_add(1, Ordering::Relaxed) {
+                        0 => json_response(r#"{"token":"stale"}"#),
+                        _ => json_response("{}"),
+                    };
+                }
+                status_response("503 Service Unavailable")
+            },
+            r#"
+name: login
+retry:
+  attempts: 2
+  delay: 0
+stages:
+- request:
+    url: http://{addr}/login
+  response:
+    extract:
+    - name: token
+      field: token
+- request:
+    url: http://{addr}/orders/${token}
+  response:
+    status: 200
+"#,
+            &[],
+        )
+        .await;
+
+        let result = run_tests(
+            vec![vec![td]],
+            None,
+            ActualRunExecutionPolicy,
+            &config::Config::default(),
+            1,
+        )
+        .await
+        .unwrap();
+
+        let iteration_result = &result.test_results[0].iteration_results[0];
+        assert_eq!(1, iteration_result.previous_attempts.len());
+        let Some(Ok((false, stage_results))) = &iteration_result.stage_results else {
+            panic!("expected the retried attempt to fail");
+        };
+        assert_eq!(
+            Validated::fail("unresolved variable token".to_string()),
+            stage_results[1].validation
+        );
+    }
+
+    fn json_response(body: &str) -> Option<String> {
+        Some(format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ))
+    }
+
+    #[tokio::test]
+    async fn poll_reissues_request_until_condition_holds() {
+        let (calls, server_calls) = call_counter();
+        let run = run_yaml_against(
+            move |_| match server_calls.fetch_add(1, Ordering::Relaxed) {
+                0 | 1 => json_response(r#"{"state":"running","id":7}"#),
+                _ => json_response(r#"{"state":"done","id":7}"#),
+            },
+            r#"
+name: job
+stages:
+- request:
+    url: http://{addr}/jobs/7
+  poll:
+    interval: 10
+    maxDuration: 5000
+    until:
+      body:
+        state: done
+      ignore:
+      - id
+  response:
+    status: 200
+    extract:
+    - name: state
+      field: state
+"#,
+        )
+        .await;
+
+        assert!(run.passed);
+        assert_eq!(3, calls.load(Ordering::Relaxed));
+        assert_eq!(Some(&json!("done")), run.state.variables().get("state"));
+    }
+
+    #[tokio::test]
+    async fn poll_fails_once_max_duration_passes() {
+        let run = run_yaml_against(
+            |_| json_response(r#"{"state":"running"}"#),
+            r#"
+name: job
+stages:
+- request:
+    url: http://{addr}/jobs/7
+  poll:
+    interval: 20
+    maxDuration: 100
+    until:
+      status: 201
+"#,
+        )
+        .await;
+
+        assert!(!run.passed);
+        let Validated::Fail(failures) = &run.results[0].validation else {
+            panic!("expected the poll to fail");
+        };
+        assert!(failures.head.starts_with("Poll condition not met after"));
+        assert!(failures
+            .iter()
+            .any(|f| f.contains("Expected status code 201")));
+    }
+
+    #[tokio::test]
+    async fn extracts_headers_status_cookies_and_json_structures() {
+        let run = run_yaml_against(
+            |request_line| {
+                if request_line.starts_with("POST") {
+                    let body = r#"{"job":{"id":7,"tags":["a","b"]}}"#;
+                    return Some(format!(
+                        "HTTP/1.1 201 Created\r\nLocation: /jobs/7\r\nSet-Cookie: session=abc123; Path=/\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ));
+                }
+                json_response("{}")
+            },
+            r#"
+name: extract
+stages:
+- request:
+    method: Post
+    url: http://{addr}/jobs
+  response:
+    extract:
+    - name: location
+      source: header
+      field: location
+    - name: code
+      source: status
+    - name: session
+      source: cookie
+      field: session
+    - name: job
+      field: job
+    - name: everything
+- request:
+    method: Put
+    url: http://{addr}${location}
+    body:
+      job: ${job}
+      note: ${location}
+"#,
+        )
+        .await;
+
+        assert!(run.passed);
+        let variables = run.state.variables();
+        assert_eq!(Some(&json!("/jobs/7")), variables.get("location"));
+        assert_eq!(Some(&json!(201)), variables.get("code"));
+        assert_eq!(Some(&json!("abc123")), variables.get("session"));
+        assert_eq!(
+            Some(&json!({"id":7,"tags":["a","b"]})),
+            variables.get("job")
+        );
+        assert_eq!(
+            Some(&json!({"job":{"id":7,"tags":["a","b"]}})),
+            variables.get("everything")
+        );
+        assert_eq!(
+            format!("http://{}/jobs/7", run.addr),
+            run.results[1].details.request.url
+        );
+        assert_eq!(
+            serde_json::json!({"job": {"id": 7, "tags": ["a", "b"]}, "note": "/jobs/7"}),
+            run.results[1].details.request.body
+        );
+    }
+
+    #[tokio::test]
+    async fn expressions_are_evaluated_in_requests() {
+        let run = run_yaml_against(
+            |_| json_response(r#"{"count":4}"#),
+            r#"
+name: expressions
+variables:
+- name: user
+  value: alice
+stages:
+- request:
+    url: http://{addr}/count
+  response:
+    extract:
+    - name: count
+      field: count
+- request:
+    method: Post
+    url: http://{addr}/items/${count + 1}
+    headers:
+    - header: Authorization
+      value: Basic ${base64('${user}:secret')}
+    body:
+      name: ${upper(user)}
+      checksum: ${sha256("abc")}
+"#,
+        )
+        .await;
+
+        assert!(run.passed);
+        let request = &run.results[1].details.request;
+        assert_eq!(format!("http://{}/items/5", run.addr), request.url);
+        assert!(request
+            .headers
+            .iter()
+            .any(|h| h.header == "Authorization" && h.value == "Basic YWxpY2U6c2VjcmV0"));
+        assert_eq!(
+            json!({
+                "name": "ALICE",
+                "checksum": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+            }),
+            request.body
+        );
+    }
+
+    #[tokio::test]
+    async fn unresolved_placeholders_are_not_sent() {
+        let requested = Arc::new(AtomicBool::new(false));
+        let requested_flag = requested.clone();
+        let run = run_yaml_against_with(
+            move |_| {
+                requested_flag.store(true, Ordering::Relaxed);
+                json_response("{}")
+            },
+            r#"
+name: unresolved
+stages:
+- request:
+    url: http://{addr}/items/${token}
+    headers:
+    - header: Authorization
+      value: Bearer ${tokn}
+"#,
+            RunOptions {
+                variables: vec![("token", "abc")],
+                ..RunOptions::default()
+            },
+        )
+        .await;
+
+        assert!(!run.passed);
+        assert_eq!(
+            Validated::fail("unresolved variable tokn".to_string()),
+            run.results[0].validation
+        );
+        assert!(!requested.load(Ordering::Relaxed));
+
+        // the failed stage reports the request as far as it was resolved
+        let request = &run.results[0].details.request;
+        assert_eq!(format!("http://{}/items/abc", run.addr), request.url);
+        assert_eq!("Bearer ${tokn}", request.headers[0].value);
+    }
+
+    #[tokio::test]
+    async fn unresolved_setup_placeholders_fail_the_setup_stage() {
+        let (calls, counted) = call_counter();
+        let run = run_yaml_against(
+            move |_| {
+                counted.fetch_add(1, Ordering::Relaxed);
+                json_response("{}")
+            },
+            r#"
+name: unresolved setup
+setup:
+  request:
+    url: http://{addr}/login?user=${missing}
+stages:
+- request:
+    url: http://{addr}/items
+"#,
+        )
+        .await;
+
+        assert!(!run.passed);
+        assert_eq!(1, run.results.len());
+        assert!(run.results[0].stage_type == StageType::Setup);
+        assert_eq!(
+            Validated::fail("unresolved variable missing".to_string()),
+            run.results[0].validation
+        );
+        assert_eq!(0, calls.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn unresolved_cleanup_placeholders_fail_the_cleanup_stage() {
+        let (calls, counted) = call_counter();
+        let run = run_yaml_against(
+            move |_| {
+                counted.fetch_add(1, Ordering::Relaxed);
+                json_response("{}")
+            },
+            r#"
+name: unresolved cleanup
+stages:
+- request:
+    url: http://{addr}/items
+cleanup:
+  always:
+    url: http://{addr}/items/${gone}
+"#,
+        )
+        .await;
+
+        assert!(!run.passed);
+        assert_eq!(2, run.results.len());
+        assert_eq!(TestStatus::Passed, run.results[0].status);
+        assert!(run.results[1].stage_type == StageType::Cleanup);
+        assert_eq!(
+            Validated::fail("unresolved variable gone".to_string()),
+            run.results[1].validation
+        );
+        assert_eq!(1, calls.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn timed_out_cleanup_fails_the_test() {
+        let mut options = RunOptions::default();
+        options.config.settings.timeout = Some(100);
+        let run = run_yaml_against_with(
+            |request_line| {
+                if request_line.contains("/cleanup") {
+                    return None;
+                }
+                status_response("200 OK")
+            },
+            r#"
+name: slow cleanup
+stages:
+- request:
+    url: http://{addr}/items
+cleanup:
+  onsuccess:
+    url: http://{addr}/done
+  always:
+    url: http://{addr}/cleanup
+"#,
+            options,
+        )
+        .await;
+
+        assert!(!run.passed);
+        assert_eq!(3, run.results.len());
+        assert_eq!(TestStatus::Passed, run.results[1].status);
+        assert_eq!(2, run.results[2].stage);
+        assert_eq!(TestStatus::Failed, run.results[2].status);
+        assert!(run.results[2].timeout_reason.is_some());
+    }
+
+    #[tokio::test]
+    async fn escaped_placeholders_are_sent_literally() {
+        let received = Arc::new(std::sync::Mutex::new(String::new()));
+        let received_line = received.clone();
+        let run = run_yaml_against(
+            move |request_line| {
+                *received_line.lock().unwrap() = request_line.to_string();
+                json_response("{}")
+            },
+            r#"
+name: escaped
+stages:
+- request:
+    url: http://{addr}/render?template=$${name}
+"#,
+        )
+        .await;
+
+        assert!(run.passed);
+        assert!(received
+            .lock()
+            .unwrap()
+            .contains("/render?template=${name}"));
+    }
+
+    #[tokio::test]
+    async fn recorded_requests_hold_the_headers_sent() {
+        let run = run_yaml_against(
+            |_| json_response(r#"{"token":"abc"}"#),
+            r#"
+name: headers
+stages:
+- request:
+    url: http://{addr}/login
+  response:
+    extract:
+    - name: token
+      field: token
+- request:
+    url: http://{addr}/orders?template=$${name}
+    headers:
+    - header: Authorization
+      value: Bearer ${token}
+    - header: X-Signature
+      value: ${upper(token)}-$${id}
+"#,
+        )
+        .await;
+
+        assert!(run.passed);
+        let request = &run.results[1].details.request;
+        assert_eq!(
+            format!("http://{}/orders?template=${{name}}", run.addr),
+            request.url
+        );
+        assert_eq!(
+            vec![
+                http::Header::new("Authorization".to_string(), "Bearer abc".to_string()),
+                http::Header::new("X-Signature".to_string(), "ABC-${id}".to_string()),
+            ],
+            request.headers
+        );
+    }
+
+    #[test]
+    fn unresolved_variables_consider_upstream_extractions() {
+        let login = definition_from_yaml(
+            r#"
+id: login
+request:
+  url: http://localhost/login
+response:
+  extract:
+  - name: token
+    field: token
+"#,
+        );
+        let mut orders = definition_from_yaml(
+            r#"
+requires: login
+variables:
+- name: limit
+  value: 10
+stages:
+- request:
+    url: http://localhost/orders?limit=${limit}
+    headers:
+    - header: Authorization
+      value: Bearer ${token}
+  response:
+    extract:
+    - name: orderId
+      field: $[0].id
+- request:
+    url: http://localhost/orders/${orderId}/${ordr}
+    body:
+      total: ${price * 2}
+"#,
+        );
+        orders.index = 1;
+
+        let unresolved = |tests: &[test::Definition]| {
+            unresolved_variables(tests)
+                .into_iter()
+                .map(|(td, u)| (td.index, u.name, u.location))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                (1, "price".to_string(), "stage 2".to_string()),
+                (1, "ordr".to_string(), "stage 2".to_string())
+            ],
+            unresolved(&[login.clone(), orders.clone()])
+        );
+        assert!(unresolved(&[orders])
+            .iter()
+            .any(|(_, name, location)| name == "token" && location == "stage 1"));
+    }
+
+    #[tokio::test]
+    async fn json_report_includes_details_and_redacts_secrets() {
+        let secret = test::Variable {
+            name: "apiKey".to_string(),
+            value: test::ValueOrDatumOrFileOrSecret::Secret {
+                value: test::SecretValue::new("s3cr3t-value"),
+            },
+            source_path: "/".to_string(),
+        };
+        let run = run_yaml_against_with(
+            |_| json_response(r#"{"token":"s3cr3t-value"}"#),
+            r#"
+name: secret
+request:
+  url: http://{addr}/items
+  headers:
+  - header: Authorization
+    value: ${apiKey}
+response:
+  status: 201
+"#,
+            RunOptions {
+                secrets: vec![secret],
+                ..RunOptions::default()
+            },
+        )
+        .await;
+
+        let execution_result = ExecutionResult {
+            test_results: vec![TestResult {
+                test_name: "secret".to_string(),
+                iteration_results: vec![IterationResult::new(0, Ok((run.passed, run.results)))],
+                definition: run.definition,
+            }],
+            runtime: 0,
+        };
+        let report = JsonResultFormatter {}.format(&execution_result).0;
+        assert!(!report.contains("s3cr3t-value"));
+
+        let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(1, report["failed"]);
+        let stage = &report["tests"][0]["iterations"][0]["stages"][0];
+        assert_eq!("failed", stage["status"]);
+        assert_eq!(1, stage["validation"].as_array().unwrap().len());
+        assert_eq!("******", stage["details"]["request"]["headers"][0]["value"]);
+        assert_eq!(200, stage["details"]["actual"]["status"]);
+        assert_eq!("******", stage["details"]["actual"]["body"]["token"]);
+        assert!(stage["details"]["actual"].get("raw_body").is_none());
+    }
+
+    #[tokio::test]
+    async fn json_report_includes_non_json_bodies_as_text() {
+        let secret = test::Variable {
+            name: "apiKey".to_string(),
+            value: test::ValueOrDatumOrFileOrSecret::Secret {
+                value: test::SecretValue::new("s3cr3t-value"),
+            },
+            source_path: "/".to_string(),
+        };
+        let run = run_yaml_against_with(
+            |_| {
+                let body = "<p>key s3cr3t-value</p>";
+                Some(format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                ))
+            },
+            r#"
+name: html
+request:
+  url: http://{addr}/page?key=${apiKey}
+"#,
+            RunOptions {
+                secrets: vec![secret],
+                ..RunOptions::default()
+            },
+        )
+        .await;
+
+        let execution_result = ExecutionResult {
+            test_results: vec![TestResult {
+                test_name: "html".to_string(),
+                iteration_results: vec![IterationResult::new(0, Ok((run.passed, run.results)))],
+                definition: run.definition,
+            }],
+            runtime: 0,
+        };
+        let report = JsonResultFormatter::report(&execution_result);
+        let actual = &report["tests"][0]["iterations"][0]["stages"][0]["details"]["actual"];
+        assert!(actual["body"].is_null());
+        assert_eq!("<p>key ******</p>", actual["raw_body"]);
+    }
+
+    #[test]
+    fn json_report_limits_raw_bodies() {
+        let td = definition_from_yaml("name: bodies\nrequest:\n  url: http://localhost/\n");
+        let stage_result = |raw_body: Vec<u8>| {
+            let mut result = timed_out_stage_result(
+                0,
+                StageType::Normal,
+                None,
+                &td,
+                RequestDetails {
+                    body: serde_json::Value::Null,
+                    headers: Vec::default(),
+                    method: http::Verb::Get.as_method(),
+                    url: "http://localhost/".to_string(),
+                },
+                ExpectedResultData::new(),
+                Instant::now(),
+                None,
+            );
+            result.details.actual =
+                Some(ResponseResultData::new(200, Vec::default(), None, raw_body));
+            result
+        };
+
+        let text = JsonResultFormatter::stage(
+            &td,
+            &stage_result("é".repeat(MAX_RAW_BODY_LENGTH + 5).into_bytes()),
+        );
+        let actual = &text["details"]["actual"];
+        assert_eq!(
+            2 * (MAX_RAW_BODY_LENGTH as u64 + 5),
+            actual["raw_body_length"]
+        );
+        assert_eq!(
+            format!(
+                "{}\n… 5 more characters not shown",
+                "é".repeat(MAX_RAW_BODY_LENGTH)
+            ),
+            actual["raw_body"]
+        );
+
+        let binary = JsonResultFormatter::stage(&td, &stage_result(vec![0xff, 0xfe, 0x00]));
+        let actual = &binary["details"]["actual"];
+        assert_eq!(3, actual["raw_body_length"]);
+        assert!(actual.get("raw_body").is_none());
+    }
+
+    #[test]
+    fn junit_reports_timeouts() {
+        let td = definition_from_yaml("name: slow\nrequest:\n  url: http://localhost/slow\n");
+        let result = timed_out_stage_result(
+            0,
+            StageType::Normal,
+            None,
+            &td,
+            RequestDetails {
+                headers: vec![],
+                url: "http://localhost/slow".to_string(),
+                method: http::Verb::Get.as_method(),
+                body: serde_json::Value::Null,
+            },
+            ExpectedResultData::new(),
+            Instant::now(),
+            None,
+        );
+        let execution_result = ExecutionResult {
+            test_results: vec![TestResult {
+                test_name: "slow".to_string(),
+                iteration_results: vec![IterationResult::new(0, Ok((false, vec![result])))],
+                definition: td.clone(),
+            }],
+            runtime: 0,
+        };
+
+        let junit = JunitResultFormatter {}.format(&execution_result).0;
+        assert!(junit.contains(
+            r#"<failure message="stage_1: Request timed out after 0 milliseconds" type="TimeoutError"/>"#
+        ));
+    }
+
+    #[test]
+    fn junit_is_escaped_and_counted() {
+        let td = definition_from_yaml("name: a<b>\"c\"\nrequest:\n  url: http://localhost/a\n");
+        let mut result = timed_out_stage_result(
+            0,
+            StageType::Normal,
+            None,
+            &td,
+            RequestDetails {
+                headers: vec![],
+                url: "http://localhost/a?x=1&y=2".to_string(),
+                method: http::Verb::Get.as_method(),
+                body: serde_json::Value::Null,
+            },
+            ExpectedResultData::new(),
+            Instant::now(),
+            None,
+        );
+        result.timeout_reason = None;
+        result.validation = Validated::fail("expected \"<ok>\" & got nothing".to_string());
+        result.total_runtime = 1500;
+        let execution_result = ExecutionResult {
+            test_results: vec![TestResult {
+                test_name: td.name.clone().unwrap(),
+                iteration_results: vec![
+                    IterationResult::new(0, Ok((false, vec![result]))),
+                    IterationResult::new_skipped(1, "required test `x` failed"),
+                ],
+                definition: td.clone(),
+            }],
+            runtime: 2000,
+        };
+
+        let junit = JunitResultFormatter {}.format(&execution_result).0;
+        assert!(junit.contains(
+            r#"<testsuites name="jikken" tests="2" failures="1" errors="0" skipped="1" time="2.000">"#
+        ));
+        assert!(junit.contains(
+            r#"<testsuite name="a&lt;b&gt;&quot;c&quot;" tests="2" failures="1" errors="0" skipped="1" time="1.500">"#
+        ));
+        assert_eq!(1, junit.matches("<testsuite ").count());
+        assert!(junit.contains(
+            r#"<testcase name="iteration_1" classname="a&lt;b&gt;&quot;c&quot;" time="1.500">"#
+        ));
+        assert!(junit.contains(
+            r#"<failure message="stage_1: expected &quot;&lt;ok&gt;&quot; &amp; got nothing" type="AssertionError"/>"#
+        ));
+        assert!(junit.contains(
+            r#"<testcase name="iteration_2" classname="a&lt;b&gt;&quot;c&quot;" time="0.000">"#
+        ));
+        assert!(junit.contains(r#"<skipped message="required test `x` failed"/>"#));
+        assert!(junit.contains("http://localhost/a?x=1&amp;y=2"));
+        assert_eq!(1, junit.matches("<system-out>").count());
+    }
 } //mod tests
@@ -0,0 +1,518 @@
+use crate::{
+    executor::runtime_formatter,
+    test::format::{line_changes, Change},
+};
+use serde_json::Value;
+
+// Renders the JSON results report as a single static HTML page. The styles and the
+// small toolbar script are inlined, so the file works offline and as an attachment.
+// Failed tests, iterations and stages start expanded.
+
+// Embedded bodies are cut short, so a large response can't bloat the page
+const MAX_EMBEDDED_LINES: usize = 1000;
+const MAX_LINE_LENGTH: usize = 500;
+
+const STYLE: &str = r#"
+body { font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; margin: 0; color: #1f2328; background: #f6f8fa; }
+header { background: #24292f; color: #fff; padding: 16px 24px; }
+header h1 { margin: 0 0 8px; font-size: 20px; }
+.summary span { margin-right: 16px; }
+.toolbar { padding: 12px 24px; }
+.toolbar button { margin-right: 8px; }
+main { padding: 0 24px 24px; }
+details { background: #fff; border: 1px solid #d0d7de; border-radius: 6px; margin: 6px 0; }
+details details { margin-left: 16px; }
+summary { cursor: pointer; padding: 6px 10px; }
+.badge { display: inline-block; min-width: 56px; text-align: center; border-radius: 4px; padding: 1px 6px; font-size: 12px; font-weight: 600; text-transform: uppercase; color: #fff; margin-right: 6px; }
+.badge.passed { background: #1a7f37; }
+.badge.failed { background: #cf222e; }
+.badge.skipped { background: #9a6700; }
+.time, .meta { color: #656d76; font-size: 12px; margin-left: 8px; }
+.content { padding: 0 10px 10px; }
+.messages { color: #cf222e; margin: 4px 0; }
+.note { color: #656d76; margin: 4px 0; }
+pre { background: #f6f8fa; padding: 8px; overflow-x: auto; margin: 0; }
+table.diff { border-collapse: collapse; width: 100%; font-family: monospace; font-size: 12px; margin: 8px 0; }
+table.diff th { text-align: left; background: #eaeef2; padding: 4px 8px; }
+table.diff td { white-space: pre; vertical-align: top; padding: 0 8px; width: 50%; border-left: 1px solid #d0d7de; }
+table.diff td.removed { background: #ffebe9; }
+table.diff td.added { background: #dafbe1; }
+.failures-only details.test.passed, .failures-only details.test.skipped { display: none; }
+"#;
+
+const SCRIPT: &str = r#"
+document.querySelectorAll("button[data-open]").forEach(function (button) {
+  button.addEventListener("click", function () {
+    var open = button.getAttribute("data-open") === "true";
+    document.querySelectorAll("details").forEach(function (d) { d.open = open; });
+  });
+});
+document.getElementById("failures-only").addEventListener("change", function (e) {
+  document.body.classList.toggle("failures-only", e.target.checked);
+});
+"#;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn badge(status: &str) -> String {
+    format!(r#"<span class="badge {0}">{0}</span>"#, escape(status))
+}
+
+fn open(status: &str) -> &'static str {
+    if status == "failed" {
+        " open"
+    } else {
+        ""
+    }
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+//The lines of a body, each shortened to MAX_LINE_LENGTH and at most
+//MAX_EMBEDDED_LINES of them, followed by a note of how many were left out
+fn embedded_lines(text: &str) -> Vec<String> {
+    let total = text.lines().count();
+    let mut lines: Vec<String> = text
+        .lines()
+        .take(MAX_EMBEDDED_LINES)
+        .map(|line| {
+            if line.chars().count() <= MAX_LINE_LENGTH {
+                return line.to_string();
+            }
+            let mut shortened: String = line.chars().take(MAX_LINE_LENGTH).collect();
+            shortened.push('…');
+            shortened
+        })
+        .collect();
+    if total > MAX_EMBEDDED_LINES {
+        lines.push(format!(
+            "… {} more lines not shown",
+            total - MAX_EMBEDDED_LINES
+        ));
+    }
+    lines
+}
+
+fn headers(value: &Value) -> String {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|h| format!("{}: {}\n", text(&h["header"]), text(&h["value"])))
+        .collect()
+}
+
+fn payload(title: &str, first_line: String, message: &Value) -> String {
+    let mut content = format!("{}\n{}", first_line, headers(&message["headers"]));
+    // responses which aren't JSON are reported with their raw body as text, binary
+    // ones by their length alone
+    let body = if message["body"].is_null() && message["raw_body"].is_null() {
+        match message["raw_body_length"].as_u64() {
+            Some(length) => format!("({} bytes of binary content not shown)", length),
+            None => String::new(),
+        }
+    } else if message["body"].is_null() {
+        text(&message["raw_body"])
+    } else {
+        pretty(&message["body"])
+    };
+    if !body.is_empty() {
+        content.push('\n');
+        content.push_str(&embedded_lines(&body).join("\n"));
+    }
+    format!(
+        "<details class=\"payload\"><summary>{}</summary><pre>{}</pre></details>",
+        escape(title),
+        escape(&content)
+    )
+}
+
+fn request(title: &str, request: &Value) -> String {
+    payload(
+        title,
+        format!("{} {}", text(&request["method"]), text(&request["url"])),
+        request,
+    )
+}
+
+fn response(title: &str, response: &Value) -> String {
+    payload(
+        title,
+        format!("HTTP {}", text(&response["status"])),
+        response,
+    )
+}
+
+fn diff_cell(change: Option<(Change, &str)>) -> String {
+    match change {
+        Some((Change::Removed, line)) => format!("<td class=\"removed\">{}</td>", escape(line)),
+        Some((Change::Added, line)) => format!("<td class=\"added\">{}</td>", escape(line)),
+        Some((Change::Same, line)) => format!("<td>{}</td>", escape(line)),
+        None => "<td></td>".to_string(),
+    }
+}
+
+//Lines removed on the left are shown next to the lines added in their place
+pub fn side_by_side(left_title: &str, left: &Value, right_title: &str, right: &Value) -> String {
+    let (left, right) = (
+        embedded_lines(&pretty(left)),
+        embedded_lines(&pretty(right)),
+    );
+    let old: Vec<&str> = left.iter().map(String::as_str).collect();
+    let new: Vec<&str> = right.iter().map(String::as_str).collect();
+
+    let mut rows: Vec<String> = Vec::new();
+    let mut removed: Vec<&str> = Vec::new();
+    let mut added: Vec<&str> = Vec::new();
+    let flush = |removed: &mut Vec<&str>, added: &mut Vec<&str>, rows: &mut Vec<String>| {
+        for k in 0..removed.len().max(added.len()) {
+            rows.push(format!(
+                "<tr>{}{}</tr>",
+                diff_cell(removed.get(k).map(|l| (Change::Removed, *l))),
+                diff_cell(added.get(k).map(|l| (Change::Added, *l)))
+            ));
+        }
+        removed.clear();
+        added.clear();
+    };
+
+    for (change, i, j) in line_changes(&old, &new) {
+        match change {
+            Change::Removed => removed.push(old[i]),
+            Change::Added => added.push(new[j]),
+            Change::Same => {
+                flush(&mut removed, &mut added, &mut rows);
+                rows.push(format!(
+                    "<tr>{}{}</tr>",
+                    diff_cell(Some((Change::Same, old[i]))),
+                    diff_cell(Some((Change::Same, new[j])))
+                ));
+            }
+        }
+    }
+    flush(&mut removed, &mut added, &mut rows);
+
+    format!(
+        "<table class=\"diff\"><thead><tr><th>{}</th><th>{}</th></tr></thead><tbody>{}</tbody></table>",
+        escape(left_title),
+        escape(right_title),
+        rows.join("")
+    )
+}
+
+fn stage(stage: &Value) -> String {
+    let status = text(&stage["status"]);
+    let details = &stage["details"];
+    let title = match stage["type"].as_str() {
+        Some("setup") => "Setup".to_string(),
+        Some("cleanup") => "Cleanup".to_string(),
+        _ => format!("Stage {}", stage["stage"].as_u64().unwrap_or_default() + 1),
+    };
+    let name = stage["name"]
+        .as_str()
+        .map(|n| format!(" {}", escape(n)))
+        .unwrap_or_default();
+
+    let mut content = String::new();
+    if let Some(reason) = stage["timeout_reason"].as_str() {
+        content.push_str(&format!("<p class=\"messages\">{}</p>", escape(reason)));
+    }
+    let messages: Vec<String> = stage["validation"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|m| format!("<li>{}</li>", escape(&text(m))))
+        .collect();
+    if !messages.is_empty() {
+        content.push_str(&format!(
+            "<ul class=\"messages\">{}</ul>",
+            messages.join("")
+        ));
+    }
+
+    content.push_str(&request("Request", &details["request"]));
+    if !details["actual"].is_null() {
+        content.push_str(&response("Response", &details["actual"]));
+    }
+    if !details["compare_request"].is_null() {
+        content.push_str(&request("Compare request", &details["compare_request"]));
+    }
+    if !details["compare_actual"].is_null() {
+        content.push_str(&response("Compare response", &details["compare_actual"]));
+    }
+
+    if status == "failed" {
+        let actual = &details["actual"]["body"];
+        let expected = &details["expected"]["body"]["body"];
+        let compared = &details["compare_actual"]["body"];
+        if !expected.is_null() && !details["actual"].is_null() && expected != actual {
+            content.push_str(&side_by_side(
+                "Expected body",
+                expected,
+                "Actual body",
+                actual,
+            ));
+        } else if !details["compare_actual"].is_null() && compared != actual {
+            content.push_str(&side_by_side(
+                "Response body",
+                actual,
+                "Compare response body",
+                compared,
+            ));
+        }
+    }
+
+    let attempts: Vec<String> = stage["previous_attempts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(self::stage)
+        .collect();
+    if !attempts.is_empty() {
+        content.push_str(&format!(
+            "<details class=\"attempts\"><summary>{} earlier attempt{}</summary>{}</details>",
+            attempts.len(),
+            if attempts.len() == 1 { "" } else { "s" },
+            attempts.join("")
+        ));
+    }
+
+    format!(
+        "<details class=\"stage {}\"{}><summary>{}{}{}<span class=\"time\">{}</span></summary><div class=\"content\">{}</div></details>",
+        escape(&status),
+        open(&status),
+        badge(&status),
+        title,
+        name,
+        runtime_formatter(stage["runtime"].as_u64().unwrap_or_default() as u32),
+        content
+    )
+}
+
+fn stages_runtime(iteration: &Value) -> u64 {
+    iteration["stages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|s| s["runtime"].as_u64())
+        .sum()
+}
+
+fn iteration(iteration: &Value) -> String {
+    let status = text(&iteration["status"]);
+    let mut content = String::new();
+    for note in [&iteration["skip_reason"], &iteration["error"]] {
+        if let Some(note) = note.as_str() {
+            content.push_str(&format!("<p class=\"note\">{}</p>", escape(note)));
+        }
+    }
+    for s in iteration["stages"].as_array().into_iter().flatten() {
+        content.push_str(&stage(s));
+    }
+    for attempt in iteration["previous_attempts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        content.push_str(&format!(
+            "<details class=\"attempts\"><summary>Earlier attempt</summary>{}</details>",
+            self::iteration(attempt)
+        ));
+    }
+
+    format!(
+        "<details class=\"iteration {}\"{}><summary>{}Iteration {}<span class=\"time\">{}</span></summary><div class=\"content\">{}</div></details>",
+        escape(&status),
+        open(&status),
+        badge(&status),
+        text(&iteration["iteration"]),
+        runtime_formatter(stages_runtime(iteration) as u32),
+        content
+    )
+}
+
+fn test(test: &Value) -> String {
+    let iterations: Vec<&Value> = test["iterations"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .collect();
+    let statuses: Vec<&str> = iterations
+        .iter()
+        .filter_map(|i| i["status"].as_str())
+        .collect();
+    let status = if statuses.contains(&"failed") {
+        "failed"
+    } else if statuses.contains(&"passed") {
+        "passed"
+    } else {
+        "skipped"
+    };
+    let runtime: u64 = iterations.iter().map(|i| stages_runtime(i)).sum();
+    let meta: Vec<String> = [&test["id"], &test["file"]]
+        .into_iter()
+        .filter_map(|v| v.as_str())
+        .map(escape)
+        .collect();
+
+    format!(
+        "<details class=\"test {0}\"{1}><summary>{2}{3}<span class=\"meta\">{4}</span><span class=\"time\">{5}</span></summary><div class=\"content\">{6}</div></details>",
+        status,
+        open(status),
+        badge(status),
+        escape(&text(&test["name"])),
+        meta.join(" &middot; "),
+        runtime_formatter(runtime as u32),
+        iterations.into_iter().map(iteration).collect::<String>()
+    )
+}
+
+pub fn render(report: &Value) -> String {
+    let count = |key: &str| report[key].as_u64().unwrap_or_default();
+    let tests: String = report["tests"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(test)
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Jikken test report</title>
+<style>{}</style>
+</head>
+<body>
+<header>
+<h1>Jikken test report</h1>
+<div class="summary"><span>{} passed</span><span>{} failed</span><span>{} skipped</span><span>Runtime {}</span><span>Generated {}</span></div>
+</header>
+<div class="toolbar"><button data-open="true">Expand all</button><button data-open="false">Collapse all</button><label><input type="checkbox" id="failures-only"> Failures only</label></div>
+<main>
+{}
+</main>
+<script>{}</script>
+</body>
+</html>
+"#,
+        STYLE,
+        count("passed"),
+        count("failed"),
+        count("skipped"),
+        runtime_formatter(count("runtime") as u32),
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        tests,
+        SCRIPT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn side_by_side_pairs_changed_lines() {
+        let table = side_by_side(
+            "Expected",
+            &serde_json::json!({"id": 1, "name": "a<b"}),
+            "Actual",
+            &serde_json::json!({"id": 2, "name": "a<b"}),
+        );
+        assert!(table.contains(
+            r#"<tr><td class="removed">  &quot;id&quot;: 1,</td><td class="added">  &quot;id&quot;: 2,</td></tr>"#
+        ));
+        assert!(table.contains(
+            "<tr><td>  &quot;name&quot;: &quot;a&lt;b&quot;</td><td>  &quot;name&quot;: &quot;a&lt;b&quot;</td></tr>"
+        ));
+    }
+
+    #[test]
+    fn large_bodies_are_truncated() {
+        let expected: Vec<u32> = (0..5000).collect();
+        let actual: Vec<u32> = (1..5001).collect();
+        let long = "x".repeat(2000);
+        let table = side_by_side(
+            "Expected",
+            &serde_json::json!({"a_note": long, "items": expected}),
+            "Actual",
+            &serde_json::json!({"a_note": long, "items": actual}),
+        );
+        assert!(table.contains("… 4005 more lines not shown"));
+        assert!(table.contains(&format!("{}…", "x".repeat(MAX_LINE_LENGTH - 13))));
+        assert!(!table.contains(&"x".repeat(MAX_LINE_LENGTH)));
+        assert!(table.matches("<tr>").count() <= 2 * MAX_EMBEDDED_LINES + 3);
+    }
+
+    #[test]
+    fn text_bodies_are_shown_raw() {
+        let html = response(
+            "Response",
+            &serde_json::json!({"status": 200, "headers": [], "body": null, "raw_body": "<p>hi</p>"}),
+        );
+        assert!(html.contains("HTTP 200\n\n&lt;p&gt;hi&lt;/p&gt;</pre>"));
+
+        let html = response(
+            "Response",
+            &serde_json::json!({"status": 200, "headers": [], "body": null, "raw_body_length": 3}),
+        );
+        assert!(html.contains("HTTP 200\n\n(3 bytes of binary content not shown)</pre>"));
+    }
+
+    #[test]
+    fn render_is_self_contained() {
+        let report = serde_json::json!({
+            "runtime": 12,
+            "passed": 0,
+            "failed": 1,
+            "skipped": 0,
+            "tests": [{
+                "name": "orders",
+                "id": null,
+                "file": "orders.jkt",
+                "iterations": [{
+                    "iteration": 1,
+                    "status": "failed",
+                    "stages": [{
+                        "stage": 0,
+                        "type": "stage",
+                        "status": "failed",
+                        "runtime": 12,
+                        "validation": ["Expected body did not match"],
+                        "details": {
+                            "request": {"method": "GET", "url": "http://localhost/orders", "headers": [], "body": null},
+                            "expected": {"body": {"body": {"count": 1}}},
+                            "actual": {"status": 200, "headers": [], "body": {"count": 2}}
+                        },
+                        "previous_attempts": []
+                    }],
+                    "previous_attempts": []
+                }]
+            }]
+        });
+        let html = render(&report);
+        assert!(!html.contains("<script src"));
+        assert!(!html.contains("<link"));
+        assert!(html.contains(r#"<details class="test failed" open>"#));
+        assert!(html.contains("GET http://localhost/orders"));
+        assert!(html.contains("<li>Expected body did not match</li>"));
+        assert!(html.contains(r#"<td class="added">  &quot;count&quot;: 2</td>"#));
+    }
+}
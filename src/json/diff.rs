@@ -0,0 +1,246 @@
+use serde_json::Value;
+
+// Per-path differences between an expected and an actual body, used to explain
+// a failed body comparison. Paths are JSONPath, so they can be pasted into
+// `ignore` or `assertions`. Outside strict mode the actual body may contain
+// keys and trailing array elements the expected body does not mention, as
+// with the comparison itself.
+
+const MAX_DIFFERENCES: usize = 20;
+const MAX_VALUE_LENGTH: usize = 80;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    Added {
+        path: String,
+        value: Value,
+    },
+    Removed {
+        path: String,
+        value: Value,
+    },
+    Changed {
+        path: String,
+        expected: Value,
+        actual: Value,
+    },
+}
+
+fn child_key(path: &str, key: &str) -> String {
+    let identifier = key
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if identifier {
+        format!("{path}.{key}")
+    } else {
+        format!(
+            "{path}['{}']",
+            key.replace('\\', "\\\\").replace('\'', "\\'")
+        )
+    }
+}
+
+fn collect(
+    path: String,
+    expected: &Value,
+    actual: &Value,
+    strict: bool,
+    differences: &mut Vec<Difference>,
+) {
+    match (expected, actual) {
+        (Value::Object(expected_map), Value::Object(actual_map)) => {
+            for (key, expected_value) in expected_map {
+                let child = child_key(&path, key);
+                match actual_map.get(key) {
+                    Some(actual_value) => {
+                        collect(child, expected_value, actual_value, strict, differences)
+                    }
+                    None => differences.push(Difference::Removed {
+                        path: child,
+                        value: expected_value.clone(),
+                    }),
+                }
+            }
+            if strict {
+                for (key, actual_value) in actual_map {
+                    if !expected_map.contains_key(key) {
+                        differences.push(Difference::Added {
+                            path: child_key(&path, key),
+                            value: actual_value.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        (Value::Array(expected_items), Value::Array(actual_items)) => {
+            for (index, expected_value) in expected_items.iter().enumerate() {
+                let child = format!("{path}[{index}]");
+                match actual_items.get(index) {
+                    Some(actual_value) => {
+                        collect(child, expected_value, actual_value, strict, differences)
+                    }
+                    None => differences.push(Difference::Removed {
+                        path: child,
+                        value: expected_value.clone(),
+                    }),
+                }
+            }
+            if strict {
+                for (index, actual_value) in
+                    actual_items.iter().enumerate().skip(expected_items.len())
+                {
+                    differences.push(Difference::Added {
+                        path: format!("{path}[{index}]"),
+                        value: actual_value.clone(),
+                    });
+                }
+            }
+        }
+        _ if expected != actual => differences.push(Difference::Changed {
+            path,
+            expected: expected.clone(),
+            actual: actual.clone(),
+        }),
+        _ => {}
+    }
+}
+
+pub fn diff(expected: &Value, actual: &Value, strict: bool) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    collect("$".to_string(), expected, actual, strict, &mut differences);
+    differences
+}
+
+fn truncated(value: &Value) -> String {
+    let text = value.to_string();
+    if text.chars().count() <= MAX_VALUE_LENGTH {
+        return text;
+    }
+    let mut shortened: String = text.chars().take(MAX_VALUE_LENGTH).collect();
+    shortened.push('…');
+    shortened
+}
+
+impl Difference {
+    fn describe(&self) -> String {
+        match self {
+            Difference::Added { path, value } => format!("  + {path}: {}", truncated(value)),
+            Difference::Removed { path, value } => format!("  - {path}: {}", truncated(value)),
+            Difference::Changed {
+                path,
+                expected,
+                actual,
+            } => format!(
+                "  ~ {path}: {} → {}",
+                truncated(expected),
+                truncated(actual)
+            ),
+        }
+    }
+}
+
+//One line per difference, listing at most the first MAX_DIFFERENCES with long
+//values shortened, so large bodies stay readable
+pub fn describe(differences: &[Difference]) -> String {
+    let mut lines = vec![format!(
+        "{} difference(s) (- expected but missing, + unexpected, ~ expected → actual):",
+        differences.len()
+    )];
+    lines.extend(
+        differences
+            .iter()
+            .take(MAX_DIFFERENCES)
+            .map(Difference::describe),
+    );
+    if differences.len() > MAX_DIFFERENCES {
+        lines.push(format!(
+            "  ... and {} more",
+            differences.len() - MAX_DIFFERENCES
+        ));
+    }
+    lines.join("\n")
+}
+
+//Colours the difference lines of a message for a terminal. Each line switches
+//back to red afterwards, as messages containing a diff are logged as errors
+pub fn colourise(message: &str) -> String {
+    message
+        .lines()
+        .map(|line| {
+            let colour = if line.starts_with("  + $") {
+                "\x1b[32m"
+            } else if line.starts_with("  - $") {
+                "\x1b[91m"
+            } else if line.starts_with("  ~ $") {
+                "\x1b[33m"
+            } else {
+                return line.to_string();
+            };
+            format!("{colour}{line}\x1b[31m")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::json::diff::{colourise, describe, diff, Difference};
+    use serde_json::json;
+
+    #[test]
+    fn diff_reports_each_path() {
+        let expected = json!({"user": {"name": "bob", "roles": ["admin", "dev"]}, "a b": 1});
+        let actual = json!({"user": {"name": "alice", "roles": ["admin"]}, "extra": true});
+
+        assert_eq!(
+            vec![
+                Difference::Removed {
+                    path: "$['a b']".to_string(),
+                    value: json!(1)
+                },
+                Difference::Changed {
+                    path: "$.user.name".to_string(),
+                    expected: json!("bob"),
+                    actual: json!("alice")
+                },
+                Difference::Removed {
+                    path: "$.user.roles[1]".to_string(),
+                    value: json!("dev")
+                },
+                Difference::Added {
+                    path: "$.extra".to_string(),
+                    value: json!(true)
+                },
+            ],
+            diff(&expected, &actual, true)
+        );
+        assert_eq!(3, diff(&expected, &actual, false).len());
+        assert!(diff(&json!({"a": 1}), &json!({"a": 1, "b": [2]}), false).is_empty());
+    }
+
+    #[test]
+    fn describe_truncates_large_payloads() {
+        let expected: Vec<u32> = (0..30).collect();
+        let actual: Vec<u32> = (100..130).collect();
+        let description = describe(&diff(&json!(expected), &json!(actual), true));
+        assert!(description.starts_with("30 difference(s)"));
+        assert!(description.contains("  ~ $[0]: 0 → 100"));
+        assert!(description.ends_with("  ... and 10 more"));
+
+        let long = "x".repeat(200);
+        let description = describe(&diff(&json!({ "a": long }), &json!({}), false));
+        assert!(description.contains('…'));
+        assert!(description.lines().all(|l| l.chars().count() < 100));
+    }
+
+    #[test]
+    fn colourise_only_touches_difference_lines() {
+        let message = "Expected body did not match\n  ~ $.a: 1 → 2\n  + $.b: 3";
+        assert_eq!(
+            "Expected body did not match\n\x1b[33m  ~ $.a: 1 → 2\x1b[31m\n\x1b[32m  + $.b: 3\x1b[31m",
+            colourise(message)
+        );
+    }
+}
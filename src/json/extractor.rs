@@ -1,11 +1,23 @@
 use serde_json::{json, Map, Value};
+use serde_json_path::JsonPath;
 use std::error::Error;
 
+// JSONPath (RFC 9535) queries are accepted alongside the dotted paths used by
+// extract and ignore. A path is treated as JSONPath when it is `$` or starts
+// with `$.` or `$[`, so dotted keys such as `$id` keep working.
+pub fn is_json_path(path: &str) -> bool {
+    path == "$" || path.starts_with("$.") || path.starts_with("$[")
+}
+
 pub fn extract_json(
     path: &str,
     depth: usize,
     json: serde_json::Value,
 ) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    if depth == 0 && is_json_path(path) {
+        return extract_json_path(path, &json);
+    }
+
     let path_segments: Vec<&str> = path.split('.').collect();
 
     // println!("path ({}), depth({}), json({})", path, depth, json);
@@ -95,3 +107,127 @@ pub fn extract_json(
         _ => Err(Box::from("path not found".to_string())),
     }
 }
+
+// A singular query returns its node as is. Any other query returns an array, even
+// when it matches a single node, so the extracted type doesn't depend on the data
+fn extract_json_path(
+    path: &str,
+    json: &serde_json::Value,
+) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    let nodes = JsonPath::parse(path)?.query(json).all();
+    match nodes.as_slice() {
+        [] => Err(Box::from("path not found".to_string())),
+        [node] if is_singular(path) => Ok((*node).clone()),
+        _ => Ok(json!(nodes)),
+    }
+}
+
+// RFC 9535 singular queries are made of single names and indices only, such as
+// `$.items[0]['id']`, and select at most one node. Wildcards, slices, filters,
+// unions and descendant segments are not singular. serde_json_path doesn't expose
+// this, so the already validated path is scanned here
+fn is_singular(path: &str) -> bool {
+    let mut rest = &path[1..];
+    while !rest.is_empty() {
+        if rest.starts_with("..") {
+            return false;
+        } else if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if &after[..end] == "*" {
+                return false;
+            }
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let selector = after.trim_start();
+            let Some(end) = selector_end(selector) else {
+                return false;
+            };
+            let Some(after) = selector[end..].trim_start().strip_prefix(']') else {
+                return false;
+            };
+            rest = after;
+        } else {
+            return false;
+        }
+    }
+
+    true
+}
+
+// The length of the name or index selector starting `selector`, None for any other selector
+fn selector_end(selector: &str) -> Option<usize> {
+    let mut chars = selector.char_indices();
+    match chars.next()? {
+        (_, quote @ ('\'' | '"')) => {
+            let mut escaped = false;
+            for (i, c) in chars {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    _ if c == quote => return Some(i + 1),
+                    _ => {}
+                }
+            }
+            None
+        }
+        (_, c) if c == '-' || c.is_ascii_digit() => Some(
+            selector
+                .find(|c: char| c != '-' && !c.is_ascii_digit())
+                .unwrap_or(selector.len()),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::json::extractor::{extract_json, is_json_path, is_singular};
+    use serde_json::json;
+
+    #[test]
+    fn detects_json_paths() {
+        assert!(is_json_path("$"));
+        assert!(is_json_path("$.items[0].id"));
+        assert!(is_json_path("$['items']"));
+        assert!(!is_json_path("items.id"));
+        assert!(!is_json_path("$id"));
+    }
+
+    #[test]
+    fn dotted_paths_fan_out_over_arrays() {
+        let data = json!({"items": [{"id": 1}, {"id": 2}]});
+        assert_eq!(json!([1, 2]), extract_json("items.id", 0, data).unwrap());
+    }
+
+    #[test]
+    fn json_paths_select_matching_items() {
+        let data = json!({"items": [{"id": 1, "name": "w"}, {"id": 2, "name": "x"}]});
+        assert_eq!(
+            json!([2]),
+            extract_json("$.items[?@.name == 'x'].id", 0, data.clone()).unwrap()
+        );
+        assert_eq!(
+            json!(2),
+            extract_json("$.items[1].id", 0, data.clone()).unwrap()
+        );
+        assert_eq!(
+            json!([1, 2]),
+            extract_json("$.items[*].id", 0, data.clone()).unwrap()
+        );
+        assert!(extract_json("$.items[?@.name == 'y'].id", 0, data).is_err());
+    }
+
+    #[test]
+    fn detects_singular_queries() {
+        assert!(is_singular("$"));
+        assert!(is_singular("$.items[0].id"));
+        assert!(is_singular("$['items'][-1][ \"a]b\" ]"));
+        assert!(is_singular("$['it\\'ems']"));
+        assert!(!is_singular("$.items[*].id"));
+        assert!(!is_singular("$.items.*"));
+        assert!(!is_singular("$..id"));
+        assert!(!is_singular("$.items[0:1]"));
+        assert!(!is_singular("$.items[0,1]"));
+        assert!(!is_singular("$.items[?@.id == 1]"));
+    }
+}
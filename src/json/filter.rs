@@ -1,4 +1,6 @@
+use crate::json::extractor::is_json_path;
 use serde_json::{json, Map, Value};
+use serde_json_path::{JsonPath, PathElement};
 use std::error::Error;
 
 pub fn filter_json(
@@ -6,6 +8,12 @@ pub fn filter_json(
     depth: usize,
     json: serde_json::Value,
 ) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    if depth == 0 && is_json_path(path) {
+        let mut json = json;
+        remove_json_path(&JsonPath::parse(path)?, &mut json);
+        return Ok(json);
+    }
+
     let path_segments: Vec<&str> = path.split('.').collect();
     if depth + 1 > path_segments.len() {
         return Ok(json);
@@ -78,9 +86,77 @@ pub fn filter_json(
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Location {
+    Index(usize),
+    Member(String),
+}
+
+// Removes every node matched by the query. The root itself can't be removed.
+fn remove_json_path(path: &JsonPath, json: &mut Value) {
+    let mut locations: Vec<Vec<Location>> = path
+        .query_located(json)
+        .locations()
+        .map(|l| {
+            l.iter()
+                .map(|e| match e {
+                    PathElement::Name(n) => Location::Member(n.to_string()),
+                    PathElement::Index(i) => Location::Index(*i),
+                })
+                .collect()
+        })
+        .collect();
+    locations.sort();
+    locations.dedup();
+
+    // deepest and highest indexed first, so earlier removals don't move later ones
+    for location in locations.into_iter().rev() {
+        let Some((last, parent_location)) = location.split_last() else {
+            continue;
+        };
+
+        match (node_mut(json, parent_location), last) {
+            (Some(Value::Array(a)), Location::Index(i)) if *i < a.len() => {
+                a.remove(*i);
+            }
+            (Some(Value::Object(o)), Location::Member(k)) => {
+                o.remove(k);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn node_mut<'a>(json: &'a mut Value, location: &[Location]) -> Option<&'a mut Value> {
+    location
+        .iter()
+        .try_fold(json, |current, l| match (current, l) {
+            (Value::Array(a), Location::Index(i)) => a.get_mut(*i),
+            (Value::Object(o), Location::Member(k)) => o.get_mut(k),
+            _ => None,
+        })
+}
+
 #[cfg(test)]
 mod test {
     use crate::json::filter::filter_json;
+    use serde_json::json;
+
+    #[test]
+    fn removes_json_path_matches() {
+        let json = json!({
+            "items": [
+                {"id": 1, "name": "x", "created": "now"},
+                {"id": 2, "name": "y", "created": "then"},
+                {"id": 3, "name": "z", "created": "soon"}
+            ],
+            "total": 3
+        });
+
+        let json = filter_json("$.items[?@.id > 1]", 0, json).unwrap();
+        let json = filter_json("$..created", 0, json).unwrap();
+        assert_eq!(json!({"items": [{"id": 1, "name": "x"}], "total": 3}), json);
+    }
 
     #[tokio::test]
     async fn object() {
@@ -226,6 +302,41 @@ mod test {
         assert_eq!(result, expected_result);
     }
 
+    #[tokio::test]
+    async fn json_path() {
+        let input_data = r#"{
+            "test": "name",
+            "items": [{
+                "id": 1,
+                "updated": "today"
+            },
+            {
+                "id": 2,
+                "updated": "yesterday"
+            }]
+        }"#;
+
+        let expected_data = r#"{
+            "test": "name",
+            "items": [{
+                "id": 1,
+                "updated": "today"
+            },
+            {
+                "id": 2
+            }]
+        }"#;
+
+        let result = filter_json(
+            "$.items[?@.id == 2].updated",
+            0,
+            serde_json::from_str(input_data).unwrap(),
+        )
+        .unwrap();
+        let expected_result: serde_json::Value = serde_json::from_str(expected_data).unwrap();
+        assert_eq!(result, expected_result);
+    }
+
     #[tokio::test]
     async fn no_matches() {
         let input_data = r#"[{
@@ -1,26 +1,30 @@
 mod config;
 mod errors;
 mod executor;
+mod html_report;
 mod json;
 mod logger;
 mod machine;
 mod new;
+mod tags;
 mod telemetry;
 mod test;
 mod updater;
 mod validated;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use glob::{glob_with, MatchOptions};
 use log::{debug, error, info, warn, Level, LevelFilter};
 use logger::SimpleLogger;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashSet,
     error::Error,
     path::{Path, PathBuf},
 };
 use telemetry::PlatformIdFailure;
-use tokio::{fs, io::AsyncWriteExt};
+use test::validation::{Diagnostic, Severity};
+use tokio::fs;
 use ulid::Ulid;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -30,14 +34,49 @@ const IGNORE_FILE: &str = ".jikkenignore";
 pub enum ExecutionMode {
     Run,
     Dryrun,
-    List,
-    Format,
-    Validate(bool),
+    List(ListFormat),
+    Graph(GraphFormat),
+    Format(bool),
+    Validate(bool, ValidationFormat),
+}
+
+//The tests a command works on, the reports it writes and how many tests run at once
+#[derive(Default)]
+pub struct RunOptions {
+    pub paths: Vec<String>,
+    pub recursive: bool,
+    pub tags: Vec<String>,
+    pub tags_or: bool,
+    pub tag_expr: Option<String>,
+    pub ids: Vec<String>,
+    pub name_regex: Option<String>,
+    pub reports: executor::ReportFiles,
+    pub jobs: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ValidationFormat {
+    Text,
+    Json,
+    Sarif,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ListFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
 }
 
 pub enum TagMode {
     AND,
     OR,
+    Expression(tags::TagExpression),
 }
 
 #[derive(Parser, Serialize, Deserialize)]
@@ -99,9 +138,43 @@ pub enum Commands {
         #[arg(long, default_value_t = false)]
         tags_or: bool,
 
+        /// Select tests with a boolean tag expression, e.g. "(payments or billing) and not slow"
+        /// {n}Supports and, or, not and parentheses. Can't be combined with --tag or --tags-or
+        #[arg(long, conflicts_with_all = ["tag", "tags_or"])]
+        tag_expr: Option<String>,
+
+        /// Select tests by id, may be repeated
+        /// {n}Tests required by the selected ones are run too
+        #[arg(long = "id", name = "id")]
+        ids: Vec<String>,
+
+        /// Select tests whose name matches a regular expression
+        #[arg(long)]
+        name_regex: Option<String>,
+
         /// Output results in junit format to specified file
         #[arg(long = "junit", name = "junit_file")]
         junit: Option<String>,
+
+        /// Output full results, including requests and responses, as JSON to specified file
+        /// {n}Secret values are redacted
+        #[arg(long = "report-json", name = "report_json_file")]
+        report_json: Option<String>,
+
+        /// Output results as a single self-contained HTML page to specified file
+        /// {n}Secret values are redacted
+        #[arg(long = "report-html", name = "report_html_file")]
+        report_html: Option<String>,
+
+        /// Replace report files that already exist instead of failing to write them
+        #[arg(long = "overwrite-reports")]
+        overwrite_reports: bool,
+
+        /// Maximum number of tests to run concurrently
+        /// {n}Tests only run alongside tests whose requirements are already satisfied
+        /// {n}Overrides the parallelism setting from the configuration file
+        #[arg(short = 'j', long = "jobs", name = "jobs")]
+        jobs: Option<u32>,
     },
 
     /// Execute tests without calling API endpoints
@@ -125,9 +198,37 @@ pub enum Commands {
         #[arg(long, default_value_t = false)]
         tags_or: bool,
 
+        /// Select tests with a boolean tag expression, e.g. "(payments or billing) and not slow"
+        /// {n}Supports and, or, not and parentheses. Can't be combined with --tag or --tags-or
+        #[arg(long, conflicts_with_all = ["tag", "tags_or"])]
+        tag_expr: Option<String>,
+
+        /// Select tests by id, may be repeated
+        /// {n}Tests required by the selected ones are run too
+        #[arg(long = "id", name = "id")]
+        ids: Vec<String>,
+
+        /// Select tests whose name matches a regular expression
+        #[arg(long)]
+        name_regex: Option<String>,
+
         /// Output results in junit format to specified file
         #[arg(long = "junit", name = "junit_file")]
         junit: Option<String>,
+
+        /// Output full results, including requests and responses, as JSON to specified file
+        /// {n}Secret values are redacted
+        #[arg(long = "report-json", name = "report_json_file")]
+        report_json: Option<String>,
+
+        /// Output results as a single self-contained HTML page to specified file
+        /// {n}Secret values are redacted
+        #[arg(long = "report-html", name = "report_html_file")]
+        report_html: Option<String>,
+
+        /// Replace report files that already exist instead of failing to write them
+        #[arg(long = "overwrite-reports")]
+        overwrite_reports: bool,
     },
 
     /// List test files
@@ -150,6 +251,63 @@ pub enum Commands {
         /// Toggle tag matching logic to select tests matching any of the given tags
         #[arg(long, default_value_t = false)]
         tags_or: bool,
+
+        /// Select tests with a boolean tag expression, e.g. "(payments or billing) and not slow"
+        /// {n}Supports and, or, not and parentheses. Can't be combined with --tag or --tags-or
+        #[arg(long, conflicts_with_all = ["tag", "tags_or"])]
+        tag_expr: Option<String>,
+
+        /// Select tests by id, may be repeated
+        #[arg(long = "id", name = "id")]
+        ids: Vec<String>,
+
+        /// Select tests whose name matches a regular expression
+        #[arg(long)]
+        name_regex: Option<String>,
+
+        /// Output format of the list
+        /// {n}json includes ids, requirements, iterations, stage counts, URLs and disabled flags
+        #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+        format: ListFormat,
+    },
+
+    /// Print the graph of test requirements
+    Graph {
+        /// The path(s) to search for test files
+        /// {n}By default, the current path is used
+        #[arg(name = "path")]
+        paths: Vec<String>,
+
+        /// Recursively search for test files
+        #[arg(short)]
+        recursive: bool,
+
+        /// Select tests to graph based on tags
+        /// {n}By default, tests must match all given tags to be selected
+        #[arg(short, long = "tag", name = "tag")]
+        tags: Vec<String>,
+
+        /// Toggle tag matching logic to select tests matching any of the given tags
+        #[arg(long, default_value_t = false)]
+        tags_or: bool,
+
+        /// Select tests with a boolean tag expression, e.g. "(payments or billing) and not slow"
+        /// {n}Supports and, or, not and parentheses. Can't be combined with --tag or --tags-or
+        #[arg(long, conflicts_with_all = ["tag", "tags_or"])]
+        tag_expr: Option<String>,
+
+        /// Select tests by id, may be repeated
+        /// {n}Tests required by the selected ones are graphed too
+        #[arg(long = "id", name = "id")]
+        ids: Vec<String>,
+
+        /// Select tests whose name matches a regular expression
+        #[arg(long)]
+        name_regex: Option<String>,
+
+        /// Output format of the graph, Graphviz DOT or Mermaid
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
     },
 
     /// Format test files
@@ -172,6 +330,24 @@ pub enum Commands {
         /// Toggle tag matching logic to select tests matching any of the given tags
         #[arg(long, default_value_t = false)]
         tags_or: bool,
+
+        /// Select tests with a boolean tag expression, e.g. "(payments or billing) and not slow"
+        /// {n}Supports and, or, not and parentheses. Can't be combined with --tag or --tags-or
+        #[arg(long, conflicts_with_all = ["tag", "tags_or"])]
+        tag_expr: Option<String>,
+
+        /// Select tests by id, may be repeated
+        #[arg(long = "id", name = "id")]
+        ids: Vec<String>,
+
+        /// Select tests whose name matches a regular expression
+        #[arg(long)]
+        name_regex: Option<String>,
+
+        /// Report files that aren't formatted, with a diff, instead of rewriting them
+        /// {n}Exits with a non-zero code when any file would change
+        #[arg(long, default_value_t = false)]
+        check: bool,
     },
 
     /// Validate test files
@@ -194,9 +370,27 @@ pub enum Commands {
         #[arg(long, default_value_t = false)]
         tags_or: bool,
 
+        /// Select tests with a boolean tag expression, e.g. "(payments or billing) and not slow"
+        /// {n}Supports and, or, not and parentheses. Can't be combined with --tag or --tags-or
+        #[arg(long, conflicts_with_all = ["tag", "tags_or"])]
+        tag_expr: Option<String>,
+
+        /// Select tests by id, may be repeated
+        #[arg(long = "id", name = "id")]
+        ids: Vec<String>,
+
+        /// Select tests whose name matches a regular expression
+        #[arg(long)]
+        name_regex: Option<String>,
+
         /// Automatically generate and insert platform IDs in tests that don't have one
         #[arg(long, default_value_t = false)]
         generate_platform_ids: bool,
+
+        /// Output format for the problems found
+        /// {n}json and sarif are written to stdout for CI tools such as code scanning
+        #[arg(long, value_enum, default_value_t = ValidationFormat::Text)]
+        format: ValidationFormat,
     },
 
     /// Create a new test
@@ -421,26 +615,98 @@ fn print_test_info(mut tests: Vec<test::Definition>) {
         });
 }
 
+fn test_info_json(mut tests: Vec<test::Definition>) -> serde_json::Value {
+    tests.sort_by_key(|td| td.file_data.filename.clone());
+    serde_json::Value::Array(
+        tests
+            .into_iter()
+            .map(|td| {
+                serde_json::json!({
+                    "path": td.file_data.filename,
+                    "name": td.name,
+                    "id": td.id,
+                    "tags": td.tags,
+                    "requires": td.requires,
+                    "iterate": td.iterate,
+                    "stages": td.stages.len(),
+                    "urls": td.stages.iter().map(|s| &s.request.url).collect::<Vec<_>>(),
+                    "disabled": td.disabled,
+                })
+            })
+            .collect(),
+    )
+}
+
+fn print_diagnostics(diagnostics: &[Diagnostic], test_count: usize) {
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Error => error!("{}", diagnostic),
+            Severity::Warning => warn!("{}", diagnostic),
+        }
+    }
+
+    let count = |severity: Severity| {
+        diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    };
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    info!(
+        "\nValidated {} test{} with {} error{} and {} warning{}.\n",
+        test_count,
+        plural(test_count),
+        errors,
+        plural(errors),
+        warnings,
+        plural(warnings)
+    );
+}
+
 async fn run_tests(
-    paths: Vec<String>,
-    tags: Vec<String>,
-    tags_or: bool,
+    options: RunOptions,
     execution_mode: ExecutionMode,
-    recursive: bool,
     project: Option<String>,
     environment: Option<String>,
     config_file: Option<String>,
-    junit_file: Option<String>,
     cli_args: Box<serde_json::Value>,
 ) -> Result<executor::Report, Box<dyn Error + Send + Sync>> {
+    let RunOptions {
+        paths,
+        recursive,
+        tags,
+        tags_or,
+        tag_expr,
+        ids,
+        name_regex,
+        reports,
+        jobs,
+    } = options;
     let mut cli_paths = paths;
 
     if cli_paths.is_empty() {
         cli_paths.push(".".to_string())
     }
 
-    let cli_tag_mode = if tags_or { TagMode::OR } else { TagMode::AND };
-    let config = config::get_config(config_file).await;
+    let selection = executor::Selection::new(
+        ids,
+        name_regex
+            .map(|r| regex::Regex::new(&r))
+            .transpose()
+            .map_err(|e| format!("invalid name regex: {}", e))?,
+    );
+    let filters_specified = !tags.is_empty() || tag_expr.is_some() || !selection.is_empty();
+    let cli_tag_mode = match tag_expr {
+        Some(expression) => TagMode::Expression(tags::parse(&expression)?),
+        None if tags_or => TagMode::OR,
+        None => TagMode::AND,
+    };
+    let mut config = config::get_config(config_file).await;
+    if let Some(jobs) = jobs {
+        config.settings.parallelism = jobs;
+    }
+
     let files = get_files(cli_paths, std::path::Path::new(IGNORE_FILE), recursive).await?;
     let plurality_policy = |count: usize| match count {
         1 => "",
@@ -465,12 +731,23 @@ async fn run_tests(
         return Ok(executor::Report::default());
     }
 
-    let filters_specified = !tags.is_empty();
+    let file_count = files.len();
+    let (tests_to_run, tests_to_ignore, load_errors) = executor::tests_from_files(
+        &config,
+        files,
+        tags,
+        project,
+        environment,
+        cli_tag_mode,
+        &selection,
+    );
 
-    let (tests_to_run, tests_to_ignore) =
-        executor::tests_from_files(&config, files, tags, project, environment, cli_tag_mode);
+    if execution_mode == ExecutionMode::List(ListFormat::Json) {
+        println!("{:#}", test_info_json(tests_to_run));
+        return Ok(executor::Report::default());
+    }
 
-    if execution_mode == ExecutionMode::List {
+    if execution_mode == ExecutionMode::List(ListFormat::Text) {
         let number_of_tests_to_run = tests_to_run.len();
         print_test_info(tests_to_run);
         if filters_specified {
@@ -483,21 +760,98 @@ async fn run_tests(
         return Ok(executor::Report::default());
     }
 
-    if execution_mode == ExecutionMode::Format {
+    if let ExecutionMode::Graph(format) = execution_mode {
+        let (tests_to_run, tests_to_ignore) =
+            executor::include_prerequisites(tests_to_run, tests_to_ignore);
+        print!(
+            "{}",
+            executor::requirement_graph(&tests_to_run, &tests_to_ignore, format)
+        );
+        return Ok(executor::Report::default());
+    }
+
+    if let ExecutionMode::Format(check) = execution_mode {
+        let mut changed = 0;
+        let mut failed = 0;
         for td in &tests_to_run {
-            let mut file = fs::File::create(&td.file_data.filename).await?;
-            let file_data = serde_yaml::to_string(&td.file_data).unwrap();
-            file.write_all(file_data.as_bytes()).await?;
+            let filename = &td.file_data.filename;
+            let source = fs::read_to_string(filename).await?;
+            let formatted = match test::format::format_source(&source) {
+                Ok(formatted) => formatted,
+                Err(e) => {
+                    error!("Unable to format test file \"{}\": {}.", filename, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            if formatted == source {
+                continue;
+            }
+
+            changed += 1;
+            if check {
+                info!("{}\n", test::format::diff(filename, &source, &formatted));
+            } else {
+                fs::write(filename, formatted).await?;
+            }
         }
 
-        info!(
-            "Successfully formatted {} test files.\n",
-            &tests_to_run.len()
-        );
-        return Ok(executor::Report::default());
+        // files that failed to load were logged as they were loaded, and can't be formatted
+        let unloaded: HashSet<&str> = load_errors
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| d.filename.as_str())
+            .collect();
+        if !unloaded.is_empty() {
+            error!(
+                "{} test file{} could not be loaded.\n",
+                unloaded.len(),
+                plurality_policy(unloaded.len())
+            );
+        }
+
+        if check {
+            info!(
+                "{} of {} test files {} not formatted.\n",
+                changed,
+                tests_to_run.len(),
+                if changed == 1 { "is" } else { "are" }
+            );
+        } else {
+            info!(
+                "Successfully formatted {} test files.\n",
+                tests_to_run.len() - failed
+            );
+        }
+        return Ok(executor::Report {
+            test_files: (tests_to_run.len() + unloaded.len()) as u16,
+            failed: (failed + unloaded.len() + if check { changed } else { 0 }) as u16,
+            ..executor::Report::default()
+        });
     }
 
-    if let ExecutionMode::Validate(generate) = execution_mode {
+    if let ExecutionMode::Validate(generate, format) = execution_mode {
+        let lint = test::validation::validate_definitions(&tests_to_run, &tests_to_ignore);
+        // load errors were already logged as the files were loaded
+        match format {
+            ValidationFormat::Text => print_diagnostics(&lint, tests_to_run.len()),
+            ValidationFormat::Json => println!(
+                "{:#}",
+                test::validation::diagnostics_to_json(
+                    &[load_errors.clone(), lint.clone()].concat(),
+                    tests_to_run.len()
+                )
+            ),
+            ValidationFormat::Sarif => println!(
+                "{:#}",
+                test::validation::diagnostics_to_sarif(
+                    &[load_errors.clone(), lint.clone()].concat(),
+                    VERSION
+                )
+            ),
+        }
+
         if let Some(token) = &config.settings.api_key {
             if uuid::Uuid::parse_str(token).is_ok() {
                 let validation_results =
@@ -508,16 +862,18 @@ async fn run_tests(
                     for failure in failures {
                         if generate && failure.1 == PlatformIdFailure::Missing {
                             let platform_id = Ulid::new().to_string();
-                            let mut test = tests_to_run[failure.0.index].clone();
-                            test.file_data.platform_id = Some(platform_id.clone());
-
-                            let mut file = fs::File::create(&test.file_data.filename).await?;
-                            let file_data = serde_yaml::to_string(&test.file_data).unwrap();
-                            file.write_all(file_data.as_bytes()).await?;
+                            let filename = &tests_to_run[failure.0.index].file_data.filename;
+                            let source = fs::read_to_string(filename).await?;
+                            let updated = test::format::set_top_level_value(
+                                &source,
+                                "platformId",
+                                &platform_id,
+                            )?;
+                            fs::write(filename, updated).await?;
 
                             info!(
                                 "Successfully updated test at path \"{}\" with platform ID {}.\n",
-                                test.file_data.filename, platform_id
+                                filename, platform_id
                             );
                         } else {
                             if !has_missing && failure.1 == PlatformIdFailure::Missing {
@@ -536,7 +892,26 @@ async fn run_tests(
             }
         }
 
-        return Ok(executor::Report::default());
+        let files_with_errors: HashSet<&str> = load_errors
+            .iter()
+            .chain(lint.iter())
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| d.filename.as_str())
+            .collect();
+        return Ok(executor::Report {
+            test_files: file_count as u16,
+            failed: files_with_errors.len() as u16,
+            ..executor::Report::default()
+        });
+    }
+
+    //prerequisites of the selected tests run even when the filters exclude them
+    let (tests_to_run, tests_to_ignore) =
+        executor::include_prerequisites(tests_to_run, tests_to_ignore);
+
+    if execution_mode == ExecutionMode::Dryrun {
+        executor::print_unresolved_variables(&executor::unresolved_variables(&tests_to_run), false);
+        log::logger().flush();
     }
 
     let report = executor::execute_tests(
@@ -544,10 +919,10 @@ async fn run_tests(
         tests_to_run,
         execution_mode == ExecutionMode::Dryrun,
         tests_to_ignore,
-        junit_file,
+        reports,
         cli_args,
     )
-    .await;
+    .await?;
 
     let runtime_label = executor::runtime_formatter(report.runtime);
 
@@ -632,7 +1007,17 @@ async fn main() -> std::process::ExitCode {
 
     let my_logger = SimpleLogger::new(
         log_level,
-        cli.quiet,
+        cli.quiet
+            || matches!(
+                cli.command,
+                Commands::Validate {
+                    format: ValidationFormat::Json | ValidationFormat::Sarif,
+                    ..
+                } | Commands::List {
+                    format: ListFormat::Json,
+                    ..
+                } | Commands::Graph { .. }
+            ),
         matches!(cli.command, Commands::Run { .. } | Commands::DryRun { .. }),
     );
 
@@ -650,24 +1035,42 @@ async fn main() -> std::process::ExitCode {
         Commands::Run {
             tags,
             tags_or,
+            tag_expr,
+            ids,
+            name_regex,
             recursive,
             paths,
             junit,
+            report_json,
+            report_html,
+            overwrite_reports,
+            jobs,
         } => {
             updater::check_for_updates().await;
             log::logger().flush();
             check_supplied_config_file_existence(&cli.config_file);
             result_report_to_exit_code(
                 run_tests(
-                    paths,
-                    tags,
-                    tags_or,
+                    RunOptions {
+                        paths,
+                        recursive,
+                        tags,
+                        tags_or,
+                        tag_expr,
+                        ids,
+                        name_regex,
+                        reports: executor::ReportFiles {
+                            junit,
+                            json: report_json,
+                            html: report_html,
+                            overwrite: overwrite_reports,
+                        },
+                        jobs,
+                    },
                     ExecutionMode::Run,
-                    recursive,
                     cli_project,
                     cli_environment,
                     cli.config_file,
-                    junit,
                     cli_args,
                 )
                 .await,
@@ -676,9 +1079,15 @@ async fn main() -> std::process::ExitCode {
         Commands::DryRun {
             tags,
             tags_or,
+            tag_expr,
+            ids,
+            name_regex,
             recursive,
             paths,
             junit,
+            report_json,
+            report_html,
+            overwrite_reports,
         } => {
             // \todo create a runner function that takes an Fn trait and
             // eliminates the duplicated code
@@ -687,15 +1096,26 @@ async fn main() -> std::process::ExitCode {
             check_supplied_config_file_existence(&cli.config_file);
             result_report_to_exit_code(
                 run_tests(
-                    paths,
-                    tags,
-                    tags_or,
+                    RunOptions {
+                        paths,
+                        recursive,
+                        tags,
+                        tags_or,
+                        tag_expr,
+                        ids,
+                        name_regex,
+                        reports: executor::ReportFiles {
+                            junit,
+                            json: report_json,
+                            html: report_html,
+                            overwrite: overwrite_reports,
+                        },
+                        ..RunOptions::default()
+                    },
                     ExecutionMode::Dryrun,
-                    recursive,
                     cli_project,
                     cli_environment,
                     cli.config_file,
-                    junit,
                     Box::new(serde_json::Value::Null),
                 )
                 .await,
@@ -704,22 +1124,64 @@ async fn main() -> std::process::ExitCode {
         Commands::List {
             tags,
             tags_or,
+            tag_expr,
+            ids,
+            name_regex,
             recursive,
             paths,
+            format,
         } => {
             updater::check_for_updates().await;
             check_supplied_config_file_existence(&cli.config_file);
             result_report_to_exit_code(
                 run_tests(
-                    paths,
-                    tags,
-                    tags_or,
-                    ExecutionMode::List,
-                    recursive,
+                    RunOptions {
+                        paths,
+                        recursive,
+                        tags,
+                        tags_or,
+                        tag_expr,
+                        ids,
+                        name_regex,
+                        ..RunOptions::default()
+                    },
+                    ExecutionMode::List(format),
+                    cli_project,
+                    cli_environment,
+                    cli.config_file,
+                    cli_args,
+                )
+                .await,
+            )
+        }
+        Commands::Graph {
+            tags,
+            tags_or,
+            tag_expr,
+            ids,
+            name_regex,
+            recursive,
+            paths,
+            format,
+        } => {
+            updater::check_for_updates().await;
+            check_supplied_config_file_existence(&cli.config_file);
+            result_report_to_exit_code(
+                run_tests(
+                    RunOptions {
+                        paths,
+                        recursive,
+                        tags,
+                        tags_or,
+                        tag_expr,
+                        ids,
+                        name_regex,
+                        ..RunOptions::default()
+                    },
+                    ExecutionMode::Graph(format),
                     cli_project,
                     cli_environment,
                     cli.config_file,
-                    None,
                     cli_args,
                 )
                 .await,
@@ -728,22 +1190,31 @@ async fn main() -> std::process::ExitCode {
         Commands::Format {
             tags,
             tags_or,
+            tag_expr,
+            ids,
+            name_regex,
             recursive,
             paths,
+            check,
         } => {
             updater::check_for_updates().await;
             check_supplied_config_file_existence(&cli.config_file);
             result_report_to_exit_code(
                 run_tests(
-                    paths,
-                    tags,
-                    tags_or,
-                    ExecutionMode::Format,
-                    recursive,
+                    RunOptions {
+                        paths,
+                        recursive,
+                        tags,
+                        tags_or,
+                        tag_expr,
+                        ids,
+                        name_regex,
+                        ..RunOptions::default()
+                    },
+                    ExecutionMode::Format(check),
                     cli_project,
                     cli_environment,
                     cli.config_file,
-                    None,
                     cli_args,
                 )
                 .await,
@@ -752,23 +1223,32 @@ async fn main() -> std::process::ExitCode {
         Commands::Validate {
             tags,
             tags_or,
+            tag_expr,
+            ids,
+            name_regex,
             recursive,
             paths,
             generate_platform_ids,
+            format,
         } => {
             updater::check_for_updates().await;
             check_supplied_config_file_existence(&cli.config_file);
             result_report_to_exit_code(
                 run_tests(
-                    paths,
-                    tags,
-                    tags_or,
-                    ExecutionMode::Validate(generate_platform_ids),
-                    recursive,
+                    RunOptions {
+                        paths,
+                        recursive,
+                        tags,
+                        tags_or,
+                        tag_expr,
+                        ids,
+                        name_regex,
+                        ..RunOptions::default()
+                    },
+                    ExecutionMode::Validate(generate_platform_ids, format),
                     cli_project,
                     cli_environment,
                     cli.config_file,
-                    None,
                     cli_args,
                 )
                 .await,
@@ -994,6 +1474,33 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn format_check_fails_files_that_cannot_be_loaded() {
+        let dir_fixture = DirectoryFixture::new(vec!["broken.jkt"].as_slice());
+        std::fs::write(
+            dir_fixture.temp_dir.path().join("broken.jkt"),
+            "name: [broken\n",
+        )
+        .unwrap();
+
+        let report = run_tests(
+            RunOptions {
+                paths: vec![dir_fixture.path_string()],
+                ..RunOptions::default()
+            },
+            ExecutionMode::Format(true),
+            None,
+            None,
+            None,
+            Box::new(serde_json::Value::Null),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(1, report.test_files);
+        assert_eq!(1, report.failed);
+    }
+
     #[tokio::test]
     async fn get_files_with_recursive_globbing() {
         let tmp_dir = tempdir().unwrap();
@@ -58,8 +58,9 @@ mod openapi_legacy {
             file::{
                 generate_value_from_schema, DatumSchema, FloatSpecification, IntegerSpecification,
                 SimpleValueVariable, Specification, StringSpecification, UnvalidatedRequest,
-                UnvalidatedResponse, UnvalidatedVariableNameOrDatumSchema,
-                UnvalidatedVariableNameOrValue, ValueOrDatumSchema,
+                UnvalidatedResponse, UnvalidatedResponseHeader,
+                UnvalidatedVariableNameOrDatumSchema, UnvalidatedVariableNameOrValue,
+                ValueOrDatumSchema,
             },
         },
     };
@@ -74,13 +75,12 @@ mod openapi_legacy {
 
     fn create_headers(
         headers: &IndexMap<String, RefOr<openapiv3::Header>, RandomState>,
-    ) -> Option<Vec<test::http::Header>> {
-        let ret: Vec<test::http::Header> = headers
+    ) -> Option<Vec<UnvalidatedResponseHeader>> {
+        let ret: Vec<UnvalidatedResponseHeader> = headers
             .iter()
-            .map(|(name, _)| test::http::Header {
+            .map(|(name, _)| UnvalidatedResponseHeader {
                 header: name.clone(),
-                matches_variable: std::cell::Cell::new(false),
-                value: String::default(),
+                ..Default::default()
             })
             .collect();
 
@@ -119,6 +119,8 @@ mod openapi_legacy {
                         extract: None,
                         ignore: None,
                         strict: None,
+                        content: None,
+                        assertions: None,
                         body: body_stuff.clone().and_then(|(v, _)| v),
                         body_schema: None, //body_stuff.map(|(_, ds)| ds),
                     }
@@ -277,6 +279,7 @@ mod openapi_legacy {
             } else {
                 Some(parameters)
             },
+            ..Default::default()
         }
     }
 
@@ -391,6 +394,9 @@ mod openapi_legacy {
                     variables,
                     name: None,
                     delay: None,
+                    timeout: None,
+                    retry: None,
+                    poll: None,
                 }]),
                 filename: create_filename(path_string, &verb),
                 ..default
@@ -472,9 +478,10 @@ mod openapi_v31 {
             FloatSpecification, IntegerSpecification, SimpleValueVariable, Specification,
             StringSpecification, UnvalidatedDateSpecification, UnvalidatedDatumSchemaVariable,
             UnvalidatedFloatSpecification, UnvalidatedIntegerSpecification, UnvalidatedRequest,
-            UnvalidatedResponse, UnvalidatedStringSpecification, UnvalidatedValueOrDatumSchema,
-            UnvalidatedValuesOrSchema, UnvalidatedVariable, UnvalidatedVariableNameOrComponent,
-            UnvalidatedVariableNameOrDatumSchema, ValueOrDatumSchema, VariableName,
+            UnvalidatedResponse, UnvalidatedResponseHeader, UnvalidatedStringSpecification,
+            UnvalidatedValueOrDatumSchema, UnvalidatedValuesOrSchema, UnvalidatedVariable,
+            UnvalidatedVariableNameOrComponent, UnvalidatedVariableNameOrDatumSchema,
+            ValueOrDatumSchema, VariableName,
         },
     };
     use oas3::spec::{Header, ObjectOrReference, Operation, PathItem, Response, Server, Spec};
@@ -503,13 +510,12 @@ mod openapi_v31 {
 
     fn create_headers(
         headers: &BTreeMap<String, ObjectOrReference<Header>>,
-    ) -> Option<Vec<test::http::Header>> {
-        let ret: Vec<test::http::Header> = headers
+    ) -> Option<Vec<UnvalidatedResponseHeader>> {
+        let ret: Vec<UnvalidatedResponseHeader> = headers
             .iter()
-            .map(|(name, _)| test::http::Header {
+            .map(|(name, _)| UnvalidatedResponseHeader {
                 header: name.clone(),
-                matches_variable: std::cell::Cell::new(false),
-                value: String::default(),
+                ..Default::default()
             })
             .collect();
 
@@ -536,6 +542,8 @@ mod openapi_v31 {
                     extract: None,
                     ignore: None,
                     strict: None,
+                    content: None,
+                    assertions: None,
                     body_schema: t.content.get("application/json").and_then(|c| {
                         c.schema(spec).ok().and_then(|s| {
                             schema_to_datum(s, spec)
@@ -823,6 +831,7 @@ mod openapi_v31 {
                 } else {
                     Some(parameters)
                 },
+                ..Default::default()
             },
             maybe_variable,
         )
@@ -892,6 +901,9 @@ mod openapi_v31 {
                     variables: maybe_vars,
                     name: None,
                     delay: None,
+                    timeout: None,
+                    retry: None,
+                    poll: None,
                 }]),
                 filename: create_filename(path_string, &verb),
                 ..default
@@ -0,0 +1,175 @@
+use crate::test::validation;
+use std::fmt;
+
+// Boolean tag expressions used to select tests, e.g. `smoke and not slow` or
+// `(payments or billing) and regression`. `not` binds tighter than `and`, which
+// binds tighter than `or`. Any other word is a tag name.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagExpression {
+    Tag(String),
+    Not(Box<TagExpression>),
+    And(Box<TagExpression>, Box<TagExpression>),
+    Or(Box<TagExpression>, Box<TagExpression>),
+}
+
+impl TagExpression {
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagExpression::Tag(tag) => tags.contains(tag),
+            TagExpression::Not(e) => !e.matches(tags),
+            TagExpression::And(l, r) => l.matches(tags) && r.matches(tags),
+            TagExpression::Or(l, r) => l.matches(tags) || r.matches(tags),
+        }
+    }
+}
+
+impl fmt::Display for TagExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TagExpression::Tag(tag) => write!(f, "{}", tag),
+            TagExpression::Not(e) => write!(f, "not {}", e),
+            TagExpression::And(l, r) => write!(f, "({} and {})", l, r),
+            TagExpression::Or(l, r) => write!(f, "({} or {})", l, r),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn error(&self, reason: String) -> validation::Error {
+        validation::Error {
+            reason: format!("invalid tag expression: {}", reason),
+        }
+    }
+
+    fn or(&mut self) -> Result<TagExpression, validation::Error> {
+        let mut left = self.and()?;
+        while self.peek() == Some("or") {
+            self.position += 1;
+            left = TagExpression::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<TagExpression, validation::Error> {
+        let mut left = self.not()?;
+        while self.peek() == Some("and") {
+            self.position += 1;
+            left = TagExpression::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<TagExpression, validation::Error> {
+        if self.peek() == Some("not") {
+            self.position += 1;
+            return Ok(TagExpression::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<TagExpression, validation::Error> {
+        match self.next().as_deref() {
+            Some("(") => {
+                let inner = self.or()?;
+                match self.next().as_deref() {
+                    Some(")") => Ok(inner),
+                    _ => Err(self.error("missing `)`".to_string())),
+                }
+            }
+            Some(token @ (")" | "and" | "or")) => {
+                Err(self.error(format!("unexpected `{}`", token)))
+            }
+            Some(tag) => Ok(TagExpression::Tag(tag.to_string())),
+            None => Err(self.error("expected a tag".to_string())),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<TagExpression, validation::Error> {
+    let mut parser = Parser {
+        tokens: tokenize(text),
+        position: 0,
+    };
+    let expression = parser.or()?;
+    match parser.next() {
+        None => Ok(expression),
+        Some(token) => Err(parser.error(format!("unexpected `{}`", token))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(values: &[&str]) -> Vec<String> {
+        values.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_respects_precedence_and_parentheses() {
+        assert_eq!(
+            "((smoke and not slow) or nightly)",
+            parse("smoke and not slow or nightly").unwrap().to_string()
+        );
+        assert_eq!(
+            "((payments or billing) and regression)",
+            parse("(payments or billing) and regression")
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!("not not a", parse("not not a").unwrap().to_string());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_expressions() {
+        for text in ["", "a and", "(a or b", "a b", "or a", "a)"] {
+            assert!(parse(text).is_err(), "`{}` should not parse", text);
+        }
+    }
+
+    #[test]
+    fn expressions_match_tags() {
+        let expression = parse("(payments or billing) and regression and not slow").unwrap();
+        assert!(expression.matches(&tags(&["billing", "regression"])));
+        assert!(!expression.matches(&tags(&["billing", "regression", "slow"])));
+        assert!(!expression.matches(&tags(&["payments"])));
+        assert!(parse("not slow").unwrap().matches(&[]));
+    }
+}
@@ -618,6 +618,7 @@ mod tests {
         let request = RequestDescriptor {
             method: test::http::Verb::Get,
             body: None,
+            payload: None,
             headers: headers_factory(),
             params: vec![],
             url: "foo".to_string(),
@@ -630,9 +631,11 @@ mod tests {
             platform_id: None,
             project: None,
             environment: None,
-            requires: None,
+            requires: vec![],
             tags: Vec::new(),
             iterate: 0,
+            timeout: None,
+            retry: None,
             variables: Vec::new(),
             global_variables: Vec::new(),
             stages: vec![StageDescriptor {
@@ -654,6 +657,9 @@ mod tests {
                     strict: true,
                 }),
                 delay: None,
+                timeout: None,
+                retry: None,
+                poll: None,
             }],
             setup: Some(RequestResponseDescriptor {
                 response: None,
@@ -742,6 +748,7 @@ mod tests {
         let request = RequestDescriptor {
             method: test::http::Verb::Get,
             body: None,
+            payload: None,
             headers: headers_factory(),
             params: vec![],
             url: "foo".to_string(),
@@ -754,9 +761,11 @@ mod tests {
             platform_id: None,
             project: None,
             environment: None,
-            requires: None,
+            requires: vec![],
             tags: Vec::new(),
             iterate: 0,
+            timeout: None,
+            retry: None,
             variables: Vec::new(),
             global_variables: vec![Variable {
                 name: "my_global".to_string(),
@@ -784,6 +793,9 @@ mod tests {
                     strict: true,
                 }),
                 delay: None,
+                timeout: None,
+                retry: None,
+                poll: None,
             }],
             setup: Some(RequestResponseDescriptor {
                 response: None,
@@ -814,6 +826,7 @@ mod tests {
         let request = RequestDescriptor {
             method: test::http::Verb::Get,
             body: None,
+            payload: None,
             headers: headers_factory(),
             params: vec![],
             url: "foo".to_string(),
@@ -826,9 +839,11 @@ mod tests {
             platform_id: None,
             project: None,
             environment: None,
-            requires: None,
+            requires: vec![],
             tags: Vec::new(),
             iterate: 0,
+            timeout: None,
+            retry: None,
             variables: Vec::new(),
             global_variables: Vec::new(),
             stages: vec![StageDescriptor {
@@ -850,6 +865,9 @@ mod tests {
                     strict: true,
                 }),
                 delay: None,
+                timeout: None,
+                retry: None,
+                poll: None,
             }],
             setup: Some(RequestResponseDescriptor {
                 response: None,
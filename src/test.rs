@@ -1,5 +1,7 @@
 pub mod definition;
+pub mod expression;
 pub mod file;
+pub mod format;
 pub mod http;
 pub mod template;
 pub mod validation;
@@ -336,10 +338,14 @@ pub struct File {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tags: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub requires: Option<String>,
+    pub requires: Option<file::UnvalidatedRequires>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub iterate: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<file::UnvalidatedRetry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub setup: Option<file::UnvalidatedRequestResponse>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub request: Option<file::UnvalidatedRequest>,
@@ -369,6 +375,8 @@ impl Default for File {
             tags: None,
             requires: None,
             iterate: None,
+            timeout: None,
+            retry: None,
             setup: None,
             request: Some(UnvalidatedRequest::default()),
             compare: None,
@@ -558,6 +566,16 @@ impl Variable {
     }
 }
 
+//The text a variable extracted during the run is substituted with inside a longer
+//string. Strings are used as is, other values as compact JSON and null as nothing
+pub fn variable_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => "".to_string(),
+        value => value.to_string(),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Definition {
@@ -567,9 +585,13 @@ pub struct Definition {
     pub platform_id: Option<String>,
     pub project: Option<String>,
     pub environment: Option<String>,
-    pub requires: Option<String>,
+    pub requires: Vec<String>,
     pub tags: Vec<String>,
     pub iterate: u32,
+    //milliseconds allowed for the setup and stages of each iteration
+    pub timeout: Option<u64>,
+    //reruns a failed iteration, setup through cleanup
+    pub retry: Option<definition::RetryDescriptor>,
     pub variables: Vec<Variable>,
     pub global_variables: Vec<Variable>,
     pub stages: Vec<definition::StageDescriptor>,
@@ -584,6 +606,14 @@ pub struct Definition {
     pub index: usize,
 }
 
+//A placeholder which none of a test's variables, nor any upstream extraction, can satisfy
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnresolvedVariable {
+    pub name: String,
+    //setup, stage N or cleanup
+    pub location: String,
+}
+
 // TODO: add validation logic to verify the descriptor is valid
 // TODO: Validation should be type driven for compile time correctness
 impl Definition {
@@ -599,6 +629,80 @@ impl Definition {
             .fold(s.to_string(), |acc, secret| secret.redact(acc.as_str()))
     }
 
+    //Names the setup and stages extract into the state
+    pub fn extracted_variables(&self) -> Vec<String> {
+        self.setup
+            .iter()
+            .filter_map(|s| s.response.as_ref())
+            .chain(self.stages.iter().filter_map(|s| s.response.as_ref()))
+            .flat_map(|r| r.extract.iter().map(|e| e.name.clone()))
+            .collect()
+    }
+
+    //Placeholders that can't be satisfied by the test, stage and global variables
+    //(secrets included), the test's own earlier extractions or `upstream` ones
+    pub fn unresolved_variables(&self, upstream: &HashSet<String>) -> Vec<UnresolvedVariable> {
+        fn referenced<T: Serialize>(descriptor: &T) -> Vec<String> {
+            serde_json::to_value(descriptor)
+                .map(|v| expression::json_placeholder_variables(&v))
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|name| expression::is_variable_name(name))
+                .collect()
+        }
+
+        let mut available: HashSet<String> = self
+            .variables
+            .iter()
+            .chain(self.global_variables.iter())
+            .map(|v| v.name.clone())
+            .chain(upstream.iter().cloned())
+            .collect();
+        let mut unresolved: Vec<UnresolvedVariable> = Vec::new();
+        let mut report = |location: String, names: Vec<String>, available: &HashSet<String>| {
+            for name in names {
+                let variable = UnresolvedVariable {
+                    name,
+                    location: location.clone(),
+                };
+                if !available.contains(&variable.name) && !unresolved.contains(&variable) {
+                    unresolved.push(variable);
+                }
+            }
+        };
+
+        if let Some(setup) = &self.setup {
+            let names = [referenced(&setup.request), referenced(&setup.response)].concat();
+            report("setup".to_string(), names, &available);
+            if let Some(r) = &setup.response {
+                available.extend(r.extract.iter().map(|e| e.name.clone()));
+            }
+        }
+
+        for (index, stage) in self.stages.iter().enumerate() {
+            let stage_available: HashSet<String> = available
+                .iter()
+                .cloned()
+                .chain(stage.variables.iter().map(|v| v.name.clone()))
+                .collect();
+            let names = [
+                referenced(&stage.request),
+                referenced(&stage.compare),
+                referenced(&stage.response),
+                referenced(&stage.poll),
+            ]
+            .concat();
+            report(format!("stage {}", index + 1), names, &stage_available);
+            if let Some(r) = &stage.response {
+                available.extend(r.extract.iter().map(|e| e.name.clone()));
+            }
+        }
+
+        report("cleanup".to_string(), referenced(&self.cleanup), &available);
+
+        unresolved
+    }
+
     fn update_request_variables(request: &definition::RequestDescriptor, var_pattern: &str) {
         for header in request.headers.iter() {
             if header.matches_variable.get() {
@@ -676,17 +780,6 @@ impl Definition {
     }
 
     fn update_response_variables(response: &definition::ResponseDescriptor, var_pattern: &str) {
-        for header in response.headers.iter() {
-            if header.matches_variable.get() {
-                continue;
-            }
-
-            if header.value.contains(var_pattern) {
-                header.matches_variable.set(true);
-                debug!("setting match true: {}", header.header);
-            }
-        }
-
         if let Some(body) = response.body.as_ref() {
             let body_data = match serde_json::to_string(&body.data) {
                 Ok(s) => Some(s),
@@ -757,7 +850,7 @@ impl Definition {
         iteration: u32,
         url: &str,
         params: &[http::Parameter],
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
     ) -> String {
         let joined: Vec<_> = params
@@ -771,9 +864,9 @@ impl Definition {
         let modified_url = if url.contains('$') {
             let mut replaced_url = url.to_string();
 
-            let state_vars: Vec<(String, &String)> = state_variables
+            let state_vars: Vec<(String, String)> = state_variables
                 .iter()
-                .map(|(k, v)| (format!("${{{}}}", k), v))
+                .map(|(k, v)| (format!("${{{}}}", k), variable_text(v)))
                 .collect();
 
             for (var_pattern, value) in &state_vars {
@@ -782,8 +875,11 @@ impl Definition {
                 }
 
                 debug!("state variable match: {}", var_pattern);
-                replaced_url
-                    .clone_from(&replaced_url.replace(var_pattern.as_str(), value.as_str()));
+                replaced_url.clone_from(&expression::replace_placeholder(
+                    &replaced_url,
+                    var_pattern,
+                    value,
+                ));
             }
 
             for variable in variables.iter().chain(self.global_variables.iter()) {
@@ -794,11 +890,14 @@ impl Definition {
                 }
 
                 let replacement = variable.generate_value(self, iteration, &self.global_variables);
-                replaced_url
-                    .clone_from(&replaced_url.replace(var_pattern.as_str(), replacement.as_str()))
+                replaced_url.clone_from(&expression::replace_placeholder(
+                    &replaced_url,
+                    &var_pattern,
+                    &replacement,
+                ))
             }
 
-            replaced_url
+            self.resolve_expressions(&replaced_url, state_variables, variables, iteration)
         } else {
             url.to_string()
         };
@@ -822,9 +921,7 @@ impl Definition {
                 let replacement = variable.generate_value(self, iteration, &self.global_variables);
                 return (
                     parameter.param.clone(),
-                    parameter
-                        .value
-                        .replace(var_pattern.as_str(), replacement.as_str()),
+                    expression::replace_placeholder(&parameter.value, &var_pattern, &replacement),
                 );
             }
         }
@@ -832,24 +929,28 @@ impl Definition {
         (parameter.param.clone(), parameter.value.clone())
     }
 
+    //State variables, and expressions using them, are resolved when the request is sent
     fn get_processed_header(&self, header: &http::Header, iteration: u32) -> (String, String) {
+        if !header.value.contains("${") {
+            return (header.header.clone(), header.value.clone());
+        }
+
+        let mut value = header.value.clone();
         for variable in self.variables.iter().chain(self.global_variables.iter()) {
             let var_pattern = format!("${{{}}}", variable.name);
 
-            if !header.value.contains(var_pattern.as_str()) {
+            if !value.contains(var_pattern.as_str()) {
                 continue;
             }
 
             let replacement = variable.generate_value(self, iteration, &self.global_variables);
-            return (
-                header.header.clone(),
-                header
-                    .value
-                    .replace(var_pattern.as_str(), replacement.as_str()),
-            );
+            value = expression::replace_placeholder(&value, &var_pattern, &replacement);
         }
 
-        (String::from(""), String::from(""))
+        (
+            header.header.clone(),
+            self.resolve_expressions(&value, &HashMap::new(), &self.variables, iteration),
+        )
     }
 
     pub fn get_setup_request_headers(&self, iteration: u32) -> Vec<(String, String)> {
@@ -858,14 +959,7 @@ impl Definition {
                 .request
                 .headers
                 .iter()
-                .map(|h| {
-                    if h.matches_variable.get() {
-                        let header = self.get_processed_header(h, iteration);
-                        (header.0, header.1)
-                    } else {
-                        (h.header.clone(), h.value.clone())
-                    }
-                })
+                .map(|h| self.get_processed_header(h, iteration))
                 .collect(),
             None => Vec::new(),
         }
@@ -874,24 +968,10 @@ impl Definition {
     pub fn get_headers(&self, headers: &[http::Header], iteration: u32) -> Vec<(String, String)> {
         headers
             .iter()
-            .map(|h| {
-                if h.matches_variable.get() {
-                    let header = self.get_processed_header(h, iteration);
-                    (header.0, header.1)
-                } else {
-                    (h.header.clone(), h.value.clone())
-                }
-            })
+            .map(|h| self.get_processed_header(h, iteration))
             .collect()
     }
 
-    pub fn get_cleanup_request_headers(&self, iteration: u32) -> Vec<(String, String)> {
-        match &self.cleanup.always {
-            Some(request) => self.get_headers(&request.headers, iteration),
-            None => Vec::new(),
-        }
-    }
-
     pub fn get_stage_compare_headers(
         &self,
         stage_index: usize,
@@ -904,14 +984,7 @@ impl Definition {
                     compare
                         .headers
                         .iter()
-                        .map(|h| {
-                            if h.matches_variable.get() {
-                                let header = self.get_processed_header(h, iteration);
-                                (header.0, header.1)
-                            } else {
-                                (h.header.clone(), h.value.clone())
-                            }
-                        })
+                        .map(|h| self.get_processed_header(h, iteration))
                         .collect()
                 } else {
                     let ignore_lookup: HashSet<String> =
@@ -923,14 +996,7 @@ impl Definition {
                         .iter()
                         .filter(|h| !ignore_lookup.contains(&h.header))
                         .chain(compare.add_headers.iter())
-                        .map(|h| {
-                            if h.matches_variable.get() {
-                                let header = self.get_processed_header(h, iteration);
-                                (header.0, header.1)
-                            } else {
-                                (h.header.clone(), h.value.clone())
-                            }
-                        })
+                        .map(|h| self.get_processed_header(h, iteration))
                         .collect()
                 };
 
@@ -943,7 +1009,7 @@ impl Definition {
     pub fn resolve_body_variables(
         &self,
         body: &BodyOrSchema,
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
         iteration: u32,
     ) -> Option<BodyOrSchema> {
@@ -961,45 +1027,166 @@ impl Definition {
     fn resolve_body_value_variables(
         &self,
         json_val: &serde_json::Value,
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
         iteration: u32,
     ) -> Option<BodyOrSchema> {
         trace!("resolve_body_value_variables()");
-        serde_json::to_string(&json_val)
-            .map(|jv| self.resolve_variables(jv.as_str(), state_variables, variables, iteration))
-            .and_then(|rs| {
-                trace!("rs is {}", rs);
-                let rsv = rs.replace(['\n', '\r'], "");
-                trace!("rsv is {}", rsv);
-                let mut ret = serde_json::from_str::<serde_json::Value>(rsv.as_str());
-                if ret.is_err() {
-                    trace!("Error resolving. Attempting to trim quotes {:?}", ret);
-                    ret = serde_json::from_str::<serde_json::Value>(rsv.trim_matches('\"'));
-                    if ret.is_err() {
-                        error!("Error producing json body! {:?} : {:?}", ret, rsv);
-                    }
-                    ret
-                } else {
-                    trace!("GOOD VAL {:?}", ret);
-                    ret
-                }
-            })
-            .map(BodyOrSchema::Body)
-            .ok()
+        Some(BodyOrSchema::Body(self.resolve_value_variables(
+            json_val,
+            state_variables,
+            variables,
+            iteration,
+        )))
+    }
+
+    //Walks the body so that a value which is exactly `${x}` takes x's JSON type,
+    //while placeholders inside longer strings (and keys) are interpolated as text
+    fn resolve_value_variables(
+        &self,
+        json_val: &serde_json::Value,
+        state_variables: &HashMap<String, serde_json::Value>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> serde_json::Value {
+        let resolve = |v: &serde_json::Value| {
+            self.resolve_value_variables(v, state_variables, variables, iteration)
+        };
+
+        match json_val {
+            serde_json::Value::String(s) => expression::sole_placeholder(s)
+                .and_then(|content| {
+                    self.typed_placeholder_value(content, state_variables, variables, iteration)
+                })
+                .unwrap_or_else(|| {
+                    serde_json::Value::String(self.interpolate_variables(
+                        s,
+                        state_variables,
+                        variables,
+                        iteration,
+                    ))
+                }),
+            serde_json::Value::Array(a) => {
+                serde_json::Value::Array(a.iter().map(resolve).collect())
+            }
+            serde_json::Value::Object(o) => serde_json::Value::Object(
+                o.iter()
+                    .map(|(k, v)| {
+                        (
+                            self.interpolate_variables(k, state_variables, variables, iteration),
+                            resolve(v),
+                        )
+                    })
+                    .collect(),
+            ),
+            _ => json_val.clone(),
+        }
+    }
+
+    //State variables keep the JSON type they were extracted with
+    fn typed_placeholder_value(
+        &self,
+        content: &str,
+        state_variables: &HashMap<String, serde_json::Value>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> Option<serde_json::Value> {
+        if !expression::is_variable_name(content) {
+            let lookup = |name: &str| {
+                state_variables.get(name).map(variable_text).or_else(|| {
+                    variables
+                        .iter()
+                        .chain(self.global_variables.iter())
+                        .find(|v| v.name == name)
+                        .map(|v| v.generate_value(self, iteration, &self.global_variables))
+                })
+            };
+            return expression::evaluate_json(content, &lookup)
+                .map_err(|e| debug!("unable to evaluate ${{{}}}: {}", content, e))
+                .ok();
+        }
+
+        if let Some(value) = state_variables.get(content) {
+            return Some(value.clone());
+        }
+
+        let variable = variables
+            .iter()
+            .chain(self.global_variables.iter())
+            .find(|v| v.name == content)?;
+        let generated = variable.generate_value(self, iteration, &self.global_variables);
+        let is_text = match &variable.value {
+            ValueOrDatumOrFileOrSecret::Value { value: v } => v.is_string(),
+            ValueOrDatumOrFileOrSecret::ValueSet { value_set: v } => v
+                .as_array()
+                .filter(|a| !a.is_empty())
+                .map(|a| a[iteration as usize % a.len()].is_string())
+                .unwrap_or(true),
+            ValueOrDatumOrFileOrSecret::Schema { value: ds } => matches!(
+                ds,
+                DatumSchema::String { .. }
+                    | DatumSchema::Name { .. }
+                    | DatumSchema::Date { .. }
+                    | DatumSchema::DateTime { .. }
+                    | DatumSchema::Email { .. }
+            ),
+            ValueOrDatumOrFileOrSecret::File { .. } => false,
+            ValueOrDatumOrFileOrSecret::Secret { .. } => true,
+        };
+
+        if is_text {
+            return Some(serde_json::Value::String(generated));
+        }
+
+        Some(
+            serde_json::from_str(&generated)
+                .unwrap_or_else(|_| serde_json::Value::String(generated.clone())),
+        )
+    }
+
+    //Textual substitution of every variable and expression in a string
+    fn interpolate_variables(
+        &self,
+        text: &str,
+        state_variables: &HashMap<String, serde_json::Value>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> String {
+        if !text.contains("${") {
+            return text.to_string();
+        }
+
+        let mut result = text.to_string();
+        for (name, value) in state_variables {
+            result = expression::replace_placeholder(
+                &result,
+                &format!("${{{}}}", name),
+                &variable_text(value),
+            );
+        }
+
+        for variable in variables.iter().chain(self.global_variables.iter()) {
+            let var_pattern = format!("${{{}}}", variable.name);
+            if result.contains(var_pattern.as_str()) {
+                let replacement = variable.generate_value(self, iteration, &self.global_variables);
+                result = expression::replace_placeholder(&result, &var_pattern, &replacement);
+            }
+        }
+
+        self.resolve_expressions(&result, state_variables, variables, iteration)
     }
 
     fn resolve_schema_variables(
         &self,
         schema: &DatumSchema,
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
         iteration: u32,
     ) -> Option<BodyOrSchema> {
         trace!("resolve_schema_variables()");
-        let ret = serde_json::to_string(&schema)
-            .map(|jv| self.resolve_variables(jv.as_str(), state_variables, variables, iteration))
-            .and_then(|rs| serde_json::from_str::<DatumSchema>(rs.as_str()))
+        let ret = serde_json::to_value(schema)
+            .map(|jv| self.resolve_value_variables(&jv, state_variables, variables, iteration))
+            .and_then(serde_json::from_value::<DatumSchema>)
             .map_err(|e| {
                 trace!("resolve_schema_variables(): Error is {e}");
                 error!("Error producing json body from schema! {e}");
@@ -1014,19 +1201,43 @@ impl Definition {
         ret
     }
 
+    //Evaluates the `${...}` expressions left once plain variables are replaced
+    fn resolve_expressions(
+        &self,
+        text: &str,
+        state_variables: &HashMap<String, serde_json::Value>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> String {
+        if !text.contains("${") {
+            return text.to_string();
+        }
+
+        let lookup = |name: &str| {
+            state_variables.get(name).map(variable_text).or_else(|| {
+                variables
+                    .iter()
+                    .chain(self.global_variables.iter())
+                    .find(|v| v.name == name)
+                    .map(|v| v.generate_value(self, iteration, &self.global_variables))
+            })
+        };
+        expression::resolve_expressions(text, &lookup)
+    }
+
     fn resolve_variables(
         &self,
         json_val: &str,
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
         iteration: u32,
     ) -> String {
         debug!("resolve_variables({})", json_val);
         let mut mut_string = json_val.to_string();
 
-        let state_vars: Vec<(String, &String)> = state_variables
+        let state_vars: Vec<(String, String)> = state_variables
             .iter()
-            .map(|(k, v)| (format!("${{{}}}", k), v))
+            .map(|(k, v)| (format!("${{{}}}", k), variable_text(v)))
             .collect();
 
         for (var_pattern, value) in &state_vars {
@@ -1035,8 +1246,8 @@ impl Definition {
             }
 
             debug!("state variable match: {}", var_pattern);
-            mut_string = mut_string
-                .replace(var_pattern.as_str(), value.as_str())
+
+            mut_string = expression::replace_placeholder(&mut_string, var_pattern, value)
                 .trim()
                 .to_string();
             //play with recursion here, these could be complex variables
@@ -1096,16 +1307,19 @@ impl Definition {
                         replacement.as_str(),
                     )
                     .trim()
-                    .replace(var_pattern.as_str(), replacement.as_str())
                     .to_string();
+                mut_string =
+                    expression::replace_placeholder(&mut_string, &var_pattern, &replacement);
             } else {
-                mut_string = mut_string
-                    .replace(var_pattern.as_str(), replacement.as_str())
-                    .trim()
-                    .to_string();
+                mut_string =
+                    expression::replace_placeholder(&mut_string, &var_pattern, &replacement)
+                        .trim()
+                        .to_string();
             }
         }
 
+        mut_string = self.resolve_expressions(&mut_string, state_variables, variables, iteration);
+
         debug!("mut string is {}", mut_string);
         debug!("mut string is {:?}", mut_string);
         mut_string
@@ -1117,7 +1331,7 @@ impl Definition {
     pub fn get_request_body(
         &self,
         body: &Option<RequestBody>,
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
         iteration: u32,
     ) -> Option<serde_json::Value> {
@@ -1134,11 +1348,66 @@ impl Definition {
         None
     }
 
+    //Resolve variables in a non-JSON request body. File paths are
+    //relative to the test file when they don't exist as given
+    pub fn get_request_payload(
+        &self,
+        payload: &Option<definition::RequestPayload>,
+        state_variables: &HashMap<String, serde_json::Value>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> Option<definition::RequestPayload> {
+        trace!("get_request_payload({:?})", payload);
+        let resolve = |v: &String| self.resolve_variables(v, state_variables, variables, iteration);
+        let resolve_source = |source: &definition::PayloadSource| match source {
+            definition::PayloadSource::Value(v) => definition::PayloadSource::Value(resolve(v)),
+            definition::PayloadSource::File(f) => {
+                let file = resolve(f);
+                let source_dir = Path::new(&self.file_data.filename).parent();
+                definition::PayloadSource::File(match source_dir {
+                    Some(dir) if !Path::new(&file).exists() => {
+                        dir.join(&file).to_string_lossy().to_string()
+                    }
+                    _ => file,
+                })
+            }
+        };
+
+        payload.as_ref().map(|p| match p {
+            definition::RequestPayload::Form(fields) => definition::RequestPayload::Form(
+                fields
+                    .iter()
+                    .map(|f| definition::FormField {
+                        name: resolve(&f.name),
+                        value: resolve(&f.value),
+                    })
+                    .collect(),
+            ),
+            definition::RequestPayload::Multipart(parts) => definition::RequestPayload::Multipart(
+                parts
+                    .iter()
+                    .map(|part| definition::MultipartPart {
+                        name: resolve(&part.name),
+                        source: resolve_source(&part.source),
+                        filename: part.filename.as_ref().map(resolve),
+                        content_type: part.content_type.as_ref().map(resolve),
+                    })
+                    .collect(),
+            ),
+            definition::RequestPayload::Raw(raw) => {
+                definition::RequestPayload::Raw(definition::RawBody {
+                    content_type: resolve(&raw.content_type),
+                    source: resolve_source(&raw.source),
+                })
+            }
+        })
+    }
+
     //Make a body for response validation!
     pub fn get_expected_request_body(
         &self,
         body: &Option<RequestBody>,
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
         iteration: u32,
     ) -> Option<BodyOrSchema> {
@@ -1153,10 +1422,97 @@ impl Definition {
         None
     }
 
+    //Make the header constraints for response validation
+    pub fn get_expected_headers(
+        &self,
+        headers: &[definition::ResponseHeaderDescriptor],
+        state_variables: &HashMap<String, serde_json::Value>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> Vec<definition::ResponseHeaderDescriptor> {
+        let resolve = |v: &String| self.resolve_variables(v, state_variables, variables, iteration);
+        let resolve_all = |vs: &Vec<String>| vs.iter().map(resolve).collect();
+
+        headers
+            .iter()
+            .map(|h| definition::ResponseHeaderDescriptor {
+                specification: h.specification.as_ref().map(|s| StringSpecification {
+                    specification: s.specification.as_ref().map(|spec| match spec {
+                        file::Specification::AnyOf(vs) => {
+                            file::Specification::AnyOf(resolve_all(vs))
+                        }
+                        file::Specification::OneOf(vs) => {
+                            file::Specification::OneOf(resolve_all(vs))
+                        }
+                        file::Specification::NoneOf(vs) => {
+                            file::Specification::NoneOf(resolve_all(vs))
+                        }
+                        file::Specification::Value(v) => file::Specification::Value(resolve(v)),
+                        file::Specification::UnTaggedValue(v) => {
+                            file::Specification::UnTaggedValue(resolve(v))
+                        }
+                    }),
+                    ..s.clone()
+                }),
+                ..h.clone()
+            })
+            .collect()
+    }
+
+    //Make the raw body constraints for response validation
+    pub fn get_expected_content(
+        &self,
+        content: &Option<file::ContentSpecification>,
+        state_variables: &HashMap<String, serde_json::Value>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> Option<file::ContentSpecification> {
+        let resolve = |v: &String| self.resolve_variables(v, state_variables, variables, iteration);
+        content.as_ref().map(|c| file::ContentSpecification {
+            text: c.text.as_ref().map(resolve),
+            contains: c.contains.as_ref().map(resolve),
+            ..c.clone()
+        })
+    }
+
+    //Make the per-path assertions for response validation
+    pub fn get_expected_assertions(
+        &self,
+        assertions: &[definition::ResponseAssertion],
+        state_variables: &HashMap<String, serde_json::Value>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> Vec<definition::ResponseAssertion> {
+        let resolve = |v: &String| self.resolve_variables(v, state_variables, variables, iteration);
+        let resolve_value = |v: &serde_json::Value| match v {
+            serde_json::Value::String(s) => serde_json::Value::String(resolve(s)),
+            _ => v.clone(),
+        };
+
+        assertions
+            .iter()
+            .map(|a| definition::ResponseAssertion {
+                path: a.path.clone(),
+                operator: match &a.operator {
+                    definition::AssertionOperator::Equals(v) => {
+                        definition::AssertionOperator::Equals(resolve_value(v))
+                    }
+                    definition::AssertionOperator::NotEquals(v) => {
+                        definition::AssertionOperator::NotEquals(resolve_value(v))
+                    }
+                    definition::AssertionOperator::Contains(v) => {
+                        definition::AssertionOperator::Contains(resolve_value(v))
+                    }
+                    operator => operator.clone(),
+                },
+            })
+            .collect()
+    }
+
     pub fn get_compare_body(
         &self,
         compare: &definition::CompareDescriptor,
-        state_variables: &HashMap<String, String>,
+        state_variables: &HashMap<String, serde_json::Value>,
         variables: &[Variable],
         iteration: u32,
     ) -> Option<serde_json::Value> {
@@ -1300,9 +1656,11 @@ mod tests {
             platform_id: None,
             project: None,
             environment: None,
-            requires: None,
+            requires: vec![],
             tags: vec![],
             iterate: 0,
+            timeout: None,
+            retry: None,
             variables: vec![],
             global_variables: vec![],
             stages: vec![],
@@ -1332,9 +1690,11 @@ mod tests {
             platform_id: None,
             project: None,
             environment: None,
-            requires: None,
+            requires: vec![],
             tags: vec![],
             iterate: 0,
+            timeout: None,
+            retry: None,
             variables: vec![Variable {
                 name: "my_var".to_string(),
                 value: ValueOrDatumOrFileOrSecret::Value {
@@ -1389,9 +1749,11 @@ mod tests {
             platform_id: None,
             project: None,
             environment: None,
-            requires: None,
+            requires: vec![],
             tags: vec![],
             iterate: 0,
+            timeout: None,
+            retry: None,
             variables: vec![Variable {
                 name: "my_var".to_string(),
                 value: ValueOrDatumOrFileOrSecret::Value {
@@ -1430,4 +1792,589 @@ mod tests {
             td.get_request_body(&Some(body), &HashMap::new(), vars.as_slice(), 1)
         )
     }
+
+    #[test]
+    fn body_sole_placeholders_keep_their_type() {
+        let variable = |name: &str, value: serde_json::Value| Variable {
+            name: name.to_string(),
+            value: ValueOrDatumOrFileOrSecret::Value { value },
+            source_path: "path".to_string(),
+        };
+        let td = Definition {
+            name: None,
+            description: None,
+            id: None,
+            platform_id: None,
+            project: None,
+            environment: None,
+            requires: vec![],
+            tags: vec![],
+            iterate: 0,
+            timeout: None,
+            retry: None,
+            variables: vec![
+                variable("count", serde_json::json!(3)),
+                variable("enabled", serde_json::json!(true)),
+                variable("code", serde_json::json!("007")),
+            ],
+            global_variables: vec![],
+            stages: vec![],
+            setup: None,
+            cleanup: CleanupDescriptor {
+                onsuccess: None,
+                onfailure: None,
+                always: None,
+            },
+            disabled: false,
+            file_data: File::default(),
+            index: 0,
+        };
+        let state = HashMap::from([
+            ("user".to_string(), serde_json::json!({"id": 1})),
+            ("tags".to_string(), serde_json::json!(["a", "b"])),
+            ("token".to_string(), serde_json::json!("abc")),
+            ("zip".to_string(), serde_json::json!("42")),
+            ("flag".to_string(), serde_json::json!("true")),
+            ("nothing".to_string(), serde_json::json!("null")),
+            ("total".to_string(), serde_json::json!(42)),
+        ]);
+
+        let body = RequestBody {
+            data: BodyOrSchema::Body(serde_json::json!({
+                "count": "${count}",
+                "enabled": "${enabled}",
+                "code": "${code}",
+                "user": "${user}",
+                "tags": ["${tags}", "${token}"],
+                "label": "id-${count}-${token}",
+                "${token}_key": "${count * 2}",
+                "zip": "${zip}",
+                "flag": "${flag}",
+                "nothing": "${nothing}",
+                "total": "${total}",
+                "unknown": "${missing}"
+            })),
+            matches_variable: true.into(),
+        };
+
+        assert_eq!(
+            Some(serde_json::json!({
+                "count": 3,
+                "enabled": true,
+                "code": "007",
+                "user": {"id": 1},
+                "tags": [["a", "b"], "abc"],
+                "label": "id-3-abc",
+                "abc_key": 6,
+                "zip": "42",
+                "flag": "true",
+                "nothing": "null",
+                "total": 42,
+                "unknown": "${missing}"
+            })),
+            td.get_request_body(&Some(body), &state, td.variables.as_slice(), 1)
+        )
+    }
+
+    #[test]
+    fn payload_withvars_changed() {
+        let td = Definition {
+            name: None,
+            description: None,
+            id: None,
+            platform_id: None,
+            project: None,
+            environment: None,
+            requires: vec![],
+            tags: vec![],
+            iterate: 0,
+            timeout: None,
+            retry: None,
+            variables: vec![Variable {
+                name: "user".to_string(),
+                value: ValueOrDatumOrFileOrSecret::Value {
+                    value: serde_json::Value::from("bob".to_string()),
+                },
+                source_path: "path".to_string(),
+            }],
+            global_variables: vec![],
+            stages: vec![],
+            setup: None,
+            cleanup: CleanupDescriptor {
+                onsuccess: None,
+                onfailure: None,
+                always: None,
+            },
+            disabled: false,
+            file_data: File {
+                filename: "tests/upload.jkt".to_string(),
+                ..File::default()
+            },
+            index: 0,
+        };
+
+        let form = definition::RequestPayload::Form(vec![
+            definition::FormField {
+                name: "username".to_string(),
+                value: "${user}".to_string(),
+            },
+            definition::FormField {
+                name: "token".to_string(),
+                value: "${token}".to_string(),
+            },
+        ]);
+        let state_variables = HashMap::from([("token".to_string(), serde_json::json!("abc"))]);
+
+        assert_eq!(
+            Some(definition::RequestPayload::Form(vec![
+                definition::FormField {
+                    name: "username".to_string(),
+                    value: "bob".to_string(),
+                },
+                definition::FormField {
+                    name: "token".to_string(),
+                    value: "abc".to_string(),
+                },
+            ])),
+            td.get_request_payload(&Some(form), &state_variables, &td.variables, 1)
+        );
+
+        let multipart = definition::RequestPayload::Multipart(vec![definition::MultipartPart {
+            name: "avatar".to_string(),
+            source: definition::PayloadSource::File("${user}.png".to_string()),
+            filename: None,
+            content_type: Some("image/png".to_string()),
+        }]);
+
+        assert_eq!(
+            Some(definition::RequestPayload::Multipart(vec![
+                definition::MultipartPart {
+                    name: "avatar".to_string(),
+                    source: definition::PayloadSource::File(
+                        Path::new("tests")
+                            .join("bob.png")
+                            .to_string_lossy()
+                            .to_string()
+                    ),
+                    filename: None,
+                    content_type: Some("image/png".to_string()),
+                }
+            ])),
+            td.get_request_payload(&Some(multipart), &HashMap::new(), &td.variables, 1)
+        );
+    }
+
+    #[test]
+    fn request_allows_a_single_body_kind() {
+        let request = UnvalidatedRequest {
+            url: "https://localhost".to_string(),
+            form: Some(vec![]),
+            raw: Some(file::UnvalidatedRawBody {
+                value: Some("hello".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "Requests can contain only one of body, form, multipart or raw",
+            definition::RequestDescriptor::new(request, &[])
+                .unwrap_err()
+                .reason
+        );
+
+        let request = UnvalidatedRequest {
+            url: "https://localhost".to_string(),
+            multipart: Some(vec![file::UnvalidatedMultipartPart {
+                name: "part".to_string(),
+                value: Some("hello".to_string()),
+                file: Some("hello.txt".to_string()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "Multipart part \"part\" must specify either a value or a file",
+            definition::RequestDescriptor::new(request, &[])
+                .unwrap_err()
+                .reason
+        );
+    }
+
+    #[test]
+    fn retry_backoff_delays() {
+        let retry = |backoff| {
+            definition::RetryDescriptor::new_opt(Some(file::UnvalidatedRetry {
+                attempts: 5,
+                delay: Some(100),
+                backoff: Some(backoff),
+                max_delay: Some(350),
+                ..Default::default()
+            }))
+            .unwrap()
+            .unwrap()
+        };
+
+        let fixed = retry(file::Backoff::Fixed);
+        assert_eq!(
+            vec![100, 100, 100],
+            (1..4).map(|a| fixed.delay_after(a)).collect::<Vec<u64>>()
+        );
+        assert!(fixed.retries_any_failure());
+
+        let exponential = retry(file::Backoff::Exponential);
+        assert_eq!(
+            vec![100, 200, 350],
+            (1..4)
+                .map(|a| exponential.delay_after(a))
+                .collect::<Vec<u64>>()
+        );
+
+        assert!(
+            definition::RetryDescriptor::new_opt(Some(file::UnvalidatedRetry::default())).is_err()
+        );
+    }
+
+    #[test]
+    fn zero_timeouts_are_rejected() {
+        let validate = |yaml: &str| {
+            let file: File = serde_yaml::from_str(yaml).unwrap();
+            validation::validate_file(file, &[], None, None, 0).map(|_| ())
+        };
+
+        assert!(validate(
+            "timeout: 1
+request:
+  url: http://localhost"
+        )
+        .is_ok());
+        assert_eq!(
+            "timeout must be greater than 0",
+            validate(
+                "timeout: 0
+request:
+  url: http://localhost"
+            )
+            .unwrap_err()
+            .reason
+        );
+        assert_eq!(
+            "stage 2 timeout must be greater than 0",
+            validate(
+                "stages:\n- request:\n    url: http://localhost\n- request:\n    url: http://localhost\n  timeout: 0"
+            )
+            .unwrap_err()
+            .reason
+        );
+    }
+
+    #[test]
+    fn poll_max_duration_covers_an_interval() {
+        let poll = |yaml: &str| {
+            definition::PollDescriptor::new_opt(Some(serde_yaml::from_str(yaml).unwrap()), &[])
+        };
+
+        assert!(poll("interval: 500\nmaxDuration: 500\nuntil:\n  status: 200").is_ok());
+        assert_eq!(
+            "Poll maxDuration (0) must be at least the interval (1000)",
+            poll("maxDuration: 0\nuntil:\n  status: 200")
+                .unwrap_err()
+                .reason
+        );
+    }
+
+    #[test]
+    fn header_and_cookie_extraction_require_a_field() {
+        let response = |extract: &str| {
+            let response: file::UnvalidatedResponse =
+                serde_yaml::from_str(&format!("extract:\n{extract}")).unwrap();
+            definition::ResponseDescriptor::new_opt(Some(response), &[])
+        };
+
+        assert!(response("- name: location\n  source: header\n  field: Location").is_ok());
+        assert!(response("- name: code\n  source: status").is_ok());
+        assert_eq!(
+            "Extracting `session` from a cookie requires a field naming it",
+            response("- name: session\n  source: cookie")
+                .unwrap_err()
+                .reason
+        );
+    }
+
+    #[test]
+    fn response_json_paths_are_validated() {
+        let response = |yaml: &str| {
+            let response: file::UnvalidatedResponse = serde_yaml::from_str(yaml).unwrap();
+            definition::ResponseDescriptor::new_opt(Some(response), &[])
+        };
+
+        assert!(response("extract:\n- name: id\n  field: $.items[?@.name == 'x'].id").is_ok());
+        assert!(response("ignore:\n- $..createdAt\n- items.updatedAt").is_ok());
+        assert!(response("ignore:\n- $.items[").is_err());
+        assert!(response("extract:\n- name: id\n  field: $.items[?@.name ==]").is_err());
+    }
+
+    #[test]
+    fn assertions_require_exactly_one_operator() {
+        let assertion =
+            |yaml: &str| definition::ResponseAssertion::new(serde_yaml::from_str(yaml).unwrap());
+
+        assert_eq!(
+            definition::AssertionOperator::Equals(serde_json::Value::Null),
+            assertion("path: id\nequals: null").unwrap().operator
+        );
+        assert_eq!(
+            definition::AssertionOperator::Absent,
+            assertion("path: id\nexists: false").unwrap().operator
+        );
+        assert_eq!(
+            "Assertion on `id`: exactly one operator is required but 2 were given",
+            assertion("path: id\nequals: 1\ngreaterThan: 0")
+                .unwrap_err()
+                .reason
+        );
+        assert!(assertion("path: id").is_err());
+        assert!(assertion("path: id\nmatches: '('").is_err());
+        assert!(assertion("path: $.items[\nexists: true").is_err());
+    }
+
+    #[test]
+    fn validate_definitions_reports_semantic_problems() {
+        let definition = |yaml: &str, index: usize| {
+            let mut file: File = serde_yaml::from_str(yaml).unwrap();
+            file.filename = format!("test{}.jkt", index);
+            validation::validate_file(file, &[], None, None, index).unwrap()
+        };
+        let tests = vec![
+            definition(
+                "id: login\ndisabled: true\nrequest:\n  url: http://localhost/login\n",
+                0,
+            ),
+            definition(
+                r#"
+id: login
+requires: [login, missing]
+variables:
+- name: unused
+  value: 1
+- name: limit
+  value: 10
+stages:
+- request:
+    url: localhost/orders?limit=${limit}
+  compare:
+    url: localhost/orders?limit=${limit}
+  response:
+    bodySchema:
+      type: Object
+      schema:
+        name:
+          type: String
+          length: 8
+          maxLength: 4
+          pattern: "("
+        count:
+          type: Integer
+          min: 10
+          max: 1
+- request:
+    url: ${baseUrl}/orders/${ordr}
+"#,
+                1,
+            ),
+        ];
+
+        let diagnostics: Vec<(validation::Severity, String, String)> =
+            validation::validate_definitions(&tests, &[])
+                .into_iter()
+                .map(|d| {
+                    assert_eq!("test1.jkt", d.filename);
+                    (d.severity, d.location, d.message)
+                })
+                .collect();
+        let has = |severity: validation::Severity, location: &str, message: &str| {
+            diagnostics
+                .iter()
+                .any(|d| d.0 == severity && d.1 == location && d.2.starts_with(message))
+        };
+
+        use validation::Severity::{Error, Warning};
+        assert!(has(Error, "id", "id `login` is already used by test0.jkt"));
+        assert!(has(
+            Warning,
+            "requires",
+            "required test `login` is disabled"
+        ));
+        assert!(has(
+            Error,
+            "requires",
+            "no test has the required id `missing`"
+        ));
+        assert!(has(Warning, "variables", "variable `unused` is never used"));
+        assert!(!diagnostics.iter().any(|d| d.2.contains("`limit`")));
+        assert!(has(
+            Error,
+            "stage 1 request.url",
+            "invalid URL `localhost/orders"
+        ));
+        assert!(has(Error, "stage 1 compare.url", "invalid URL"));
+        assert!(has(
+            Warning,
+            "stage 1 compare",
+            "the compare request is identical"
+        ));
+        assert!(has(
+            Error,
+            "stage 1 response.bodySchema.name",
+            "length 8 contradicts minLength or maxLength"
+        ));
+        assert!(has(
+            Error,
+            "stage 1 response.bodySchema.name",
+            "invalid regex `(`"
+        ));
+        assert!(has(
+            Error,
+            "stage 1 response.bodySchema.count",
+            "min 10 is greater than max 1"
+        ));
+        assert!(has(Error, "stage 2", "variable `ordr` is not provided"));
+        assert!(has(Error, "stage 2", "variable `baseUrl` is not provided"));
+        assert!(!diagnostics.iter().any(|d| d.1 == "stage 2 request.url"));
+        assert_eq!(12, diagnostics.len());
+    }
+
+    #[test]
+    fn validate_definitions_warns_about_unrequired_extractions() {
+        let definition = |yaml: &str, index: usize| {
+            let mut file: File = serde_yaml::from_str(yaml).unwrap();
+            file.filename = format!("test{}.jkt", index);
+            validation::validate_file(file, &[], None, None, index).unwrap()
+        };
+        let tests = vec![
+            definition(
+                "id: login\nrequest:\n  url: http://localhost/login\nresponse:\n  extract:\n  - name: token\n    field: token\n",
+                0,
+            ),
+            definition(
+                "requires: login\nrequest:\n  url: http://localhost/orders/${token}\n",
+                1,
+            ),
+            definition("request:\n  url: http://localhost/items/${token}\n", 2),
+        ];
+
+        let diagnostics: Vec<(String, validation::Severity, String)> =
+            validation::validate_definitions(&tests, &[])
+                .into_iter()
+                .map(|d| (d.filename, d.severity, d.rule.to_string()))
+                .collect();
+
+        assert_eq!(
+            vec![(
+                "test2.jkt".to_string(),
+                validation::Severity::Warning,
+                "unrequired-extraction".to_string()
+            )],
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn validate_definition_reports_source_positions() {
+        let yaml = r#"id: orders
+variables:
+- name: unused
+  value: 1
+stages:
+- request:
+    url: http://localhost/orders
+- request:
+    url: http://localhost/orders/${ordr}
+"#;
+        let mut file: File = serde_yaml::from_str(yaml).unwrap();
+        file.filename = "./tests/orders.jkt".to_string();
+        let tests = vec![validation::validate_file(file, &[], None, None, 0).unwrap()];
+
+        let diagnostics = validation::validate_definition(&tests, 0, &[], Some(yaml));
+        let position = |rule: &str| {
+            diagnostics
+                .iter()
+                .find(|d| d.rule == rule)
+                .map(|d| (d.line, d.column))
+        };
+        assert_eq!(Some((Some(3), Some(3))), position("unused-variable"));
+        assert_eq!(Some((Some(9), Some(34))), position("unknown-variable"));
+
+        let json = validation::diagnostics_to_json(&diagnostics, 1);
+        assert_eq!(1, json["errors"]);
+        assert_eq!(1, json["warnings"]);
+        assert_eq!("unknown-variable", json["diagnostics"][0]["rule"]);
+        assert_eq!(9, json["diagnostics"][0]["line"]);
+
+        let sarif = validation::diagnostics_to_sarif(&diagnostics, "1.0.0");
+        assert_eq!("2.1.0", sarif["version"]);
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!("error", result["level"]);
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!("tests/orders.jkt", location["artifactLocation"]["uri"]);
+        assert_eq!(9, location["region"]["startLine"]);
+    }
+
+    #[test]
+    fn source_positions_find_the_right_stage() {
+        let yaml = r#"id: orders
+stages:
+- request:
+    url: http://localhost/orders
+  response:
+    bodySchema:
+      type: Object
+      schema:
+        name:
+          type: String
+          pattern: "^a"
+# the second stage
+- request:
+    url: http://localhost/orders/1
+  response:
+    bodySchema:
+      type: Object
+      schema:
+        name:
+          type: String
+          pattern: "("
+cleanup:
+  always:
+    url: http://localhost/orders/1
+"#;
+        let mut file: File = serde_yaml::from_str(yaml).unwrap();
+        file.filename = "orders.jkt".to_string();
+        let tests = vec![validation::validate_file(file, &[], None, None, 0).unwrap()];
+
+        let diagnostics = validation::validate_definition(&tests, 0, &[], Some(yaml));
+        let pattern = diagnostics
+            .iter()
+            .find(|d| d.rule == "invalid-regex")
+            .unwrap();
+        assert_eq!("stage 2 response.bodySchema.name", pattern.location);
+        assert_eq!((Some(21), Some(21)), (pattern.line, pattern.column));
+
+        assert_eq!(
+            Some((14, 5)),
+            validation::source_position(yaml, "stage 2 request.url", &[])
+        );
+        assert_eq!(
+            Some((4, 5)),
+            validation::source_position(yaml, "stage 1 request.url", &[])
+        );
+        assert_eq!(
+            Some((3, 3)),
+            validation::source_position(
+                "id: single\nrequest:\n  url: http://localhost\n",
+                "stage 1 request.url",
+                &[]
+            )
+        );
+    }
 }
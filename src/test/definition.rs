@@ -1,14 +1,22 @@
 use crate::{
+    json::extractor::{extract_json, is_json_path},
     test,
     test::{
         file,
-        file::{NumericSpecification, ValueOrNumericSpecification},
+        file::{
+            Checker, ContentSpecification, JsonType, NumericSpecification, StringSpecification,
+            ValueOrNumericSpecification,
+        },
         http, validation,
     },
 };
 use log::trace;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::{cell::Cell, collections::HashSet};
+use serde_json_path::JsonPath;
+use std::{cell::Cell, collections::HashSet, error::Error, path::Path};
+use ulid::Ulid;
+use validated::Validated;
 
 use super::file::BodyOrSchema;
 use crate::test::Variable;
@@ -28,6 +36,199 @@ pub struct RequestDescriptor {
     pub params: Vec<http::Parameter>,
     pub headers: Vec<http::Header>,
     pub body: Option<RequestBody>,
+    pub payload: Option<RequestPayload>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PayloadSource {
+    Value(String),
+    File(String),
+}
+
+impl PayloadSource {
+    fn new(
+        value: Option<String>,
+        file: Option<String>,
+        context: &str,
+    ) -> Result<PayloadSource, validation::Error> {
+        match (value, file) {
+            (Some(v), None) => Ok(PayloadSource::Value(v)),
+            (None, Some(f)) => Ok(PayloadSource::File(f)),
+            _ => Err(validation::Error {
+                reason: format!("{} must specify either a value or a file", context),
+            }),
+        }
+    }
+
+    //Files are read asynchronously, a large upload doesn't hold up other tests
+    async fn bytes(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        match self {
+            PayloadSource::Value(v) => Ok(v.as_bytes().to_vec()),
+            PayloadSource::File(f) => tokio::fs::read(f)
+                .await
+                .map_err(|e| Box::from(format!("unable to read request file ({}): {}", f, e))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FormField {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MultipartPart {
+    pub name: String,
+    pub source: PayloadSource,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RawBody {
+    pub content_type: String,
+    pub source: PayloadSource,
+}
+
+//Request bodies which aren't sent as JSON
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RequestPayload {
+    Form(Vec<FormField>),
+    Multipart(Vec<MultipartPart>),
+    Raw(RawBody),
+}
+
+impl RequestPayload {
+    pub fn new_opt(
+        request: &file::UnvalidatedRequest,
+    ) -> Result<Option<RequestPayload>, validation::Error> {
+        let specified = [
+            request.body.is_some(),
+            request.form.is_some(),
+            request.multipart.is_some(),
+            request.raw.is_some(),
+        ]
+        .into_iter()
+        .filter(|b| *b)
+        .count();
+
+        if specified > 1 {
+            return Err(validation::Error {
+                reason: "Requests can contain only one of body, form, multipart or raw".to_string(),
+            });
+        }
+
+        if let Some(form) = &request.form {
+            return Ok(Some(RequestPayload::Form(
+                form.iter()
+                    .map(|f| FormField {
+                        name: f.name.clone(),
+                        value: f.value.clone(),
+                    })
+                    .collect(),
+            )));
+        }
+
+        if let Some(multipart) = &request.multipart {
+            return multipart
+                .iter()
+                .map(|p| {
+                    PayloadSource::new(
+                        p.value.clone(),
+                        p.file.clone(),
+                        format!("Multipart part \"{}\"", p.name).as_str(),
+                    )
+                    .map(|source| MultipartPart {
+                        name: p.name.clone(),
+                        source,
+                        filename: p.filename.clone(),
+                        content_type: p.content_type.clone(),
+                    })
+                })
+                .collect::<Result<Vec<MultipartPart>, validation::Error>>()
+                .map(|parts| Some(RequestPayload::Multipart(parts)));
+        }
+
+        if let Some(raw) = &request.raw {
+            let source = PayloadSource::new(raw.value.clone(), raw.file.clone(), "Raw body")?;
+            let content_type = raw.content_type.clone().unwrap_or(
+                match source {
+                    PayloadSource::Value(_) => "text/plain",
+                    PayloadSource::File(_) => "application/octet-stream",
+                }
+                .to_string(),
+            );
+            return Ok(Some(RequestPayload::Raw(RawBody {
+                content_type,
+                source,
+            })));
+        }
+
+        Ok(None)
+    }
+
+    //Returns the content type and bytes to send
+    pub async fn encode(&self) -> Result<(String, Vec<u8>), Box<dyn Error + Send + Sync>> {
+        match self {
+            RequestPayload::Form(fields) => Ok((
+                "application/x-www-form-urlencoded".to_string(),
+                url::form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(fields.iter().map(|f| (&f.name, &f.value)))
+                    .finish()
+                    .into_bytes(),
+            )),
+            RequestPayload::Multipart(parts) => {
+                let boundary = format!("jikken-{}", Ulid::new());
+                // line breaks would start new header lines in the part
+                let escape = |s: &str| {
+                    s.replace('"', "%22")
+                        .replace('\r', "%0D")
+                        .replace('\n', "%0A")
+                };
+                let mut data: Vec<u8> = Vec::new();
+                for part in parts {
+                    data.extend(format!("--{}\r\n", boundary).as_bytes());
+                    let filename = part.filename.clone().or(match &part.source {
+                        PayloadSource::File(f) => Path::new(f)
+                            .file_name()
+                            .map(|n| n.to_string_lossy().to_string()),
+                        PayloadSource::Value(_) => None,
+                    });
+                    data.extend(
+                        format!(
+                            "Content-Disposition: form-data; name=\"{}\"",
+                            escape(&part.name)
+                        )
+                        .as_bytes(),
+                    );
+                    if let Some(f) = filename {
+                        data.extend(format!("; filename=\"{}\"", escape(&f)).as_bytes());
+                    }
+                    data.extend(b"\r\n");
+
+                    let content_type = part.content_type.clone().or(match &part.source {
+                        PayloadSource::File(_) => Some("application/octet-stream".to_string()),
+                        PayloadSource::Value(_) => None,
+                    });
+                    if let Some(ct) = content_type {
+                        data.extend(format!("Content-Type: {}\r\n", ct).as_bytes());
+                    }
+                    data.extend(b"\r\n");
+                    data.extend(part.source.bytes().await?);
+                    data.extend(b"\r\n");
+                }
+                data.extend(format!("--{}--\r\n", boundary).as_bytes());
+
+                Ok((format!("multipart/form-data; boundary={}", boundary), data))
+            }
+            RequestPayload::Raw(raw) => Ok((raw.content_type.clone(), raw.source.bytes().await?)),
+        }
+    }
 }
 
 impl RequestDescriptor {
@@ -36,6 +237,8 @@ impl RequestDescriptor {
         variables: &[Variable],
     ) -> Result<RequestDescriptor, validation::Error> {
         trace!("RequestDescriptor::new({:?})", request);
+        let request_payload = RequestPayload::new_opt(&request)?;
+
         let validated_params = match request.params {
             Some(params) => params
                 .iter()
@@ -88,6 +291,7 @@ impl RequestDescriptor {
             params: validated_params,
             headers: validated_headers,
             body: request_body,
+            payload: request_payload,
         })
     }
 
@@ -233,11 +437,32 @@ impl CompareDescriptor {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractionSource {
+    #[default]
+    Body,
+    Header,
+    Status,
+    Cookie,
+}
+
+impl ExtractionSource {
+    pub fn is_body(&self) -> bool {
+        *self == ExtractionSource::Body
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ResponseExtraction {
     pub name: String,
+    //body path, header name or cookie name depending on the source. An empty
+    //body path extracts the whole body
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub field: String,
+    #[serde(default, skip_serializing_if = "ExtractionSource::is_body")]
+    pub source: ExtractionSource,
 }
 
 impl ResponseExtraction {
@@ -245,6 +470,283 @@ impl ResponseExtraction {
         ResponseExtraction {
             name: "".to_string(),
             field: "".to_string(),
+            source: ExtractionSource::Body,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self.source {
+            ExtractionSource::Body if self.field.is_empty() => "body".to_string(),
+            ExtractionSource::Body => self.field.clone(),
+            ExtractionSource::Header => format!("header {}", self.field),
+            ExtractionSource::Status => "status".to_string(),
+            ExtractionSource::Cookie => format!("cookie {}", self.field),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ResponseHeaderDescriptor {
+    pub header: String,
+    pub specification: Option<StringSpecification>,
+    pub absent: bool,
+}
+
+impl ResponseHeaderDescriptor {
+    pub fn new(
+        header: file::UnvalidatedResponseHeader,
+    ) -> Result<ResponseHeaderDescriptor, validation::Error> {
+        let absent = header.absent.unwrap_or(false);
+        if absent && header.has_constraints() {
+            return Err(validation::Error {
+                reason: format!(
+                    "Response header \"{}\" can't be absent and have a value constraint",
+                    header.header
+                ),
+            });
+        }
+
+        let name = header.header.clone();
+        TryInto::<Option<StringSpecification>>::try_into(header)
+            .map(|specification| ResponseHeaderDescriptor {
+                header: name.clone(),
+                specification,
+                absent,
+            })
+            .map_err(|e| validation::Error {
+                reason: format!("Response header \"{}\": {}", name, e),
+            })
+    }
+}
+
+impl Checker for ResponseHeaderDescriptor {
+    type Item = Vec<http::Header>;
+    fn check(
+        &self,
+        val: &Self::Item,
+        formatter: &impl Fn(&str, &str) -> String,
+    ) -> Vec<Validated<(), String>> {
+        let values: Vec<&String> = val
+            .iter()
+            .filter(|h| h.header.eq_ignore_ascii_case(&self.header))
+            .map(|h| &h.value)
+            .collect();
+
+        if self.absent {
+            return match values.first() {
+                Some(v) => vec![Validated::fail(formatter(
+                    format!("header {} to be absent", self.header).as_str(),
+                    format!("{:?}", v).as_str(),
+                ))],
+                None => vec![Validated::Good(())],
+            };
+        }
+
+        if values.is_empty() {
+            return vec![Validated::fail(formatter(
+                format!("header {}", self.header).as_str(),
+                "none",
+            ))];
+        }
+
+        let Some(specification) = &self.specification else {
+            return vec![Validated::Good(())];
+        };
+
+        //A header can be sent multiple times, only one of its values needs to match
+        let header_formatter =
+            |e: &str, a: &str| formatter(&format!("header {} {e}", self.header), a);
+        let checks: Vec<Vec<Validated<(), String>>> = values
+            .iter()
+            .map(|v| specification.check(v, &header_formatter))
+            .collect();
+
+        checks
+            .iter()
+            .find(|c| c.iter().all(|v| !v.is_fail()))
+            .or(checks.first())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AssertionOperator {
+    Equals(serde_json::Value),
+    NotEquals(serde_json::Value),
+    Contains(serde_json::Value),
+    Matches(String),
+    Exists,
+    Absent,
+    Length(ValueOrNumericSpecification<u64>),
+    GreaterThan(f64),
+    LessThan(f64),
+    Type(JsonType),
+}
+
+impl AssertionOperator {
+    fn expectation(&self) -> String {
+        match self {
+            AssertionOperator::Equals(v) => format!("to equal {}", v),
+            AssertionOperator::NotEquals(v) => format!("not to equal {}", v),
+            AssertionOperator::Contains(v) => format!("to contain {}", v),
+            AssertionOperator::Matches(p) => format!("to match pattern {}", p),
+            AssertionOperator::Exists => "to exist".to_string(),
+            AssertionOperator::Absent => "to be absent".to_string(),
+            AssertionOperator::Length(l) => format!(
+                "to have length {}",
+                serde_json::to_string(l).unwrap_or_default()
+            ),
+            AssertionOperator::GreaterThan(n) => format!("to be greater than {}", n),
+            AssertionOperator::LessThan(n) => format!("to be less than {}", n),
+            AssertionOperator::Type(t) => format!("to be of type {}", t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ResponseAssertion {
+    pub path: String,
+    pub operator: AssertionOperator,
+}
+
+impl ResponseAssertion {
+    pub fn new(
+        assertion: file::UnvalidatedAssertion,
+    ) -> Result<ResponseAssertion, validation::Error> {
+        let path = assertion.path;
+        let error = |reason: String| validation::Error {
+            reason: format!("Assertion on `{}`: {}", path, reason),
+        };
+
+        if path.is_empty() {
+            return Err(validation::Error {
+                reason: "Assertions require a path".to_string(),
+            });
+        }
+
+        if is_json_path(&path) {
+            JsonPath::parse(&path).map_err(|e| error(e.to_string()))?;
+        }
+
+        if let Some(Err(e)) = assertion.matches.as_ref().map(|p| Regex::new(p)) {
+            return Err(error(format!("invalid regex supplied for matches: {}", e)));
+        }
+
+        let mut operators: Vec<AssertionOperator> = [
+            assertion.equals.map(AssertionOperator::Equals),
+            assertion.not_equals.map(AssertionOperator::NotEquals),
+            assertion.contains.map(AssertionOperator::Contains),
+            assertion.matches.map(AssertionOperator::Matches),
+            assertion.exists.map(|e| {
+                if e {
+                    AssertionOperator::Exists
+                } else {
+                    AssertionOperator::Absent
+                }
+            }),
+            assertion.absent.map(|a| {
+                if a {
+                    AssertionOperator::Absent
+                } else {
+                    AssertionOperator::Exists
+                }
+            }),
+            assertion.length.map(AssertionOperator::Length),
+            assertion.greater_than.map(AssertionOperator::GreaterThan),
+            assertion.less_than.map(AssertionOperator::LessThan),
+            assertion.type_is.map(AssertionOperator::Type),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if operators.len() != 1 {
+            return Err(error(format!(
+                "exactly one operator is required but {} were given",
+                operators.len()
+            )));
+        }
+
+        Ok(ResponseAssertion {
+            path: path.clone(),
+            operator: operators.remove(0),
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        format!("`{}` {}", self.path, self.operator.expectation())
+    }
+}
+
+impl Checker for ResponseAssertion {
+    type Item = serde_json::Value;
+    fn check(
+        &self,
+        val: &Self::Item,
+        formatter: &impl Fn(&str, &str) -> String,
+    ) -> Vec<Validated<(), String>> {
+        let actual = extract_json(&self.path, 0, val.clone()).ok();
+        let displayed_actual = actual
+            .as_ref()
+            .map(|a| a.to_string())
+            .unwrap_or("none".to_string());
+
+        let passed = match (&self.operator, &actual) {
+            (AssertionOperator::Absent, a) => a.is_none(),
+            (AssertionOperator::NotEquals(e), a) => a.as_ref() != Some(e),
+            (_, None) => false,
+            (AssertionOperator::Exists, Some(_)) => true,
+            (AssertionOperator::Equals(e), Some(a)) => a == e,
+            (AssertionOperator::Contains(e), Some(a)) => match (a, e) {
+                (serde_json::Value::String(s), serde_json::Value::String(part)) => {
+                    s.contains(part.as_str())
+                }
+                (serde_json::Value::Array(items), item) => items.contains(item),
+                (serde_json::Value::Object(o), serde_json::Value::String(key)) => {
+                    o.contains_key(key)
+                }
+                _ => false,
+            },
+            (AssertionOperator::Matches(p), Some(a)) => a
+                .as_str()
+                .and_then(|s| Regex::new(p).ok().map(|re| re.is_match(s)))
+                .unwrap_or(false),
+            (AssertionOperator::Length(l), Some(a)) => {
+                let length = match a {
+                    serde_json::Value::String(s) => Some(s.chars().count()),
+                    serde_json::Value::Array(items) => Some(items.len()),
+                    serde_json::Value::Object(o) => Some(o.len()),
+                    _ => None,
+                };
+
+                return match length {
+                    Some(length) => l.check(&(length as u64), &|e, a| {
+                        formatter(&format!("`{}` length {}", self.path, e), a)
+                    }),
+                    None => vec![Validated::fail(formatter(
+                        &self.describe(),
+                        &displayed_actual,
+                    ))],
+                };
+            }
+            (AssertionOperator::GreaterThan(n), Some(a)) => {
+                a.as_f64().map(|v| v > *n).unwrap_or(false)
+            }
+            (AssertionOperator::LessThan(n), Some(a)) => {
+                a.as_f64().map(|v| v < *n).unwrap_or(false)
+            }
+            (AssertionOperator::Type(t), Some(a)) => JsonType::of(a) == *t,
+        };
+
+        if passed {
+            vec![Validated::Good(())]
+        } else {
+            vec![Validated::fail(formatter(
+                &self.describe(),
+                &displayed_actual,
+            ))]
         }
     }
 }
@@ -254,11 +756,13 @@ impl ResponseExtraction {
 pub struct ResponseDescriptor {
     pub status: Option<ValueOrNumericSpecification<u16>>,
     pub response_time: Option<NumericSpecification<u32>>,
-    pub headers: Vec<http::Header>,
+    pub headers: Vec<ResponseHeaderDescriptor>,
     pub body: Option<RequestBody>,
     pub ignore: Vec<String>,
     pub extract: Vec<ResponseExtraction>,
     pub strict: bool,
+    pub content: Option<ContentSpecification>,
+    pub assertions: Vec<ResponseAssertion>,
 }
 
 // TODO: add validation logic to verify the descriptor is valid
@@ -269,17 +773,19 @@ impl ResponseDescriptor {
     ) -> Result<Option<ResponseDescriptor>, validation::Error> {
         match response {
             Some(res) => {
-                let validated_headers = match res.headers {
-                    Some(headers) => headers
-                        .iter()
-                        .map(|h| http::Header {
-                            header: h.header.clone(),
-                            value: h.value.clone(),
-                            matches_variable: Cell::from(false),
-                        })
-                        .collect(),
-                    None => Vec::new(),
-                };
+                let validated_headers = res
+                    .headers
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(ResponseHeaderDescriptor::new)
+                    .collect::<Result<Vec<ResponseHeaderDescriptor>, validation::Error>>()?;
+
+                let validated_assertions = res
+                    .assertions
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(ResponseAssertion::new)
+                    .collect::<Result<Vec<ResponseAssertion>, validation::Error>>()?;
 
                 //Value in this case means max. So we do a translation
                 let validated_response_time =
@@ -291,8 +797,48 @@ impl ResponseDescriptor {
                         ValueOrNumericSpecification::Schema(s) => s,
                     });
 
+                let validated_content = res
+                    .content
+                    .map(TryInto::<ContentSpecification>::try_into)
+                    .transpose()
+                    .map_err(|e| validation::Error {
+                        reason: format!("Response content: {}", e),
+                    })?;
+
                 let validated_ignore = res.ignore.unwrap_or_default();
                 let validated_extraction: Vec<ResponseExtraction> = res.extract.unwrap_or_default();
+                if let Some(e) = validated_extraction.iter().find(|e| {
+                    matches!(
+                        e.source,
+                        ExtractionSource::Header | ExtractionSource::Cookie
+                    ) && e.field.is_empty()
+                }) {
+                    return Err(validation::Error {
+                        reason: format!(
+                            "Extracting `{}` from a {} requires a field naming it",
+                            e.name,
+                            if e.source == ExtractionSource::Header {
+                                "header"
+                            } else {
+                                "cookie"
+                            }
+                        ),
+                    });
+                }
+
+                let json_paths = validated_ignore.iter().chain(
+                    validated_extraction
+                        .iter()
+                        .filter(|e| e.source.is_body())
+                        .map(|e| &e.field),
+                );
+                for path in json_paths.filter(|p| is_json_path(p)) {
+                    if let Err(e) = JsonPath::parse(path) {
+                        return Err(validation::Error {
+                            reason: e.to_string(),
+                        });
+                    }
+                }
 
                 if res.body.is_some() && res.body_schema.is_some() {
                     return Err(validation::Error {
@@ -347,6 +893,8 @@ impl ResponseDescriptor {
                     ignore: validated_ignore,
                     extract: validated_extraction,
                     strict: res.strict.unwrap_or(true),
+                    content: validated_content,
+                    assertions: validated_assertions,
                 }))
             }
             None => Ok(None),
@@ -364,6 +912,10 @@ pub struct StageDescriptor {
     //I would prefer to do this is Option<chrono::duration>
     //But it requires too much effort in serialization/deserialization
     pub delay: Option<u64>,
+    //milliseconds allowed for the stage's requests, falls back to the configured default
+    pub timeout: Option<u64>,
+    pub retry: Option<RetryDescriptor>,
+    pub poll: Option<PollDescriptor>,
     //#[serde(skip_serializing)]
     //pub source_path: String,
 }
@@ -382,6 +934,9 @@ impl StageDescriptor {
             // source_path: source_path.to_string(),
             name: stage.name,
             delay: stage.delay,
+            timeout: stage.timeout,
+            retry: RetryDescriptor::new_opt(stage.retry)?,
+            poll: PollDescriptor::new_opt(stage.poll, variables)?,
         })
     }
 
@@ -405,6 +960,9 @@ impl StageDescriptor {
                 // source_path: source_path.to_string(),
                 name: None,
                 delay: None,
+                timeout: None,
+                retry: None,
+                poll: None,
             });
             count += 1;
         }
@@ -455,6 +1013,112 @@ impl StageDescriptor {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryDescriptor {
+    pub attempts: u32,
+    pub delay: u64,
+    pub backoff: file::Backoff,
+    pub max_delay: Option<u64>,
+    pub on_status: Vec<u16>,
+    pub on_connection_error: bool,
+}
+
+impl RetryDescriptor {
+    const DEFAULT_DELAY: u64 = 1000;
+
+    pub fn new_opt(
+        retry_opt: Option<file::UnvalidatedRetry>,
+    ) -> Result<Option<RetryDescriptor>, validation::Error> {
+        let Some(retry) = retry_opt else {
+            return Ok(None);
+        };
+
+        if retry.attempts == 0 {
+            return Err(validation::Error {
+                reason: "Retry attempts must be at least 1".to_string(),
+            });
+        }
+
+        Ok(Some(RetryDescriptor {
+            attempts: retry.attempts,
+            delay: retry.delay.unwrap_or(Self::DEFAULT_DELAY),
+            backoff: retry.backoff.unwrap_or_default(),
+            max_delay: retry.max_delay,
+            on_status: retry.on_status.unwrap_or_default(),
+            on_connection_error: retry.on_connection_error.unwrap_or_default(),
+        }))
+    }
+
+    //Without any conditions every failure is retried
+    pub fn retries_any_failure(&self) -> bool {
+        self.on_status.is_empty() && !self.on_connection_error
+    }
+
+    //Milliseconds to wait after the given (1 based) attempt failed
+    pub fn delay_after(&self, attempt: u32) -> u64 {
+        let delay = match self.backoff {
+            file::Backoff::Fixed => self.delay,
+            file::Backoff::Exponential => self
+                .delay
+                .saturating_mul(2_u64.saturating_pow(attempt.saturating_sub(1))),
+        };
+
+        self.max_delay.map_or(delay, |max| delay.min(max))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PollDescriptor {
+    pub interval: u64,
+    pub max_duration: u64,
+    //the response is polled until it passes these checks
+    pub until: ResponseDescriptor,
+}
+
+impl PollDescriptor {
+    const DEFAULT_INTERVAL: u64 = 1000;
+
+    pub fn new_opt(
+        poll_opt: Option<file::UnvalidatedPoll>,
+        variables: &[Variable],
+    ) -> Result<Option<PollDescriptor>, validation::Error> {
+        let Some(poll) = poll_opt else {
+            return Ok(None);
+        };
+
+        let interval = poll.interval.unwrap_or(Self::DEFAULT_INTERVAL);
+        if interval == 0 {
+            return Err(validation::Error {
+                reason: "Poll interval must be greater than 0".to_string(),
+            });
+        }
+
+        // a shorter duration gives up before the request is polled a second time
+        if poll.max_duration < interval {
+            return Err(validation::Error {
+                reason: format!(
+                    "Poll maxDuration ({}) must be at least the interval ({})",
+                    poll.max_duration, interval
+                ),
+            });
+        }
+
+        let until = ResponseDescriptor::new_opt(Some(poll.until), variables)?.ok_or_else(|| {
+            validation::Error {
+                reason: "Poll requires an until condition".to_string(),
+            }
+        })?;
+
+        Ok(Some(PollDescriptor {
+            interval,
+            max_duration: poll.max_duration,
+            until,
+        }))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct RequestResponseDescriptor {
     pub request: RequestDescriptor,
@@ -476,11 +1140,13 @@ impl RequestResponseDescriptor {
     }
 }
 
+#[derive(Clone)]
 pub struct ResolvedRequest {
     pub url: String,
     pub method: http::Method,
     pub headers: Vec<(String, String)>,
     pub body: Option<serde_json::Value>,
+    pub payload: Option<RequestPayload>,
 }
 
 impl ResolvedRequest {
@@ -495,6 +1161,120 @@ impl ResolvedRequest {
             method,
             headers,
             body,
+            payload: None,
+        }
+    }
+
+    pub fn with_payload(self, payload: Option<RequestPayload>) -> ResolvedRequest {
+        ResolvedRequest { payload, ..self }
+    }
+
+    //Placeholders left after resolution would otherwise be sent to the server as is
+    pub fn unresolved_variables(&self) -> Vec<String> {
+        let mut texts: Vec<&str> = vec![&self.url];
+        self.headers.iter().for_each(|(_, v)| texts.push(v));
+
+        match &self.payload {
+            Some(RequestPayload::Form(fields)) => fields.iter().for_each(|f| texts.push(&f.value)),
+            Some(RequestPayload::Multipart(parts)) => parts.iter().for_each(|p| {
+                if let PayloadSource::Value(v) = &p.source {
+                    texts.push(v);
+                }
+            }),
+            Some(RequestPayload::Raw(RawBody {
+                source: PayloadSource::Value(v),
+                ..
+            })) => texts.push(v),
+            _ => {}
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        let body_names = self
+            .body
+            .as_ref()
+            .map(test::expression::json_placeholder_variables)
+            .unwrap_or_default();
+        for name in texts
+            .into_iter()
+            .flat_map(test::expression::placeholder_variables)
+            .chain(body_names)
+        {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        names
+    }
+
+    //Escaped `$${...}` placeholders are sent as literal `${...}` text
+    pub fn unescape_placeholders(self) -> ResolvedRequest {
+        fn unescape_value(value: serde_json::Value) -> serde_json::Value {
+            match value {
+                serde_json::Value::String(s) => {
+                    serde_json::Value::String(test::expression::unescape_placeholders(&s))
+                }
+                serde_json::Value::Array(a) => {
+                    serde_json::Value::Array(a.into_iter().map(unescape_value).collect())
+                }
+                serde_json::Value::Object(o) => serde_json::Value::Object(
+                    o.into_iter()
+                        .map(|(k, v)| {
+                            (
+                                test::expression::unescape_placeholders(&k),
+                                unescape_value(v),
+                            )
+                        })
+                        .collect(),
+                ),
+                other => other,
+            }
+        }
+
+        fn unescape_source(source: PayloadSource) -> PayloadSource {
+            match source {
+                PayloadSource::Value(v) => {
+                    PayloadSource::Value(test::expression::unescape_placeholders(&v))
+                }
+                file => file,
+            }
+        }
+
+        let payload = self.payload.map(|payload| match payload {
+            RequestPayload::Form(fields) => RequestPayload::Form(
+                fields
+                    .into_iter()
+                    .map(|f| FormField {
+                        value: test::expression::unescape_placeholders(&f.value),
+                        ..f
+                    })
+                    .collect(),
+            ),
+            RequestPayload::Multipart(parts) => RequestPayload::Multipart(
+                parts
+                    .into_iter()
+                    .map(|p| MultipartPart {
+                        source: unescape_source(p.source),
+                        ..p
+                    })
+                    .collect(),
+            ),
+            RequestPayload::Raw(raw) => RequestPayload::Raw(RawBody {
+                source: unescape_source(raw.source),
+                ..raw
+            }),
+        });
+
+        ResolvedRequest {
+            url: test::expression::unescape_placeholders(&self.url),
+            method: self.method,
+            headers: self
+                .headers
+                .into_iter()
+                .map(|(k, v)| (k, test::expression::unescape_placeholders(&v)))
+                .collect(),
+            body: self.body.map(unescape_value),
+            payload,
         }
     }
 }
@@ -0,0 +1,709 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{
+    format::{Item, StrftimeItems},
+    Utc,
+};
+use log::{debug, error};
+use sha2::{Digest, Sha256};
+use std::{error::Error, fmt};
+
+// Expressions evaluated inside `${...}` placeholders, e.g. `${base64('user:pass')}`,
+// `${now("%Y-%m-%d")}` or `${count + 1}`. Identifiers are looked up as variables
+// and text has to be quoted. Variable names may contain hyphens, so `${count-1}`
+// names a variable and subtracting takes spaces: `${count - 1}`.
+
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("base64", 1, 1),
+    ("base64decode", 1, 1),
+    ("sha256", 1, 1),
+    ("uuid", 0, 0),
+    ("upper", 1, 1),
+    ("lower", 1, 1),
+    ("trim", 1, 1),
+    ("len", 1, 1),
+    ("now", 0, 1),
+    ("urlencode", 1, 1),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Text(String),
+    Variable(String),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+// Raised when an expression uses a variable that isn't known yet. Headers are
+// resolved in two passes, so these placeholders are left for the second one.
+#[derive(Debug)]
+struct UnknownVariable(String);
+
+impl fmt::Display for UnknownVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown variable `{}`", self.0)
+    }
+}
+
+impl Error for UnknownVariable {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Val {
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for Val {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Val::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Val::Number(n) => write!(f, "{}", n),
+            Val::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Val {
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Val::Number(n) => Some(*n),
+            Val::Text(s) => s.trim().parse::<f64>().ok(),
+        }
+    }
+}
+
+// A placeholder written as `$${...}` is escaped, it is sent as a literal `${...}`
+fn is_escaped(text: &str, rest: &str, start: usize) -> bool {
+    text[..text.len() - rest.len() + start].ends_with('$')
+}
+
+// Replaces each `${name}` in `text` with `value`, leaving escaped ones alone
+pub fn replace_placeholder(text: &str, placeholder: &str, value: &str) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for (start, _) in text.match_indices(placeholder) {
+        if text[..start].ends_with('$') {
+            continue;
+        }
+
+        result.push_str(&text[last..start]);
+        result.push_str(value);
+        last = start + placeholder.len();
+    }
+
+    result.push_str(&text[last..]);
+    result
+}
+
+// Turns escaped `$${...}` placeholders into the literal text sent to a server
+pub fn unescape_placeholders(text: &str) -> String {
+    text.replace("$${", "${")
+}
+
+// Replaces every `${...}` holding an expression with its value. Plain variable
+// names are left alone, as are escaped placeholders and expressions holding
+// unresolved placeholders or failing to evaluate.
+pub fn resolve_expressions(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let escaped = is_escaped(text, rest, start);
+        let after = &rest[start + 2..];
+        let Some(end) = closing_brace(after) else {
+            result.push_str(&rest[start..]);
+            return result;
+        };
+
+        let content = &after[..end];
+        rest = &after[end + 1..];
+        if escaped || is_variable_name(content) || content.contains("${") {
+            result.push_str(&format!("${{{}}}", content));
+            continue;
+        }
+
+        match evaluate_placeholder(content, lookup) {
+            Ok(value) => result.push_str(&value),
+            Err(e) => {
+                if e.downcast_ref::<UnknownVariable>().is_some() {
+                    debug!("unable to evaluate ${{{}}} yet: {}", content, e);
+                } else {
+                    error!("unable to evaluate ${{{}}}: {}", content, e);
+                }
+                result.push_str(&format!("${{{}}}", content));
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+pub fn is_variable_name(content: &str) -> bool {
+    !content.is_empty()
+        && content
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Placeholders inside serialized JSON strings have their quotes escaped, so the
+// expression is unescaped before parsing and the result escaped again
+fn evaluate_placeholder(
+    content: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    if !content.contains('\\') {
+        return evaluate(content, lookup);
+    }
+
+    let unescaped = content.replace("\\\"", "\"").replace("\\\\", "\\");
+    let value = evaluate(&unescaped, lookup)?;
+    let escaped = serde_json::to_string(&value)?;
+    Ok(escaped[1..escaped.len() - 1].to_string())
+}
+
+pub fn evaluate(
+    expression: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let expr = parse(expression)?;
+    Ok(eval(&expr, lookup)?.to_string())
+}
+
+// Like evaluate, but numbers are kept as JSON numbers
+pub fn evaluate_json(
+    expression: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    let expr = parse(expression)?;
+    Ok(match eval(&expr, lookup)? {
+        Val::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => serde_json::json!(n as i64),
+        Val::Number(n) => serde_json::json!(n),
+        Val::Text(s) => serde_json::Value::String(s),
+    })
+}
+
+// The content of `text` when it is exactly one `${...}` placeholder
+pub fn sole_placeholder(text: &str) -> Option<&str> {
+    let content = text.strip_prefix("${")?;
+    let end = closing_brace(content)?;
+    (end == content.len() - 1).then(|| &content[..end])
+}
+
+// The variables referenced by every unescaped `${...}` in `text`, in order of
+// appearance. A placeholder that references none, e.g. one that fails to parse,
+// is listed by its content instead.
+pub fn placeholder_variables(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        let escaped = is_escaped(text, rest, start);
+        let after = &rest[start + 2..];
+        let Some(end) = closing_brace(after) else {
+            break;
+        };
+
+        let content = &after[..end];
+        rest = &after[end + 1..];
+        if escaped {
+            continue;
+        }
+
+        let referenced = if content.contains("${") {
+            placeholder_variables(content)
+        } else if is_variable_name(content) {
+            vec![content.to_string()]
+        } else {
+            match parse(content) {
+                Ok(expr) => {
+                    let mut variables = Vec::new();
+                    collect_variables(&expr, &mut variables);
+                    variables
+                }
+                Err(_) => Vec::new(),
+            }
+        };
+
+        if referenced.is_empty() {
+            names.push(content.to_string());
+        }
+
+        for name in referenced {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    names
+}
+
+// placeholder_variables over every string and key of a JSON value
+pub fn json_placeholder_variables(value: &serde_json::Value) -> Vec<String> {
+    fn add(text: &str, names: &mut Vec<String>) {
+        for name in placeholder_variables(text) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    fn collect(value: &serde_json::Value, names: &mut Vec<String>) {
+        match value {
+            serde_json::Value::String(s) => add(s, names),
+            serde_json::Value::Array(a) => a.iter().for_each(|v| collect(v, names)),
+            serde_json::Value::Object(o) => o.iter().for_each(|(k, v)| {
+                add(k, names);
+                collect(v, names);
+            }),
+            _ => {}
+        }
+    }
+
+    let mut names = Vec::new();
+    collect(value, &mut names);
+    names
+}
+
+fn collect_variables(expr: &Expr, variables: &mut Vec<String>) {
+    match expr {
+        Expr::Variable(name) if !variables.contains(name) => variables.push(name.clone()),
+        Expr::Negate(e) => collect_variables(e, variables),
+        Expr::Binary(l, _, r) => {
+            collect_variables(l, variables);
+            collect_variables(r, variables);
+        }
+        Expr::Call(_, args) => args.iter().for_each(|e| collect_variables(e, variables)),
+        _ => {}
+    }
+}
+
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match (c, quote) {
+            ('\\', _) => escaped = true,
+            (q, Some(open)) if q == open => quote = None,
+            (_, Some(_)) => {}
+            ('\'' | '"', None) => quote = Some(c),
+            ('{', None) => depth += 1,
+            ('}', None) if depth == 0 => return Some(i),
+            ('}', None) => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn parse(expression: &str) -> Result<Expr, Box<dyn Error + Send + Sync>> {
+    let mut parser = Parser {
+        chars: expression.chars().collect(),
+        pos: 0,
+    };
+    let expr = parser.parse_additive()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(expr),
+        Some(c) => Err(Box::from(format!("unexpected `{}` in `{}`", c, expression))),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, Box<dyn Error + Send + Sync>> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(op @ ('+' | '-')) => {
+                    self.pos += 1;
+                    let right = self.parse_multiplicative()?;
+                    left = Expr::Binary(Box::new(left), op, Box::new(right));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, Box<dyn Error + Send + Sync>> {
+        let mut left = self.parse_unary()?;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(op @ ('*' | '/' | '%')) => {
+                    self.pos += 1;
+                    let right = self.parse_unary()?;
+                    left = Expr::Binary(Box::new(left), op, Box::new(right));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Box<dyn Error + Send + Sync>> {
+        self.skip_whitespace();
+        if self.peek() == Some('-') {
+            self.pos += 1;
+            return Ok(Expr::Negate(Box::new(self.parse_unary()?)));
+        }
+
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, Box<dyn Error + Send + Sync>> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.parse_additive()?;
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(Box::from("missing `)`".to_string()));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some(q @ ('"' | '\'')) => {
+                self.pos += 1;
+                let mut text = String::new();
+                loop {
+                    match self.peek() {
+                        None => return Err(Box::from("unterminated string".to_string())),
+                        Some('\\') => {
+                            self.pos += 1;
+                            if let Some(c) = self.peek() {
+                                text.push(c);
+                                self.pos += 1;
+                            }
+                        }
+                        Some(c) if c == q => {
+                            self.pos += 1;
+                            return Ok(Expr::Text(text));
+                        }
+                        Some(c) => {
+                            text.push(c);
+                            self.pos += 1;
+                        }
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_digit() || c == '.') {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                text.parse::<f64>()
+                    .map(Expr::Number)
+                    .map_err(|_| Box::from(format!("invalid number `{}`", text)))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let name = self.parse_identifier();
+                if self.peek() != Some('(') {
+                    return Ok(Expr::Variable(name));
+                }
+
+                self.pos += 1;
+                let args = self.parse_arguments()?;
+                Ok(Expr::Call(name, args))
+            }
+            Some(c) => Err(Box::from(format!("unexpected `{}`", c))),
+            None => Err(Box::from("expected an expression".to_string())),
+        }
+    }
+
+    // Hyphens are part of variable names, as in is_variable_name
+    fn parse_identifier(&mut self) -> String {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            self.pos += 1;
+        }
+
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Expr>, Box<dyn Error + Send + Sync>> {
+        let mut args = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(')') {
+            self.pos += 1;
+            return Ok(args);
+        }
+
+        loop {
+            args.push(self.parse_additive()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(')') => {
+                    self.pos += 1;
+                    return Ok(args);
+                }
+                Some(c) => {
+                    return Err(Box::from(format!(
+                        "unexpected `{}` in arguments, text arguments must be quoted",
+                        c
+                    )))
+                }
+                None => return Err(Box::from("missing `)`".to_string())),
+            }
+        }
+    }
+}
+
+fn eval(
+    expr: &Expr,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Val, Box<dyn Error + Send + Sync>> {
+    match expr {
+        Expr::Number(n) => Ok(Val::Number(*n)),
+        Expr::Text(s) => Ok(Val::Text(s.clone())),
+        Expr::Variable(name) => lookup(name)
+            .map(Val::Text)
+            .ok_or_else(|| Box::from(UnknownVariable(name.clone()))),
+        Expr::Negate(e) => {
+            let value = eval(e, lookup)?;
+            value
+                .as_number()
+                .map(|n| Val::Number(-n))
+                .ok_or_else(|| Box::from(format!("`{}` is not a number", value)))
+        }
+        Expr::Binary(l, op, r) => {
+            let left = eval(l, lookup)?;
+            let right = eval(r, lookup)?;
+            let numbers = (left.as_number(), right.as_number());
+
+            match (op, numbers) {
+                ('+', (Some(a), Some(b))) => Ok(Val::Number(a + b)),
+                ('+', _) => Ok(Val::Text(format!("{}{}", left, right))),
+                (_, (Some(_), Some(b))) if b == 0.0 && (*op == '/' || *op == '%') => {
+                    Err(Box::from("division by zero".to_string()))
+                }
+                ('-', (Some(a), Some(b))) => Ok(Val::Number(a - b)),
+                ('*', (Some(a), Some(b))) => Ok(Val::Number(a * b)),
+                ('/', (Some(a), Some(b))) => Ok(Val::Number(a / b)),
+                ('%', (Some(a), Some(b))) => Ok(Val::Number(a % b)),
+                _ => Err(Box::from(format!(
+                    "`{} {} {}` requires numbers",
+                    left, op, right
+                ))),
+            }
+        }
+        Expr::Call(name, args) => call(name, args, lookup),
+    }
+}
+
+fn call(
+    name: &str,
+    args: &[Expr],
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Val, Box<dyn Error + Send + Sync>> {
+    let Some((_, min, max)) = FUNCTIONS.iter().find(|(f, _, _)| *f == name) else {
+        return Err(Box::from(format!("unknown function `{}`", name)));
+    };
+
+    if args.len() < *min || args.len() > *max {
+        return Err(Box::from(format!(
+            "{}() takes {} argument{}",
+            name,
+            if min == max {
+                min.to_string()
+            } else {
+                format!("{} to {}", min, max)
+            },
+            if *max == 1 { "" } else { "s" }
+        )));
+    }
+
+    let values = args
+        .iter()
+        .map(|a| eval(a, lookup).map(|v| v.to_string()))
+        .collect::<Result<Vec<String>, Box<dyn Error + Send + Sync>>>()?;
+    let first = values.first().cloned().unwrap_or_default();
+
+    Ok(match name {
+        "base64" => Val::Text(STANDARD.encode(first.as_bytes())),
+        "base64decode" => Val::Text(String::from_utf8(STANDARD.decode(first.as_bytes())?)?),
+        "sha256" => {
+            let mut hasher = Sha256::new();
+            hasher.update(first.as_bytes());
+            Val::Text(hex::encode(hasher.finalize().as_slice()))
+        }
+        "uuid" => Val::Text(uuid::Uuid::new_v4().to_string()),
+        "upper" => Val::Text(first.to_uppercase()),
+        "lower" => Val::Text(first.to_lowercase()),
+        "trim" => Val::Text(first.trim().to_string()),
+        "len" => Val::Number(match serde_json::from_str::<serde_json::Value>(&first) {
+            Ok(serde_json::Value::Array(a)) => a.len(),
+            Ok(serde_json::Value::Object(o)) => o.len(),
+            _ => first.chars().count(),
+        } as f64),
+        "now" if values.is_empty() => Val::Text(Utc::now().to_rfc3339()),
+        "now" => {
+            //chrono panics when displaying a format with an unknown specifier
+            if StrftimeItems::new(&first).any(|item| item == Item::Error) {
+                return Err(Box::from(format!("invalid now() format `{}`", first)));
+            }
+            Val::Text(Utc::now().format(&first).to_string())
+        }
+        "urlencode" => Val::Text(url::form_urlencoded::byte_serialize(first.as_bytes()).collect()),
+        _ => return Err(Box::from(format!("unknown function `{}`", name))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve(text: &str) -> String {
+        let variables: HashMap<&str, &str> = HashMap::from([
+            ("count", "4"),
+            ("name", "jikken"),
+            ("items", "[1,2,3]"),
+            ("user", "alice"),
+            ("my-var", "hyphenated"),
+        ]);
+        resolve_expressions(text, &|n| variables.get(n).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn evaluates_functions() {
+        assert_eq!("dXNlcjpwYXNz", resolve("${base64('user:pass')}"));
+        assert_eq!("user:pass", resolve("${base64decode('dXNlcjpwYXNz')}"));
+        assert_eq!(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            resolve("${sha256('hello')}")
+        );
+        assert_eq!("JIKKEN", resolve("${upper(name)}"));
+        assert_eq!("3", resolve("${len(items)}"));
+        assert_eq!("6", resolve("${len(name)}"));
+        assert_eq!("a+b%3Dc", resolve("${urlencode('a b=c')}"));
+        assert_eq!(36, resolve("${uuid()}").len());
+        assert_eq!(
+            Utc::now().format("%Y").to_string(),
+            resolve("${now(\"%Y\")}")
+        );
+        assert_eq!("YWxpY2U6c2VjcmV0", resolve("${base64(user + ':secret')}"));
+    }
+
+    #[test]
+    fn evaluates_arithmetic() {
+        assert_eq!("5", resolve("${count + 1}"));
+        assert_eq!("id-14", resolve("id-${(count + 3) * 2}"));
+        assert_eq!("2.5", resolve("${10 / count}"));
+        assert_eq!("-3", resolve("${1 - count}"));
+        assert_eq!("jikken!", resolve("${name + '!'}"));
+        assert_eq!("3", resolve("${count -1}"));
+        assert_eq!("HYPHENATED!", resolve("${upper(my-var) + '!'}"));
+    }
+
+    #[test]
+    fn leaves_unresolvable_placeholders() {
+        assert_eq!("${unknown}", resolve("${unknown}"));
+        assert_eq!("${my-var}", resolve("${my-var}"));
+        assert_eq!("${count-1}", resolve("${count-1}"));
+        assert_eq!("${base64(user:pass)}", resolve("${base64(user:pass)}"));
+        assert_eq!("${base64(alice)}", resolve("${base64(alice)}"));
+        assert_eq!("${missing + 1}", resolve("${missing + 1}"));
+        assert_eq!("${count / 0}", resolve("${count / 0}"));
+        assert_eq!("${nope(1)}", resolve("${nope(1)}"));
+        assert_eq!("${upper(name}", resolve("${upper(name}"));
+        assert_eq!("${upper(token)}", resolve("${upper(token)}"));
+        assert_eq!("${base64(${token})}", resolve("${base64(${token})}"));
+    }
+
+    #[test]
+    fn invalid_now_format_is_an_error() {
+        assert_eq!("${now(\"%Q\")}", resolve("${now(\"%Q\")}"));
+        assert_eq!(
+            "invalid now() format `%Q`",
+            evaluate_json("now('%Q')", &|_| None)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn unquoted_text_arguments_are_an_error() {
+        assert_eq!(
+            "unexpected `:` in arguments, text arguments must be quoted",
+            evaluate_json("base64(user:pass)", &|_| Some("alice".to_string()))
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn sole_placeholders() {
+        assert_eq!(Some("count"), sole_placeholder("${count}"));
+        assert_eq!(
+            Some("len(items) + 1"),
+            sole_placeholder("${len(items) + 1}")
+        );
+        assert_eq!(None, sole_placeholder("id-${count}"));
+        assert_eq!(None, sole_placeholder("${a}-${b}"));
+        assert_eq!(
+            serde_json::json!(5),
+            evaluate_json("count + 1", &|_| Some("4".to_string())).unwrap()
+        );
+    }
+
+    #[test]
+    fn placeholder_variables_lists_references() {
+        assert_eq!(
+            vec!["token", "count", "user", "password", "1 +", "uuid()"],
+            placeholder_variables(
+                "${token} ${count + 1} ${base64(${user}:${password})} ${token} ${1 +} ${uuid()}"
+            )
+        );
+        assert!(placeholder_variables("no placeholders $ { here").is_empty());
+        assert_eq!(
+            vec!["name"],
+            placeholder_variables("$${template} $${base64(${user})} ${name}")
+        );
+    }
+
+    #[test]
+    fn escaped_placeholders_are_kept() {
+        assert_eq!("$${1 + 1} 2", resolve("$${1 + 1} ${1 + 1}"));
+        assert_eq!(
+            "$${name} bob",
+            replace_placeholder("$${name} ${name}", "${name}", "bob")
+        );
+        assert_eq!("${name} $5", unescape_placeholders("$${name} $5"));
+    }
+
+    #[test]
+    fn escaped_json_strings() {
+        assert_eq!(
+            r#"{"auth":"QmFzaWMgYQ=="}"#,
+            resolve(r#"{"auth":"${base64(\"Basic a\")}"}"#)
+        );
+        assert_eq!(r#"{"v":"\"x\""}"#, resolve(r#"{"v":"${'\"x\"'}"}"#));
+    }
+}
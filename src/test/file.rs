@@ -1,5 +1,5 @@
 use crate::{
-    json::filter::filter_json,
+    json::{diff, filter::filter_json},
     test,
     test::{definition, file::Validated::Good, http, variable, variable::Modifier},
     validated::ValidatedExt,
@@ -15,6 +15,7 @@ use rand::{distributions::uniform::SampleUniform, rngs::ThreadRng, Rng};
 use regex::Regex;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
 use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
 use std::{
     cmp::{max, min},
     collections::BTreeMap,
@@ -2354,6 +2355,13 @@ pub struct UnvalidatedRequest {
     //in the (Validated)RequestDescriptor struct
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<UnvalidatedVariableNameOrValue>,
+    //Non-JSON bodies. Only one of body, form, multipart or raw may be given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form: Option<Vec<UnvalidatedFormField>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multipart: Option<Vec<UnvalidatedMultipartPart>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<UnvalidatedRawBody>,
 }
 
 impl Default for UnvalidatedRequest {
@@ -2364,6 +2372,9 @@ impl Default for UnvalidatedRequest {
             params: None,
             headers: None,
             body: None,
+            form: None,
+            multipart: None,
+            raw: None,
         }
     }
 }
@@ -2375,9 +2386,45 @@ impl Hash for UnvalidatedRequest {
         self.params.hash(state);
         self.headers.hash(state);
         serde_json::to_string(&self.body).unwrap().hash(state);
+        self.form.hash(state);
+        self.multipart.hash(state);
+        self.raw.hash(state);
     }
 }
 
+#[derive(Hash, Default, Serialize, Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct UnvalidatedFormField {
+    pub name: String,
+    pub value: String,
+}
+
+//Parts are sourced from either a value or the contents of a file
+#[derive(Hash, Default, Serialize, Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct UnvalidatedMultipartPart {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+#[derive(Hash, Default, Serialize, Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct UnvalidatedRawBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct UnvalidatedCompareRequest {
@@ -2624,10 +2671,20 @@ impl<'a> BodyOrSchemaChecker<'a> {
         );
         match result {
             Ok(_) => Ok(vec![Good(())]),
-            Err(msg) => Ok(vec![Validated::fail(formatter(
-                format!("body {modified_expected}").as_str(),
-                format!("body {modified_actual} ; {msg}").as_str(),
-            ))]),
+            Err(msg) => {
+                let differences = diff::diff(&modified_expected, &modified_actual, self.strict);
+                //the comparison is authoritative, fall back to its own message
+                //should the two ever disagree
+                let explanation = if differences.is_empty() {
+                    msg
+                } else {
+                    diff::describe(&differences)
+                };
+                Ok(vec![Validated::fail(formatter(
+                    "body",
+                    format!("body, {explanation}").as_str(),
+                ))])
+            }
         }
     }
 }
@@ -2658,6 +2715,142 @@ impl<'a> Checker for BodyOrSchemaChecker<'a> {
     }
 }
 
+//Header names are matched case-insensitively; a header with no
+//constraints only needs to be present
+#[derive(Default, Serialize, Hash, Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct UnvalidatedResponseHeader {
+    pub header: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub any_of: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_of: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub none_of: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub absent: Option<bool>,
+}
+
+impl UnvalidatedResponseHeader {
+    pub fn has_constraints(&self) -> bool {
+        self.value.is_some()
+            || self.any_of.is_some()
+            || self.one_of.is_some()
+            || self.none_of.is_some()
+            || self.pattern.is_some()
+    }
+}
+
+impl TryFrom<UnvalidatedResponseHeader> for Option<StringSpecification> {
+    type Error = String;
+
+    fn try_from(unvalidated_header: UnvalidatedResponseHeader) -> Result<Self, Self::Error> {
+        if !unvalidated_header.has_constraints() {
+            return Ok(None);
+        }
+
+        TryInto::<StringSpecification>::try_into(UnvalidatedStringSpecification {
+            value: unvalidated_header.value,
+            any_of: unvalidated_header.any_of,
+            one_of: unvalidated_header.one_of,
+            none_of: unvalidated_header.none_of,
+            pattern: unvalidated_header.pattern,
+            ..Default::default()
+        })
+        .map(Some)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonType {
+    String,
+    Number,
+    Boolean,
+    Object,
+    Array,
+    Null,
+}
+
+impl JsonType {
+    pub fn of(value: &Value) -> JsonType {
+        match value {
+            Value::String(_) => JsonType::String,
+            Value::Number(_) => JsonType::Number,
+            Value::Bool(_) => JsonType::Boolean,
+            Value::Object(_) => JsonType::Object,
+            Value::Array(_) => JsonType::Array,
+            Value::Null => JsonType::Null,
+        }
+    }
+}
+
+impl fmt::Display for JsonType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            JsonType::String => "string",
+            JsonType::Number => "number",
+            JsonType::Boolean => "boolean",
+            JsonType::Object => "object",
+            JsonType::Array => "array",
+            JsonType::Null => "null",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//A present value, including an explicit null, deserializes to Some
+fn deserialize_present_value<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Value::deserialize(deserializer).map(Some)
+}
+
+//A single check against one path (dotted or JSONPath) of a JSON response body.
+//Exactly one operator is expected per assertion
+#[derive(Default, Serialize, Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct UnvalidatedAssertion {
+    pub path: String,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present_value",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub equals: Option<Value>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present_value",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub not_equals: Option<Value>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present_value",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub contains: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exists: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub absent: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<ValueOrNumericSpecification<u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub greater_than: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub less_than: Option<f64>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_is: Option<JsonType>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct UnvalidatedResponse {
@@ -2666,7 +2859,7 @@ pub struct UnvalidatedResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub time: Option<ValueOrNumericSpecification<u32>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub headers: Option<Vec<http::Header>>,
+    pub headers: Option<Vec<UnvalidatedResponseHeader>>,
     //Responses can only contain a body OR a body_schema
     //We used to signify this using (serde-flattened)enums, but its
     //easier to manage validation errors if we flatten the
@@ -2682,6 +2875,10 @@ pub struct UnvalidatedResponse {
     pub extract: Option<Vec<definition::ResponseExtraction>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub strict: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<UnvalidatedContentSpecification>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assertions: Option<Vec<UnvalidatedAssertion>>,
 }
 
 impl Hash for UnvalidatedResponse {
@@ -2695,6 +2892,8 @@ impl Hash for UnvalidatedResponse {
         self.ignore.hash(state);
         self.extract.hash(state);
         self.strict.hash(state);
+        self.content.hash(state);
+        serde_json::to_string(&self.assertions).unwrap().hash(state);
     }
 }
 
@@ -2709,6 +2908,154 @@ impl Default for UnvalidatedResponse {
             extract: None,
             strict: None,
             body_schema: None,
+            content: None,
+            assertions: None,
+        }
+    }
+}
+
+//Assertions on the raw response body, usable whether or not it is JSON
+#[derive(Default, Serialize, Hash, Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct UnvalidatedContentSpecification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_length: Option<ValueOrNumericSpecification<u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+#[derive(Default, Serialize, Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentSpecification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_length: Option<ValueOrNumericSpecification<u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl TryFrom<UnvalidatedContentSpecification> for ContentSpecification {
+    type Error = String;
+
+    fn try_from(unvalidated: UnvalidatedContentSpecification) -> Result<Self, Self::Error> {
+        if let Some(p) = &unvalidated.pattern {
+            if let Err(e) = Regex::new(p) {
+                return Err(format!("invalid regex supplied for pattern: {}", e));
+            }
+        }
+
+        if let Some(digest) = &unvalidated.sha256 {
+            if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err("sha256 must be 64 hexadecimal characters".to_string());
+            }
+        }
+
+        Ok(Self {
+            text: unvalidated.text,
+            contains: unvalidated.contains,
+            pattern: unvalidated.pattern,
+            byte_length: unvalidated.byte_length,
+            sha256: unvalidated.sha256.map(|d| d.to_lowercase()),
+        })
+    }
+}
+
+impl ContentSpecification {
+    //Matches only a body identical to the one given; used when comparing non-JSON responses
+    pub fn same_as(body: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(body);
+        Self {
+            sha256: Some(hex::encode(hasher.finalize().as_slice())),
+            ..Default::default()
+        }
+    }
+}
+
+impl Checker for ContentSpecification {
+    type Item = Vec<u8>;
+    fn check(
+        &self,
+        val: &Self::Item,
+        formatter: &impl Fn(&str, &str) -> String,
+    ) -> Vec<Validated<(), String>> {
+        const MAX_DISPLAYED_CHARS: usize = 200;
+        let text = String::from_utf8_lossy(val);
+        let displayed_text = if text.chars().count() > MAX_DISPLAYED_CHARS {
+            format!(
+                "{:?}...",
+                text.chars().take(MAX_DISPLAYED_CHARS).collect::<String>()
+            )
+        } else {
+            format!("{:?}", text)
+        };
+
+        let mut ret = vec![];
+        if let Some(expected) = &self.text {
+            if *expected != text {
+                ret.push(Validated::fail(formatter(
+                    format!("body text {:?}", expected).as_str(),
+                    displayed_text.as_str(),
+                )));
+            }
+        }
+
+        if let Some(expected) = &self.contains {
+            if !text.contains(expected.as_str()) {
+                ret.push(Validated::fail(formatter(
+                    format!("body text containing {:?}", expected).as_str(),
+                    displayed_text.as_str(),
+                )));
+            }
+        }
+
+        if let Some(p) = &self.pattern {
+            let matched = Regex::new(p).map(|re| re.is_match(&text)).unwrap_or(false);
+            if !matched {
+                ret.push(Validated::fail(formatter(
+                    format!("body text matching pattern {}", p).as_str(),
+                    displayed_text.as_str(),
+                )));
+            }
+        }
+
+        if let Some(length) = &self.byte_length {
+            ret.append(
+                length
+                    .check(&(val.len() as u64), &|e, a| {
+                        formatter(format!("body byte length {}", e).as_str(), a)
+                    })
+                    .as_mut(),
+            );
+        }
+
+        if let Some(expected) = &self.sha256 {
+            let mut hasher = Sha256::new();
+            hasher.update(val);
+            let actual = hex::encode(hasher.finalize().as_slice());
+            if *expected != actual {
+                ret.push(Validated::fail(formatter(
+                    format!("body sha256 {}", expected).as_str(),
+                    actual.as_str(),
+                )));
+            }
+        }
+
+        if ret.is_empty() {
+            vec![Good(())]
+        } else {
+            ret
         }
     }
 }
@@ -2922,6 +3269,56 @@ pub enum UnvalidatedDatumSchemaVariable {
     },
 }
 
+//Tests can require a single test id or a list of them
+#[derive(Hash, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum UnvalidatedRequires {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl UnvalidatedRequires {
+    pub fn ids(&self) -> Vec<String> {
+        match self {
+            UnvalidatedRequires::Single(id) => vec![id.clone()],
+            UnvalidatedRequires::Multiple(ids) => ids.clone(),
+        }
+    }
+}
+
+#[derive(Hash, Default, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backoff {
+    #[default]
+    Fixed,
+    Exponential,
+}
+
+#[derive(Hash, Default, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct UnvalidatedRetry {
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<Backoff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_status: Option<Vec<u16>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_connection_error: Option<bool>,
+}
+
+#[derive(Hash, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct UnvalidatedPoll {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    pub max_duration: u64,
+    pub until: UnvalidatedResponse,
+}
+
 #[derive(Hash, Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct UnvalidatedStage {
@@ -2936,6 +3333,12 @@ pub struct UnvalidatedStage {
     pub variables: Option<Vec<UnvalidatedVariable>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<UnvalidatedRetry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll: Option<UnvalidatedPoll>,
 }
 
 #[derive(Hash, Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -3369,6 +3772,75 @@ mod tests {
         assert!(serde_json::from_value::<UnvalidatedSpecification<bool>>(json).is_err());
     }
 
+    #[test]
+    fn unvalidated_response_header_constraints() {
+        let unconstrained: UnvalidatedResponseHeader =
+            serde_json::from_value(json!({ "header": "X-Request-Id" })).unwrap();
+        let attempt: Result<Option<StringSpecification>, String> = unconstrained.try_into();
+        assert_eq!(Ok(None), attempt);
+
+        let constrained: UnvalidatedResponseHeader = serde_json::from_value(json!({
+            "header": "Content-Type",
+            "pattern": "^application/(json|xml)$"
+        }))
+        .unwrap();
+        let attempt: Result<Option<StringSpecification>, String> = constrained.try_into();
+        assert_eq!(
+            Some("^application/(json|xml)$".to_string()),
+            attempt.unwrap().unwrap().pattern
+        );
+
+        assert!(serde_json::from_value::<UnvalidatedResponseHeader>(json!({
+            "header": "Content-Type",
+            "values": "text/html"
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn content_specification_checks_raw_body() {
+        let formatter = |e: &str, a: &str| format!("Expected {} but received {}", e, a);
+        let body = b"hello world".to_vec();
+
+        let spec: ContentSpecification = UnvalidatedContentSpecification {
+            sha256: Some(
+                "B94D27B9934D3E08A52E52D7DA7DABFAC484EFE37A5380EE9088F7ACE2EFCDE9".to_string(),
+            ),
+            pattern: Some("^hello".to_string()),
+            ..Default::default()
+        }
+        .try_into()
+        .unwrap();
+        assert!(spec.check(&body, &formatter).iter().all(|v| v.is_good()));
+        assert_eq!(ContentSpecification::same_as(&body).sha256, spec.sha256);
+
+        let failures = ContentSpecification {
+            text: Some("hello".to_string()),
+            contains: Some("planet".to_string()),
+            ..Default::default()
+        }
+        .check(&body, &formatter);
+        assert_eq!(
+            vec![
+                Validated::fail(
+                    "Expected body text \"hello\" but received \"hello world\"".to_string()
+                ),
+                Validated::fail(
+                    "Expected body text containing \"planet\" but received \"hello world\""
+                        .to_string()
+                ),
+            ],
+            failures
+        );
+
+        let invalid: Result<ContentSpecification, String> = UnvalidatedContentSpecification {
+            sha256: Some("abc".to_string()),
+            ..Default::default()
+        }
+        .try_into();
+        assert!(invalid.is_err());
+    }
+
     #[test]
     fn unvalidated_specification_disallow_any_of_and_one_of() {
         let unvalidated = UnvalidatedSpecification::<bool> {
@@ -0,0 +1,772 @@
+use crate::errors::GenericError;
+use std::error::Error;
+
+// Formats test files by editing their YAML text instead of re-serializing them, so
+// comments, key order and scalar styles survive. Indentation becomes two spaces with
+// sequences at the column of their key, `key:   value` spacing is tightened and
+// trailing whitespace and repeated blank lines are removed. Block scalars and
+// multi-line flow collections or quoted strings are moved along with their key.
+
+const INDENT: usize = 2;
+
+struct Level {
+    raw: usize,
+    new: usize,
+    seq: bool,
+}
+
+#[derive(Default)]
+struct Scan {
+    depth: usize,
+    quote: Option<char>,
+}
+
+impl Scan {
+    //consumes one line of a flow collection or quoted scalar, returning whether it
+    //is still open afterwards
+    fn line(&mut self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut prev = ' ';
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match self.quote {
+                Some('"') if c == '\\' => i += 1,
+                Some('\'') if c == '\'' && chars.get(i + 1) == Some(&'\'') => i += 1,
+                Some(q) if c == q => self.quote = None,
+                Some(_) => {}
+                None => match c {
+                    '#' if prev.is_whitespace() => break,
+                    '"' | '\'' if i == 0 || "[{,:".contains(prev) || prev.is_whitespace() => {
+                        self.quote = Some(c)
+                    }
+                    '[' | '{' => self.depth += 1,
+                    ']' | '}' => self.depth = self.depth.saturating_sub(1),
+                    _ => {}
+                },
+            }
+            prev = c;
+            i += 1;
+            if self.depth == 0 && self.quote.is_none() {
+                break;
+            }
+        }
+        self.depth > 0 || self.quote.is_some()
+    }
+}
+
+enum Continuation {
+    Block {
+        parent_raw: usize,
+        parent_new: usize,
+        explicit: Option<usize>,
+        content: Option<usize>,
+        blanks: usize,
+    },
+    Flow {
+        shift: isize,
+        scan: Scan,
+    },
+}
+
+enum Pending {
+    Blank,
+    Comment(usize, String),
+}
+
+#[derive(Default)]
+struct Formatter {
+    levels: Vec<Level>,
+    continuation: Option<Continuation>,
+    pending: Vec<Pending>,
+    output: Vec<String>,
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_dash(content: &str) -> bool {
+    content == "-" || content.starts_with("- ")
+}
+
+//splits `key: value` into its key and the (possibly empty) rest
+fn split_key(content: &str) -> Option<(&str, &str)> {
+    let end = match content.chars().next()? {
+        q @ ('"' | '\'') => content[1..].find(q)? + 2,
+        '[' | '{' | '#' | '-' | '?' | '|' | '>' | '&' | '*' | '!' => return None,
+        _ => {
+            let comment = content.find(" #").unwrap_or(content.len());
+            content[..comment]
+                .match_indices(':')
+                .map(|(i, _)| i)
+                .find(|i| content[i + 1..].is_empty() || content[i + 1..].starts_with(' '))?
+        }
+    };
+    let rest = content[end..].trim_start();
+    let value = rest.strip_prefix(':')?;
+    if !value.is_empty() && !value.starts_with(' ') {
+        return None;
+    }
+    Some((content[..end].trim_end(), value.trim_start()))
+}
+
+impl Formatter {
+    fn push(&mut self, line: String) {
+        if !(line.is_empty() && self.output.last().is_none_or(|l| l.is_empty())) {
+            self.output.push(line);
+        }
+    }
+
+    //blank lines and comments are placed once the indentation of the next line is known
+    fn flush(&mut self, indent: Option<usize>) {
+        for pending in std::mem::take(&mut self.pending) {
+            match pending {
+                Pending::Blank => self.push(String::new()),
+                Pending::Comment(raw, text) => {
+                    let indent = indent.unwrap_or_else(|| {
+                        self.levels
+                            .iter()
+                            .rev()
+                            .find(|l| l.raw <= raw)
+                            .map_or(0, |l| l.new)
+                    });
+                    self.push(format!("{}{}", " ".repeat(indent), text));
+                }
+            }
+        }
+    }
+
+    //finds the output column of a line starting at `raw`, opening a level for it
+    //when it starts a new mapping or sequence
+    fn place(&mut self, raw: usize, dash: bool) -> usize {
+        loop {
+            match self.levels.last() {
+                None => {
+                    self.levels.push(Level {
+                        raw,
+                        new: 0,
+                        seq: dash,
+                    });
+                    return 0;
+                }
+                Some(top) if top.raw > raw => {
+                    self.levels.pop();
+                }
+                Some(top) if top.raw == raw => {
+                    if top.seq == dash {
+                        return top.new;
+                    }
+                    if top.seq {
+                        self.levels.pop();
+                        continue;
+                    }
+                    //a sequence written at the column of its key
+                    let new = top.new;
+                    self.levels.push(Level {
+                        raw,
+                        new,
+                        seq: true,
+                    });
+                    return new;
+                }
+                Some(top) => {
+                    let new = if dash && !top.seq {
+                        top.new
+                    } else {
+                        top.new + INDENT
+                    };
+                    self.levels.push(Level {
+                        raw,
+                        new,
+                        seq: dash,
+                    });
+                    return new;
+                }
+            }
+        }
+    }
+
+    fn node(&mut self, raw: usize, new: usize, content: &str) -> String {
+        if is_dash(content) {
+            let rest = content[1..].trim_start();
+            if rest.is_empty() {
+                return "-".to_string();
+            }
+            if rest.starts_with('#') {
+                return format!("- {}", rest);
+            }
+            let rest_raw = raw + content.len() - rest.len();
+            let rest_new = new + INDENT;
+            let nested = if is_dash(rest) || split_key(rest).is_some() {
+                self.levels.push(Level {
+                    raw: rest_raw,
+                    new: rest_new,
+                    seq: is_dash(rest),
+                });
+                self.node(rest_raw, rest_new, rest)
+            } else {
+                self.value(raw, new, rest)
+            };
+            return format!("- {}", nested);
+        }
+
+        match split_key(content) {
+            Some((key, "")) => format!("{}:", key),
+            Some((key, value)) => format!("{}: {}", key, self.value(raw, new, value)),
+            None => self.value(raw, new, content),
+        }
+    }
+
+    //scalars are kept as written; block scalars, flow collections and quoted
+    //strings may continue on the following lines
+    fn value(&mut self, parent_raw: usize, parent_new: usize, text: &str) -> String {
+        let body = text
+            .split(' ')
+            .skip_while(|t| t.starts_with('&') || t.starts_with('!'))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if body.starts_with('|') || body.starts_with('>') {
+            let header = body.split(" #").next().unwrap_or_default();
+            self.continuation = Some(Continuation::Block {
+                parent_raw,
+                parent_new,
+                explicit: header
+                    .chars()
+                    .find_map(|c| c.to_digit(10))
+                    .map(|d| d as usize),
+                content: None,
+                blanks: 0,
+            });
+        } else if body.starts_with(['[', '{', '"', '\'']) {
+            let mut scan = Scan::default();
+            if scan.line(&body) {
+                self.continuation = Some(Continuation::Flow {
+                    shift: parent_new as isize - parent_raw as isize,
+                    scan,
+                });
+            }
+        }
+        text.to_string()
+    }
+
+    //returns whether the line was consumed by an open block scalar or flow node
+    fn continue_node(&mut self, line: &str) -> bool {
+        match &mut self.continuation {
+            None => false,
+            Some(Continuation::Block {
+                parent_raw,
+                parent_new,
+                explicit,
+                content,
+                blanks,
+            }) => {
+                if line.trim().is_empty() {
+                    *blanks += 1;
+                    return true;
+                }
+                let indent = indentation(line);
+                let start = match (*explicit, *content) {
+                    (Some(e), _) => *parent_raw + e,
+                    (None, Some(c)) => c,
+                    (None, None) if indent > *parent_raw => indent,
+                    _ => usize::MAX,
+                };
+                if indent < start {
+                    //blank lines after the block aren't part of it
+                    if *blanks > 0 {
+                        self.pending.push(Pending::Blank);
+                    }
+                    self.continuation = None;
+                    return false;
+                }
+                *content = Some(start);
+                let target = *parent_new + explicit.unwrap_or(INDENT);
+                let lines = std::iter::repeat_n(String::new(), std::mem::take(blanks))
+                    .chain(std::iter::once(format!(
+                        "{}{}",
+                        " ".repeat(target),
+                        &line[start..]
+                    )))
+                    .collect::<Vec<_>>();
+                self.output.extend(lines);
+                true
+            }
+            Some(Continuation::Flow { shift, scan }) => {
+                let line = line.trim_end();
+                if line.is_empty() {
+                    self.output.push(String::new());
+                    return true;
+                }
+                let indent = (indentation(line) as isize + *shift).max(0) as usize;
+                let open = scan.line(line.trim_start());
+                self.output
+                    .push(format!("{}{}", " ".repeat(indent), line.trim_start()));
+                if !open {
+                    self.continuation = None;
+                }
+                true
+            }
+        }
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.continue_node(line) {
+            return Ok(());
+        }
+
+        let line = line.trim_end();
+        let content = line.trim_start();
+        if content.is_empty() {
+            if !self.output.is_empty() || !self.pending.is_empty() {
+                self.pending.push(Pending::Blank);
+            }
+            return Ok(());
+        }
+        if content.starts_with('#') {
+            self.pending
+                .push(Pending::Comment(indentation(line), content.to_string()));
+            return Ok(());
+        }
+        if line.starts_with("---") || line.starts_with("...") || line.starts_with('%') {
+            self.flush(Some(0));
+            self.levels.clear();
+            self.push(line.to_string());
+            return Ok(());
+        }
+        if line[..line.len() - content.len()].contains('\t') {
+            return Err(Box::from(GenericError {
+                reason: "tabs can't be used for indentation".to_string(),
+            }));
+        }
+
+        let raw = indentation(line);
+        let new = self.place(raw, is_dash(content));
+        self.flush(Some(new));
+        let text = self.node(raw, new, content);
+        self.push(format!("{}{}", " ".repeat(new), text));
+        Ok(())
+    }
+}
+
+pub fn format_source(source: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut formatter = Formatter::default();
+    for line in source.lines() {
+        formatter.line(line)?;
+    }
+    formatter.flush(None);
+    while formatter.output.last().is_some_and(|l| l.is_empty()) {
+        formatter.output.pop();
+    }
+
+    let mut formatted = formatter.output.join("\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    ensure_same_content(&serde_yaml::from_str(source)?, &formatted)?;
+    Ok(formatted)
+}
+
+//guards against rewriting a file into something that reads differently
+fn ensure_same_content(
+    expected: &serde_yaml::Value,
+    after: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let after: serde_yaml::Value = serde_yaml::from_str(after).unwrap_or(serde_yaml::Value::Null);
+    if *expected != after {
+        return Err(Box::from(GenericError {
+            reason: "formatting would change the meaning of the file, it was left unchanged"
+                .to_string(),
+        }));
+    }
+    Ok(())
+}
+
+fn top_level_key(line: &str) -> Option<&str> {
+    if line.starts_with([' ', '#', '-']) {
+        return None;
+    }
+    split_key(line).map(|(key, _)| key.trim_matches(['"', '\'']))
+}
+
+//Sets a top level key of a test file in place, keeping comments and layout.
+//A new key is added after `id` (or `name`), otherwise at the top of the document
+pub fn set_top_level_value(
+    source: &str,
+    key: &str,
+    value: &str,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut lines: Vec<String> = source.lines().map(String::from).collect();
+    let entry = format!("{}: {}", key, value);
+
+    if let Some(i) = lines.iter().position(|l| top_level_key(l) == Some(key)) {
+        lines[i] = entry;
+    } else {
+        let anchor = ["id", "name"]
+            .iter()
+            .find_map(|k| lines.iter().position(|l| top_level_key(l) == Some(k)));
+        let position = match anchor {
+            Some(start) => {
+                //after the anchor's value, which may span several lines
+                let end = lines[start + 1..]
+                    .iter()
+                    .position(|l| top_level_key(l).is_some())
+                    .map_or(lines.len(), |p| start + 1 + p);
+                lines[start..end]
+                    .iter()
+                    .rposition(|l| !l.trim().is_empty() && !l.starts_with('#'))
+                    .map_or(end, |p| start + p + 1)
+            }
+            None => lines
+                .iter()
+                .position(|l| !l.trim().is_empty() && !l.starts_with('#') && !l.starts_with("---"))
+                .unwrap_or(lines.len()),
+        };
+        lines.insert(position, entry);
+    }
+
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+
+    let mut expected: serde_yaml::Value = serde_yaml::from_str(source)?;
+    if let Some(mapping) = expected.as_mapping_mut() {
+        mapping.insert(key.into(), value.into());
+    }
+    ensure_same_content(&expected, &updated)?;
+    Ok(updated)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Same,
+    Removed,
+    Added,
+}
+
+//The furthest reaching forward and backward paths of Myers' algorithm meet on
+//a "middle snake", a run of equal lines which splits the edit script in two.
+//Returned as its start and end in `old` and `new`, relative to the ranges given
+fn middle_snake(old: &[&str], new: &[&str]) -> (usize, usize, usize, usize) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    let mut forward = vec![0isize; (2 * max + 3) as usize];
+    let mut backward = vec![0isize; (2 * max + 3) as usize];
+    let index = |k: isize| (k + offset) as usize;
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            let reverse = delta - k;
+            if odd && reverse.abs() < d && x + backward[index(reverse)] >= n {
+                return (x0 as usize, y0 as usize, x as usize, y as usize);
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            let reverse = delta - k;
+            if !odd && reverse.abs() <= d && x + forward[index(reverse)] >= n {
+                return (
+                    (n - x) as usize,
+                    (m - y) as usize,
+                    (n - x0) as usize,
+                    (m - y0) as usize,
+                );
+            }
+        }
+    }
+
+    unreachable!("the forward and backward paths always meet")
+}
+
+//Pairs of equal lines, in order, found by splitting on middle snakes so only
+//linear memory is used however long the texts are
+fn matching_lines(
+    old: &[&str],
+    new: &[&str],
+    old_start: usize,
+    new_start: usize,
+    matches: &mut Vec<(usize, usize)>,
+) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    matches.extend((0..prefix).map(|k| (old_start + k, new_start + k)));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let (old_start, new_start) = (old_start + prefix, new_start + prefix);
+
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+    if !old.is_empty() && !new.is_empty() {
+        let (x0, y0, x1, y1) = middle_snake(old, new);
+        matching_lines(&old[..x0], &new[..y0], old_start, new_start, matches);
+        matches.extend((0..x1 - x0).map(|k| (old_start + x0 + k, new_start + y0 + k)));
+        matching_lines(
+            &old[x1..],
+            &new[y1..],
+            old_start + x1,
+            new_start + y1,
+            matches,
+        );
+    }
+
+    matches.extend((0..suffix).map(|k| (old_start + old.len() + k, new_start + new.len() + k)));
+}
+
+//Aligns two lists of lines by a shortest edit script. Each change comes with the
+//old and new line numbers before it, removals preceding the additions they replace
+pub fn line_changes(old: &[&str], new: &[&str]) -> Vec<(Change, usize, usize)> {
+    let mut matches = Vec::new();
+    matching_lines(old, new, 0, 0, &mut matches);
+
+    let mut changes: Vec<(Change, usize, usize)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in matches.into_iter().chain([(old.len(), new.len())]) {
+        changes.extend((i..next_i).map(|k| (Change::Removed, k, j)));
+        changes.extend((j..next_j).map(|k| (Change::Added, next_i, k)));
+        if next_i < old.len() {
+            changes.push((Change::Same, next_i, next_j));
+        }
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    changes
+}
+
+//A unified diff of two texts by line, with three lines of context
+pub fn diff(filename: &str, before: &str, after: &str) -> String {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+    let changes = line_changes(&old, &new);
+
+    let context = 3;
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (k, _) in changes
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| !matches!(c.0, Change::Same))
+    {
+        let start = k.saturating_sub(context);
+        let end = (k + context + 1).min(changes.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", filename, filename);
+    for (start, end) in hunks {
+        let hunk = &changes[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|c| !matches!(c.0, Change::Added))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|c| !matches!(c.0, Change::Removed))
+            .count();
+        let line_number = |position: usize, count: usize| {
+            if count == 0 {
+                position
+            } else {
+                position + 1
+            }
+        };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            line_number(hunk[0].1, old_count),
+            old_count,
+            line_number(hunk[0].2, new_count),
+            new_count
+        ));
+        for (change, i, j) in hunk {
+            match change {
+                Change::Same => out.push_str(&format!(" {}\n", old[*i])),
+                Change::Removed => out.push_str(&format!("-{}\n", old[*i])),
+                Change::Added => out.push_str(&format!("+{}\n", new[*j])),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_keeps_comments_and_order() {
+        let source = r#"# login flow
+name:   Login   # shown in reports
+id: login
+
+
+stages:
+    # first we sign in
+    -   request:
+            method: Post
+            url: ${baseUrl}/login
+            body: {
+                "user": "admin",
+                "password": "secret" }
+        response:
+            status: 200
+    - request:
+          url: ${baseUrl}/me
+      response:
+          assertions:
+              - path: name
+                equals: admin
+variables:
+  - name: note
+    value: |
+        line one
+          indented
+
+        line three
+tags: smoke
+"#;
+        let expected = r#"# login flow
+name: Login   # shown in reports
+id: login
+
+stages:
+# first we sign in
+- request:
+    method: Post
+    url: ${baseUrl}/login
+    body: {
+        "user": "admin",
+        "password": "secret" }
+  response:
+    status: 200
+- request:
+    url: ${baseUrl}/me
+  response:
+    assertions:
+    - path: name
+      equals: admin
+variables:
+- name: note
+  value: |
+    line one
+      indented
+
+    line three
+tags: smoke
+"#;
+        let formatted = format_source(source).unwrap();
+        assert_eq!(expected, formatted);
+        assert_eq!(expected, format_source(&formatted).unwrap());
+    }
+
+    #[test]
+    fn set_top_level_value_keeps_layout() {
+        let source = "# orders\nname: Orders\nid: orders # stable\n\n# checks\nrequest:\n  url: http://localhost/orders\n";
+        assert_eq!(
+            "# orders\nname: Orders\nid: orders # stable\nplatformId: 01ABC\n\n# checks\nrequest:\n  url: http://localhost/orders\n",
+            set_top_level_value(source, "platformId", "01ABC").unwrap()
+        );
+        assert_eq!(
+            "# orders\nplatformId: 01ABC\nrequest: {}\n",
+            set_top_level_value("# orders\nrequest: {}\n", "platformId", "01ABC").unwrap()
+        );
+        assert_eq!(
+            "id: a\nplatformId: 02DEF\n",
+            set_top_level_value("id: a\nplatformId: 01ABC\n", "platformId", "02DEF").unwrap()
+        );
+    }
+
+    #[test]
+    fn diff_shows_changed_lines_with_context() {
+        let before = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let after = "a\nb\nc\nd\nE\nf\ng\nh\ni\n";
+        assert_eq!(
+            "--- t.jkt\n+++ t.jkt\n@@ -2,7 +2,7 @@\n b\n c\n d\n-e\n+E\n f\n g\n h\n",
+            diff("t.jkt", before, after)
+        );
+    }
+
+    #[test]
+    fn line_changes_are_a_shortest_edit_script() {
+        let lcs_length = |old: &[&str], new: &[&str]| {
+            let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+            for i in (0..old.len()).rev() {
+                for j in (0..new.len()).rev() {
+                    lcs[i][j] = if old[i] == new[j] {
+                        lcs[i + 1][j + 1] + 1
+                    } else {
+                        lcs[i + 1][j].max(lcs[i][j + 1])
+                    };
+                }
+            }
+            lcs[0][0]
+        };
+
+        let mut seed = 7u32;
+        let mut lines = |count: u32| -> Vec<&str> {
+            (0..count)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    ["a", "b", "c", "d"][(seed >> 16) as usize % 4]
+                })
+                .collect()
+        };
+
+        for round in 0..200 {
+            let old = lines(round % 13);
+            let new = lines(round % 11);
+            let changes = line_changes(&old, &new);
+
+            let rebuilt_old: Vec<&str> = changes
+                .iter()
+                .filter(|c| c.0 != Change::Added)
+                .map(|c| old[c.1])
+                .collect();
+            let rebuilt_new: Vec<&str> = changes
+                .iter()
+                .filter(|c| c.0 != Change::Removed)
+                .map(|c| new[c.2])
+                .collect();
+            assert_eq!(old, rebuilt_old);
+            assert_eq!(new, rebuilt_new);
+            assert!(changes
+                .iter()
+                .filter(|c| c.0 == Change::Same)
+                .all(|c| old[c.1] == new[c.2]));
+            assert_eq!(
+                lcs_length(&old, &new),
+                changes.iter().filter(|c| c.0 == Change::Same).count()
+            );
+        }
+    }
+}
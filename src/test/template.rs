@@ -32,8 +32,10 @@ pub fn template_full() -> Result<test::File, Box<dyn Error + Send + Sync>> {
         project: Some("".to_string()),
         env: Some("".to_string()),
         tags: Some("".to_string()),
-        requires: Some("".to_string()),
+        requires: Some(file::UnvalidatedRequires::Single("".to_string())),
         iterate: Some(1),
+        timeout: Some(30000),
+        retry: Some(new_full_retry()),
         setup: Some(new_full_request_response()?),
         request: Some(new_full_request()?),
         compare: Some(new_full_compare()?),
@@ -70,6 +72,9 @@ fn new_stage() -> file::UnvalidatedStage {
         variables: None,
         name: None,
         delay: None,
+        timeout: None,
+        retry: None,
+        poll: None,
     }
 }
 
@@ -81,9 +86,27 @@ fn new_full_stage() -> Result<file::UnvalidatedStage, Box<dyn Error + Send + Syn
         variables: Some(vec![new_full_variables()?]),
         name: None,
         delay: None,
+        timeout: Some(30000),
+        retry: Some(new_full_retry()),
+        poll: Some(file::UnvalidatedPoll {
+            interval: Some(1000),
+            max_duration: 30000,
+            until: new_full_response()?,
+        }),
     })
 }
 
+fn new_full_retry() -> file::UnvalidatedRetry {
+    file::UnvalidatedRetry {
+        attempts: 1,
+        delay: Some(0),
+        backoff: Some(file::Backoff::Fixed),
+        max_delay: Some(0),
+        on_status: Some(vec![]),
+        on_connection_error: Some(false),
+    }
+}
+
 //Do we want to create a variable of every type as part of the full template?
 fn new_full_variables() -> Result<file::UnvalidatedVariable, Box<dyn Error + Send + Sync>> {
     Ok(file::UnvalidatedVariable::Simple(SimpleValueVariable {
@@ -100,12 +123,22 @@ fn new_full_response() -> Result<file::UnvalidatedResponse, Box<dyn Error + Send
     Ok(file::UnvalidatedResponse {
         status: Some(test::file::ValueOrNumericSpecification::Value(200)),
         time: Some(test::file::ValueOrNumericSpecification::Value(500)),
-        headers: Some(vec![new_header()]),
+        headers: Some(vec![file::UnvalidatedResponseHeader {
+            header: "".to_string(),
+            value: Some("".to_string()),
+            ..Default::default()
+        }]),
         body: Some(serde_json::from_str("{}")?),
         ignore: Some(vec!["".to_string()]),
         extract: Some(vec![definition::ResponseExtraction::new()]),
         strict: Some(true),
         body_schema: None,
+        content: Some(file::UnvalidatedContentSpecification::default()),
+        assertions: Some(vec![file::UnvalidatedAssertion {
+            path: "".to_string(),
+            equals: Some(serde_json::Value::String("".to_string())),
+            ..Default::default()
+        }]),
     })
 }
 
@@ -135,6 +168,7 @@ fn new_full_request() -> Result<file::UnvalidatedRequest, Box<dyn Error + Send +
         params: Some(vec![new_parameter()]),
         headers: Some(vec![new_header()]),
         body: Some(serde_json::from_str("{}")?),
+        ..Default::default()
     })
 }
 
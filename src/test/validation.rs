@@ -1,11 +1,19 @@
 use crate::{
     test,
-    test::{definition, validation, variable},
+    test::{
+        definition, expression,
+        file::{
+            BodyOrSchema, DatumSchema, NumericSpecification, Specification, StringSpecification,
+            ValueOrDatumSchema, ValueOrNumericSpecification, ValuesOrSchema,
+        },
+        validation, variable,
+    },
 };
 use log::warn;
 use regex::Regex;
-use std::{fmt, path::PathBuf};
+use std::{collections::HashSet, fmt, fs, path::PathBuf};
 use ulid::Ulid;
+use url::Url;
 
 #[derive(Debug, Clone)]
 pub struct Error {
@@ -51,6 +59,24 @@ fn validate_test_file(
         });
     }
 
+    // a zero timeout has passed before the first request is sent
+    if file.timeout == Some(0) {
+        return Err(validation::Error {
+            reason: "timeout must be greater than 0".to_string(),
+        });
+    }
+
+    if let Some(index) = file
+        .stages
+        .iter()
+        .flatten()
+        .position(|stage| stage.timeout == Some(0))
+    {
+        return Err(validation::Error {
+            reason: format!("stage {} timeout must be greater than 0", index + 1),
+        });
+    }
+
     Ok(true)
 }
 
@@ -88,9 +114,14 @@ pub fn validate_file(
         platform_id: file.platform_id,
         project: file.project.or(project),
         environment: file.env.or(environment),
-        requires: file.requires,
+        requires: file
+            .requires
+            .map(|r| r.ids().iter().map(|id| id.to_lowercase()).collect())
+            .unwrap_or_default(),
         tags: new_tags,
         iterate: file.iterate.unwrap_or(1),
+        timeout: file.timeout,
+        retry: definition::RetryDescriptor::new_opt(file.retry)?,
         variables: variables.clone(),
         global_variables: global_variables.to_vec(),
         stages: definition::StageDescriptor::validate_stages_opt(
@@ -111,7 +142,744 @@ pub fn validate_file(
     Ok(td)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+//A problem found in a test file. The rule identifies the kind of problem, the
+//location names the part of the test at fault, e.g. `stage 2 response.bodySchema.items`,
+//and line and column point into the file when they can be found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub filename: String,
+    pub rule: &'static str,
+    pub location: String,
+    pub severity: Severity,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Diagnostic {
+    //A file which couldn't be read, parsed or turned into a test
+    pub fn load_error(filename: &str, message: String, position: Option<(usize, usize)>) -> Self {
+        Diagnostic {
+            filename: filename.to_string(),
+            rule: "load-error",
+            location: "file".to_string(),
+            severity: Severity::Error,
+            message,
+            line: position.map(|p| p.0),
+            column: position.map(|p| p.1),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.filename)?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, ":{}:{}", line, column)?;
+        }
+
+        write!(
+            f,
+            ": {}: {}: {}",
+            self.severity, self.location, self.message
+        )
+    }
+}
+
+//The byte range of the `index`th (0-based) entry of a block style `stages:` list
+fn stage_span(source: &str, index: usize) -> Option<(usize, usize)> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        lines.push((offset, line));
+        offset += line.len();
+    }
+
+    let is_content = |line: &str| {
+        let trimmed = line.trim_start();
+        !trimmed.is_empty() && !trimmed.starts_with('#')
+    };
+    let indent = |line: &str| line.len() - line.trim_start().len();
+
+    let key = lines
+        .iter()
+        .position(|(_, line)| indent(line) == 0 && line.trim_end().starts_with("stages:"))?;
+    let mut item_indent = None;
+    let mut items = Vec::new();
+    let mut end = source.len();
+    for (start, line) in lines[key + 1..].iter().filter(|(_, l)| is_content(l)) {
+        let item = line.trim_start().starts_with('-');
+        let item_indent = *item_indent.get_or_insert(indent(line));
+        if indent(line) < item_indent || (indent(line) == item_indent && !item) {
+            end = *start;
+            break;
+        }
+        if item && indent(line) == item_indent {
+            items.push(*start);
+        }
+    }
+
+    let start = *items.get(index)?;
+    Some((start, items.get(index + 1).copied().unwrap_or(end)))
+}
+
+//The 1-based line and column of a problem in YAML source. The keys of `location`
+//(`stage 2 response.bodySchema` searches `response:` then `bodySchema:`) are found in
+//turn, skipping missing ones, followed by the first of `near` found after them. A
+//`stage N` prefix limits the search to that entry of `stages:`
+pub fn source_position(source: &str, location: &str, near: &[&str]) -> Option<(usize, usize)> {
+    let mut tokens: Vec<&str> = location.split_whitespace().collect();
+    let (mut offset, limit) = match tokens.as_slice() {
+        ["stage", n, ..] => {
+            let span = n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| stage_span(source, n.saturating_sub(1)));
+            tokens.drain(..2);
+            span.unwrap_or((0, source.len()))
+        }
+        _ => (0, source.len()),
+    };
+    let keys = tokens
+        .into_iter()
+        .filter(|t| t.parse::<usize>().is_err())
+        .flat_map(|t| t.split('.'))
+        .map(|k| format!("{}:", k.trim_end_matches("[]")));
+
+    let mut found = None;
+    for key in keys {
+        if let Some(i) = source[offset..limit].find(&key) {
+            found = Some(offset + i);
+            offset += i + key.len();
+        }
+    }
+
+    if let Some(i) = near.iter().find_map(|n| source[offset..limit].find(n)) {
+        found = Some(offset + i);
+    }
+
+    found.map(|i| {
+        let before = &source[..i];
+        let line_start = before.rfind('\n').map(|n| n + 1).unwrap_or(0);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    })
+}
+
+//SARIF wants paths relative to the repository root with forward slashes
+fn artifact_uri(filename: &str) -> String {
+    let uri = filename.replace('\\', "/");
+    uri.strip_prefix("./").unwrap_or(&uri).to_string()
+}
+
+pub fn diagnostics_to_json(diagnostics: &[Diagnostic], test_count: usize) -> serde_json::Value {
+    let count = |severity: Severity| {
+        diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    };
+    serde_json::json!({
+        "tests": test_count,
+        "errors": count(Severity::Error),
+        "warnings": count(Severity::Warning),
+        "diagnostics": diagnostics
+            .iter()
+            .map(|d| serde_json::json!({
+                "file": d.filename,
+                "line": d.line,
+                "column": d.column,
+                "severity": d.severity.to_string(),
+                "rule": d.rule,
+                "location": d.location,
+                "message": d.message,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+pub fn diagnostics_to_sarif(diagnostics: &[Diagnostic], version: &str) -> serde_json::Value {
+    let mut rules: Vec<&str> = diagnostics.iter().map(|d| d.rule).collect();
+    rules.sort();
+    rules.dedup();
+
+    let results: Vec<serde_json::Value> = diagnostics
+        .iter()
+        .map(|d| {
+            let mut physical_location = serde_json::json!({
+                "artifactLocation": { "uri": artifact_uri(&d.filename) }
+            });
+            if let (Some(line), Some(column)) = (d.line, d.column) {
+                physical_location["region"] =
+                    serde_json::json!({ "startLine": line, "startColumn": column });
+            }
+
+            serde_json::json!({
+                "ruleId": d.rule,
+                "level": d.severity.to_string(),
+                "message": { "text": format!("{}: {}", d.location, d.message) },
+                "locations": [{
+                    "physicalLocation": physical_location,
+                    "logicalLocations": [{ "fullyQualifiedName": d.location }]
+                }]
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "jikken",
+                    "version": version,
+                    "informationUri": "https://www.jikken.io",
+                    "rules": rules.iter().map(|r| serde_json::json!({ "id": r })).collect::<Vec<_>>()
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+struct Lint<'a> {
+    filename: &'a str,
+    source: Option<&'a str>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Lint<'_> {
+    fn report(
+        &mut self,
+        severity: Severity,
+        rule: &'static str,
+        location: &str,
+        message: String,
+        near: &[&str],
+    ) {
+        let position = self
+            .source
+            .and_then(|source| source_position(source, location, near));
+        self.diagnostics.push(Diagnostic {
+            filename: self.filename.to_string(),
+            rule,
+            location: location.to_string(),
+            severity,
+            message,
+            line: position.map(|p| p.0),
+            column: position.map(|p| p.1),
+        });
+    }
+
+    fn error(&mut self, rule: &'static str, location: &str, message: String, near: &[&str]) {
+        self.report(Severity::Error, rule, location, message, near);
+    }
+
+    fn warning(&mut self, rule: &'static str, location: &str, message: String, near: &[&str]) {
+        self.report(Severity::Warning, rule, location, message, near);
+    }
+}
+
+//Variables extracted by the tests that may run before the one at `position`: its
+//transitive prerequisites and, as tests otherwise run in order, the tests preceding it.
+//Prerequisites are also looked up in `unselected`, the tests filtered out of the run,
+//as they are brought back in to satisfy `requires`
+pub fn upstream_extractions(
+    tests: &[test::Definition],
+    position: usize,
+    unselected: &[test::Definition],
+) -> HashSet<String> {
+    extractions(tests, position, unselected, position)
+}
+
+//Variables extracted by the transitive prerequisites of the test at `position`, the
+//only tests sure to have finished before it when tests run concurrently
+pub fn required_extractions(
+    tests: &[test::Definition],
+    position: usize,
+    unselected: &[test::Definition],
+) -> HashSet<String> {
+    extractions(tests, position, unselected, 0)
+}
+
+fn extractions(
+    tests: &[test::Definition],
+    position: usize,
+    unselected: &[test::Definition],
+    preceding: usize,
+) -> HashSet<String> {
+    let candidates: Vec<&test::Definition> = tests.iter().chain(unselected.iter()).collect();
+    let mut upstream: Vec<usize> = (0..preceding).collect();
+    let mut pending: Vec<&String> = tests[position].requires.iter().collect();
+    while let Some(id) = pending.pop() {
+        for (i, t) in candidates.iter().enumerate() {
+            if t.id.as_ref() == Some(id) && !upstream.contains(&i) {
+                upstream.push(i);
+                pending.extend(t.requires.iter());
+            }
+        }
+    }
+
+    upstream
+        .into_iter()
+        .filter(|i| *i != position)
+        .flat_map(|i| candidates[i].extracted_variables())
+        .collect()
+}
+
+//Each test file is read again so problems can be given a line and column. Only the
+//selected tests are linted, the unselected ones can still satisfy their requirements
+pub fn validate_definitions(
+    tests: &[test::Definition],
+    unselected: &[test::Definition],
+) -> Vec<Diagnostic> {
+    tests
+        .iter()
+        .enumerate()
+        .flat_map(|(position, td)| {
+            let source = fs::read_to_string(&td.file_data.filename).ok();
+            validate_definition(tests, position, unselected, source.as_deref())
+        })
+        .collect()
+}
+
 // this method is intended to do a thorough validation of rules and logic in the resolved test definition
-pub fn _validate_definition(_test_definition: &test::Definition) -> Result<bool, Error> {
-    Ok(true)
+pub fn validate_definition(
+    tests: &[test::Definition],
+    position: usize,
+    unselected: &[test::Definition],
+    source: Option<&str>,
+) -> Vec<Diagnostic> {
+    let td = &tests[position];
+    let preceding = &tests[..position];
+    let mut lint = Lint {
+        filename: &td.file_data.filename,
+        source,
+        diagnostics: Vec::new(),
+    };
+
+    if let Some(id) = &td.id {
+        if let Some(other) = preceding.iter().find(|t| t.id.as_ref() == Some(id)) {
+            lint.error(
+                "duplicate-id",
+                "id",
+                format!(
+                    "id `{}` is already used by {}",
+                    id, other.file_data.filename
+                ),
+                &[id],
+            );
+        }
+    }
+
+    for id in &td.requires {
+        match tests
+            .iter()
+            .chain(unselected.iter())
+            .find(|t| t.id.as_ref() == Some(id))
+        {
+            None => lint.error(
+                "missing-requirement",
+                "requires",
+                format!("no test has the required id `{}`", id),
+                &[id],
+            ),
+            Some(t) if t.disabled => lint.warning(
+                "disabled-requirement",
+                "requires",
+                format!(
+                    "required test `{}` is disabled, so this test will be skipped",
+                    id
+                ),
+                &[id],
+            ),
+            _ => {}
+        }
+    }
+
+    let unknown = td.unresolved_variables(&upstream_extractions(tests, position, unselected));
+    for unresolved in &unknown {
+        lint.error(
+            "unknown-variable",
+            &unresolved.location,
+            format!(
+                "variable `{}` is not provided by any test, stage, global, secret or extracted variable",
+                unresolved.name
+            ),
+            &[&format!("${{{}}}", unresolved.name), &unresolved.name],
+        );
+    }
+
+    // with several jobs a test only sees what the tests it requires extracted
+    for unresolved in td.unresolved_variables(&required_extractions(tests, position, unselected)) {
+        if !unknown.contains(&unresolved) {
+            lint.warning(
+                "unrequired-extraction",
+                &unresolved.location,
+                format!(
+                    "variable `{}` is extracted by an earlier test this test doesn't require, so it is unset when tests run concurrently",
+                    unresolved.name
+                ),
+                &[&format!("${{{}}}", unresolved.name), &unresolved.name],
+            );
+        }
+    }
+
+    let used = serde_json::to_value(&td.file_data)
+        .map(|v| expression::json_placeholder_variables(&v))
+        .unwrap_or_default();
+    let stage_variables = td.stages.iter().enumerate().flat_map(|(i, s)| {
+        s.variables
+            .iter()
+            .map(move |v| (format!("stage {} variables", i + 1), v))
+    });
+    for (location, variable) in td
+        .variables
+        .iter()
+        .map(|v| ("variables".to_string(), v))
+        .chain(stage_variables)
+    {
+        if !used.contains(&variable.name) {
+            lint.warning(
+                "unused-variable",
+                &location,
+                format!("variable `{}` is never used", variable.name),
+                &[&format!("name: {}", variable.name), &variable.name],
+            );
+        }
+
+        if let test::ValueOrDatumOrFileOrSecret::Schema { value } = &variable.value {
+            validate_schema(&mut lint, &format!("{} {}", location, variable.name), value);
+        }
+    }
+
+    if let Some(setup) = &td.setup {
+        validate_request(&mut lint, "setup request", &setup.request);
+        if let Some(response) = &setup.response {
+            validate_response(&mut lint, "setup response", response);
+        }
+    }
+
+    for (index, stage) in td.stages.iter().enumerate() {
+        let location = format!("stage {}", index + 1);
+        validate_request(&mut lint, &format!("{} request", location), &stage.request);
+        if let Some(response) = &stage.response {
+            validate_response(&mut lint, &format!("{} response", location), response);
+        }
+
+        if let Some(poll) = &stage.poll {
+            validate_response(&mut lint, &format!("{} poll.until", location), &poll.until);
+        }
+
+        if let Some(compare) = &stage.compare {
+            let location = format!("{} compare", location);
+            validate_url(&mut lint, &format!("{}.url", location), &compare.url);
+            if compares_nothing(stage, compare) {
+                lint.warning(
+                    "empty-compare",
+                    &location,
+                    "the compare request is identical to the stage request, so there is nothing to compare".to_string(),
+                    &[],
+                );
+            }
+        }
+    }
+
+    for (name, request) in [
+        ("onsuccess", &td.cleanup.onsuccess),
+        ("onfailure", &td.cleanup.onfailure),
+        ("always", &td.cleanup.always),
+    ] {
+        if let Some(request) = request {
+            validate_request(&mut lint, &format!("cleanup {}", name), request);
+        }
+    }
+
+    lint.diagnostics
+}
+
+fn validate_request(lint: &mut Lint, location: &str, request: &definition::RequestDescriptor) {
+    validate_url(lint, &format!("{}.url", location), &request.url);
+    if let Some(BodyOrSchema::Schema(schema)) = request.body.as_ref().map(|b| &b.data) {
+        validate_schema(lint, &format!("{}.bodySchema", location), schema);
+    }
+}
+
+//The URL is checked with its placeholders filled in, unless a placeholder provides
+//its scheme and host
+fn validate_url(lint: &mut Lint, location: &str, url: &str) {
+    if url.trim_start().starts_with("${") {
+        return;
+    }
+
+    let placeholder = Regex::new(r"\$\{[^}]*\}").unwrap();
+    let filled = placeholder.replace_all(url, "placeholder");
+    match Url::parse(&filled) {
+        Err(e) => lint.error(
+            "invalid-url",
+            location,
+            format!("invalid URL `{}`: {}", url, e),
+            &[url],
+        ),
+        Ok(parsed) if !matches!(parsed.scheme(), "http" | "https") => lint.error(
+            "invalid-url",
+            location,
+            format!("URL `{}` must start with http:// or https://", url),
+            &[url],
+        ),
+        _ => {}
+    }
+}
+
+fn validate_response(lint: &mut Lint, location: &str, response: &definition::ResponseDescriptor) {
+    if let Some(ValueOrNumericSpecification::Schema(status)) = &response.status {
+        validate_numeric(lint, &format!("{}.status", location), status);
+    }
+
+    if let Some(response_time) = &response.response_time {
+        validate_numeric(lint, &format!("{}.responseTime", location), response_time);
+    }
+
+    for header in &response.headers {
+        if let Some(specification) = &header.specification {
+            validate_string(
+                lint,
+                &format!("{}.headers {}", location, header.header),
+                specification,
+            );
+        }
+    }
+
+    if let Some(BodyOrSchema::Schema(schema)) = response.body.as_ref().map(|b| &b.data) {
+        validate_schema(lint, &format!("{}.bodySchema", location), schema);
+    }
+
+    if let Some(content) = &response.content {
+        if let Some(pattern) = &content.pattern {
+            validate_pattern(lint, &format!("{}.content", location), pattern);
+        }
+
+        if let Some(ValueOrNumericSpecification::Schema(length)) = &content.byte_length {
+            validate_numeric(lint, &format!("{}.content.byteLength", location), length);
+        }
+    }
+
+    for assertion in &response.assertions {
+        let location = format!("{}.assertions {}", location, assertion.path);
+        match &assertion.operator {
+            definition::AssertionOperator::Matches(pattern) => {
+                validate_pattern(lint, &location, pattern)
+            }
+            definition::AssertionOperator::Length(ValueOrNumericSpecification::Schema(length)) => {
+                validate_numeric(lint, &location, length)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn validate_pattern(lint: &mut Lint, location: &str, pattern: &str) {
+    if let Err(e) = Regex::new(pattern) {
+        lint.error(
+            "invalid-regex",
+            location,
+            format!("invalid regex `{}`: {}", pattern, e),
+            &[pattern],
+        );
+    }
+}
+
+fn specification_values<T>(specification: &Specification<T>) -> Vec<&T> {
+    match specification {
+        Specification::AnyOf(v) | Specification::OneOf(v) | Specification::NoneOf(v) => {
+            v.iter().collect()
+        }
+        Specification::Value(v) | Specification::UnTaggedValue(v) => vec![v],
+    }
+}
+
+fn validate_numeric<T>(lint: &mut Lint, location: &str, specification: &NumericSpecification<T>)
+where
+    T: fmt::Display + Clone + PartialOrd,
+{
+    if let (Some(min), Some(max)) = (&specification.min, &specification.max) {
+        if min > max {
+            lint.error(
+                "contradictory-schema",
+                location,
+                format!("min {} is greater than max {}", min, max),
+                &["min:"],
+            );
+        }
+    }
+
+    if let Some(Specification::Value(v) | Specification::UnTaggedValue(v)) =
+        &specification.specification
+    {
+        let below = specification.min.as_ref().is_some_and(|min| v < min);
+        let above = specification.max.as_ref().is_some_and(|max| v > max);
+        if below || above {
+            lint.error(
+                "contradictory-schema",
+                location,
+                format!("value {} can never satisfy its min and max", v),
+                &["value:", "min:", "max:"],
+            );
+        }
+    }
+}
+
+fn validate_lengths(
+    lint: &mut Lint,
+    location: &str,
+    length: Option<i64>,
+    min_length: Option<i64>,
+    max_length: Option<i64>,
+) {
+    for (name, value) in [
+        ("length", length),
+        ("minLength", min_length),
+        ("maxLength", max_length),
+    ] {
+        if value.is_some_and(|v| v < 0) {
+            lint.error(
+                "contradictory-schema",
+                location,
+                format!("{} cannot be negative", name),
+                &[&format!("{}:", name)],
+            );
+        }
+    }
+
+    if let (Some(min), Some(max)) = (min_length, max_length) {
+        if min > max {
+            lint.error(
+                "contradictory-schema",
+                location,
+                format!("minLength {} is greater than maxLength {}", min, max),
+                &["minLength:"],
+            );
+        }
+    }
+
+    if let Some(length) = length {
+        if min_length.is_some_and(|min| length < min) || max_length.is_some_and(|max| length > max)
+        {
+            lint.error(
+                "contradictory-schema",
+                location,
+                format!("length {} contradicts minLength or maxLength", length),
+                &["length:"],
+            );
+        }
+    }
+}
+
+fn validate_string(lint: &mut Lint, location: &str, specification: &StringSpecification) {
+    validate_lengths(
+        lint,
+        location,
+        specification.length,
+        specification.min_length,
+        specification.max_length,
+    );
+
+    if let Some(pattern) = &specification.pattern {
+        validate_pattern(lint, location, pattern);
+    }
+}
+
+fn validate_schema(lint: &mut Lint, location: &str, schema: &DatumSchema) {
+    match schema {
+        DatumSchema::Float {
+            specification: Some(s),
+        } => validate_numeric(lint, location, s),
+        DatumSchema::Integer {
+            specification: Some(s),
+        } => validate_numeric(lint, location, s),
+        DatumSchema::String {
+            specification: Some(s),
+        } => validate_string(lint, location, s),
+        DatumSchema::Name {
+            specification: Some(s),
+        } => validate_string(lint, location, &s.specification),
+        DatumSchema::Email {
+            specification: Some(s),
+        } => validate_string(lint, location, &s.specification),
+        DatumSchema::List {
+            specification: Some(s),
+        } => {
+            validate_lengths(lint, location, s.length, s.min_length, s.max_length);
+            if let Some(ValuesOrSchema::Schemas(items)) = &s.schema {
+                for item in specification_values(items) {
+                    validate_schema(lint, &format!("{}[]", location), item);
+                }
+            }
+        }
+        DatumSchema::Object {
+            schema: Some(fields),
+        } => {
+            for (name, field) in fields {
+                if let ValueOrDatumSchema::Datum(d) = field {
+                    validate_schema(lint, &format!("{}.{}", location, name), d);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+//Comparing a request with an identical one can only ever pass
+fn compares_nothing(
+    stage: &definition::StageDescriptor,
+    compare: &definition::CompareDescriptor,
+) -> bool {
+    let params = |params: &[test::http::Parameter]| {
+        params
+            .iter()
+            .map(|p| (p.param.clone(), p.value.clone()))
+            .collect::<Vec<_>>()
+    };
+    let headers = |headers: &[test::http::Header]| {
+        headers
+            .iter()
+            .map(|h| (h.header.clone(), h.value.clone()))
+            .collect::<Vec<_>>()
+    };
+    let compare_headers = if compare.headers.is_empty() {
+        stage
+            .request
+            .headers
+            .iter()
+            .filter(|h| !compare.ignore_headers.contains(&h.header))
+            .chain(compare.add_headers.iter())
+            .cloned()
+            .collect()
+    } else {
+        compare.headers.clone()
+    };
+
+    compare.method == stage.request.method
+        && compare.url == stage.request.url
+        && params(&stage.get_compare_parameters()) == params(&stage.request.params)
+        && headers(&compare_headers) == headers(&stage.request.headers)
+        && compare.body.as_ref().map(|b| &b.data) == stage.request.body.as_ref().map(|b| &b.data)
 }